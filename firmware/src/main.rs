@@ -0,0 +1,1268 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+#![allow(dead_code)]
+// the routes of the control panel nest deeper than the default limit takes
+#![recursion_limit = "256"]
+//use embedded_io::*;
+//use embedded_svc::ipv4::Interface;
+use embedded_svc::wifi::{AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration, Wifi};
+
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{UdpSocket, PacketMetadata};
+use embassy_net::{
+    driver::Driver, Config, IpEndpoint, IpListenEndpoint, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4,
+};
+use heapless::Vec;
+
+use core::future::pending;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use esp_wifi::initialize;
+//use esp_wifi::wifi::utils::create_network_interface;
+use esp_wifi::wifi::{WifiApDevice, WifiController, WifiEvent, WifiDevice, WifiStaDevice};
+//use esp_wifi::wifi_interface::WifiStack;
+use esp_wifi::{EspWifiInitFor};
+//use smoltcp::iface::SocketStorage;
+use static_cell::make_static;
+
+use embassy_executor::Spawner;
+//use embassy_futures::join::join;
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embassy_sync::{
+    //blocking_mutex::raw::NoopRawMutex,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::{Channel},
+    signal::Signal,
+};
+
+use esp_storage::FlashStorage;
+
+use esp_backtrace as _;
+//use esp_println::println;
+use esp32c3_hal::{
+    gpio::{GpioPin, Input, PullUp, PushPull, Output},
+    clock::ClockControl,
+    embassy,
+    //interrupt,
+    Rng,
+    IO,
+    peripherals::{/*Interrupt,*/ Peripherals, UART1},
+    prelude::*,
+    uart,
+    uart::{
+	Uart,UartRx,UartTx,TxRxPins,
+    }
+};
+
+use picoserve::extract::State;
+
+use pesebre_core::catalog::{self, Track};
+use pesebre_core::connectivity::{Connectivity, Mode, State as ConnectivityState};
+use pesebre_core::control::{Control, ControlMessages};
+use pesebre_core::dfplayer_mini::{self, DfEvent, DfEventChannel, DfPlayer, DfPlayerError, DfReplyChannel, FrameDecoder, ReliableConfig};
+use pesebre_core::dhcp::{self, DhcpConfig, DhcpServer};
+use pesebre_core::dns::{self, DnsResponder};
+use pesebre_core::events::{PanelEvent, EVENT_STREAMS};
+use pesebre_core::http::{EmbassyTimer, Interface};
+use pesebre_core::mdns::{self, MdnsResponder};
+use pesebre_core::player::PlayerState;
+use pesebre_core::playlist::Playlist;
+use pesebre_core::provisioning::{self, Candidate, Network, Networks, Security, Trial};
+use pesebre_core::settings::{Settings, SettingsStore};
+use pesebre_core::status::dotted;
+use pesebre_core::volume::VolumeControl;
+
+const READ_BUF_SIZE: usize = 10;
+/// Flash sectors of the settings: the NVS partition, which nothing else
+/// uses.
+const SETTINGS_FLASH_OFFSET : u32 = 0x9000;
+const SETTINGS_SECTORS : u32 = 6;
+/// Settings are saved once they stay put this long, to spare the flash a
+/// write for every tap on the volume.
+const SETTINGS_SAVE_DELAY : Duration = Duration::from_secs(5);
+/// Time the settings page has to get its answer before the access point
+/// goes down to take new settings.
+const AP_RESTART_DELAY : Duration = Duration::from_secs(2);
+/// Web tasks, each serving a connection. Up to `EVENT_STREAMS` of them can
+/// be kept busy by pages following the events, the rest serve requests.
+const WEB_TASK_POOL_SIZE : usize = EVENT_STREAMS + 2;
+/// Announcements of the pesebre when it joins a network, as RFC 6762 asks,
+/// a second apart.
+const MDNS_ANNOUNCEMENTS : usize = 2;
+/// How often the mDNS responder checks whether the address changed, to
+/// announce the new one.
+const MDNS_ADDRESS_CHECK : Duration = Duration::from_secs(1);
+/// Time the household network has to let the pesebre in, and then to lease
+/// it an address.
+const JOIN_TIMEOUT : Duration = Duration::from_secs(15);
+const DHCP_TIMEOUT : Duration = Duration::from_secs(15);
+/// Networks looked at when looking for the household one, or listing them
+/// on the setup page.
+const SCAN_SIZE : usize = provisioning::MAX_NETWORKS;
+/// Time the setup page has to tell where the pesebre went before the access
+/// point goes down for the network just tried.
+const SETUP_SWITCH_DELAY : Duration = Duration::from_secs(10);
+/// Time the button is held for the access point to come up, or go down.
+const BUTTON_HOLD : Duration = Duration::from_secs(3);
+const BUTTON_POLL : Duration = Duration::from_millis(50);
+static CONTROL: Control = Control::new();
+/// Every name leads to the control panel, for phones to pop it up when they
+/// join the access point.
+static PORTAL_MODE: AtomicBool = AtomicBool::new(true);
+static BUTTON_HELD: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static DF_EVENTS: DfEventChannel = Channel::new();
+static DF_REPLIES: DfReplyChannel = Channel::new();
+
+/// Network of the access point of the pesebre, and of the household one.
+type ApStack = Stack<WifiDevice<'static, WifiApDevice>>;
+type StaStack = Stack<WifiDevice<'static, WifiStaDevice>>;
+
+#[main]
+async fn main(spawner: Spawner) {
+    // setup logger
+    // To change the log_level change the env section in .cargo/config.toml
+    // or remove it and set ESP_LOGLEVEL manually before running cargo run
+    // this requires a clean rebuild because of https://github.com/rust-lang/cargo/issues/10358
+    //esp_println::logger::init_logger_from_env();
+    esp_println::logger::init_logger(log::LevelFilter::Info);
+    log::info!("Pesbre Navideño");
+
+    let peripherals = Peripherals::take();
+    let system = peripherals.SYSTEM.split();
+
+    let clocks = ClockControl::max(system.clock_control).freeze();
+    let timer_group0 = esp32c3_hal::timer::TimerGroup::new(peripherals.TIMG0, &clocks);
+
+    
+    embassy::init(
+        &clocks,
+        timer_group0.timer0,
+    );
+
+    let mut settings = SettingsStore::new(FlashStorage::new(), SETTINGS_FLASH_OFFSET, SETTINGS_SECTORS);
+    CONTROL.settings.publish(&settings.load());
+
+    let io = IO::new(peripherals.GPIO, peripherals.IO_MUX);
+
+    let led = io.pins.gpio12.into_push_pull_output();
+    // the BOOT button of the board
+    let button = io.pins.gpio9.into_pull_up_input();
+
+    esp32c3_hal::interrupt::enable(
+        esp32c3_hal::peripherals::Interrupt::GPIO,
+        esp32c3_hal::interrupt::Priority::Priority1,
+    ).unwrap();
+
+    let uart_config : uart::config::Config = uart::config::Config {
+	baudrate: 9600,
+	data_bits: uart::config::DataBits::DataBits8,
+	parity: uart::config::Parity::ParityNone,
+	stop_bits: uart::config::StopBits::STOP1,
+    };
+
+    let uart_pins = TxRxPins::new_tx_rx(
+        io.pins.gpio0.into_push_pull_output(),
+        io.pins.gpio1.into_floating_input(),
+    );
+    
+    let uart1 = Uart::new_with_config(
+	peripherals.UART1,
+	uart_config,
+	Some(uart_pins),
+	&clocks);
+
+    let (tx, rx) = uart1.split();
+
+    esp32c3_hal::interrupt::enable(
+        esp32c3_hal::peripherals::Interrupt::UART1,
+        esp32c3_hal::interrupt::Priority::Priority1,
+    ).unwrap();
+
+    let timer = esp32c3_hal::systimer::SystemTimer::new(peripherals.SYSTIMER).alarm0;
+
+    let wifi_init = initialize(
+        EspWifiInitFor::Wifi,
+        timer,
+        Rng::new(peripherals.RNG),
+        system.radio_clock_control,
+        &clocks,
+    ).unwrap();
+    
+    let wifi = peripherals.WIFI;
+    let (ap_interface, sta_interface, controller) =
+        esp_wifi::wifi::new_ap_sta(&wifi_init, wifi).unwrap();
+
+    let dnss = Vec::<_,3>::from_slice(&[Ipv4Address::from_bytes(&[192, 168, 2, 1]),Ipv4Address::from_bytes(&[192, 168, 2, 1]),Ipv4Address::from_bytes(&[192, 168, 2, 1])]).unwrap();
+    
+    let config = Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 2, 1), 24),
+        gateway: Some(Ipv4Address::from_bytes(&[192, 168, 2, 150])),
+        dns_servers: dnss,
+    });
+
+    let seed = 1234; // very random, very secure seed
+
+    // Init network stack
+    let stack = &*make_static!(Stack::new(
+        ap_interface,
+        config,
+        make_static!(StackResources::<{WEB_TASK_POOL_SIZE + 2}>::new()),
+        seed
+    ));
+
+    // the household network leases the address
+    let sta_stack = &*make_static!(Stack::new(
+        sta_interface,
+        Config::dhcpv4(Default::default()),
+        make_static!(StackResources::<{WEB_TASK_POOL_SIZE + 2}>::new()),
+        seed + 1
+    ));
+
+
+    fn make_app(interface: Interface) -> picoserve::Router<AppRouter,()> {
+	pesebre_core::http::make_app(&CONTROL, interface)
+    }
+    
+    // the first admin password is only taken on the access point
+    let web_app = make_static!(make_app(Interface::AccessPoint));
+    let station_app = make_static!(make_app(Interface::Station));
+
+    let webserver_config = make_static!(picoserve::Config {
+        start_read_request_timeout: Some(Duration::from_secs(15)),
+        read_request_timeout: Some(Duration::from_secs(10)),
+    });
+
+    if let Err(why) = spawner.spawn(connection(controller, &sta_stack)) {
+	log::error!("Failed spawning 'connection' task: {why:?}");
+    }
+
+    if let Err(why) = spawner.spawn(button_task(button)) {
+	log::error!("Failed spawning 'button_task' task: {why:?}");
+    }
+    
+    if let Err(why) = spawner.spawn(net_task(&stack)) {
+	log::error!("Failed spawning 'net_task' task: {why:?}");
+    }
+
+    if let Err(why) = spawner.spawn(station_net_task(&sta_stack)) {
+	log::error!("Failed spawning 'station_net_task' task: {why:?}");
+    }
+
+    if let Err(why) = spawner.spawn(dns_server(&stack)) {
+	log::error!("Failed spawning 'connection' task: {why:?}");
+    }
+
+    if let Err(why) = spawner.spawn(dhcp_server(&stack)) {
+	log::error!("Failed spawning 'dhcp_server' task: {why:?}");
+    }
+
+    if let Err(why) = spawner.spawn(mdns_responder(&sta_stack)) {
+	log::error!("Failed spawning 'mdns_responder' task: {why:?}");
+    }
+    
+    if let Err(why) = spawner.spawn(loop_luces(led)){
+	log::error!("Failed spawning 'loop_luces' task: {why:?}");
+    }
+    if let Err(why) = spawner.spawn(reader(rx)){
+	log::error!("Failed spawning 'reader' task: {why:?}");
+    }
+    if let Err(why) = spawner.spawn(writer(tx)){
+	log::error!("Failed spawning 'writer' task: {why:?}");
+    }
+    if let Err(why) = spawner.spawn(settings_task(settings)){
+	log::error!("Failed spawning 'settings_task' task: {why:?}");
+    }
+
+    for id in 0..WEB_TASK_POOL_SIZE {
+
+	if let Err(why) = spawner.spawn(web_task(&stack, web_app, webserver_config)){
+	    log::error!("Failed spawning 'web_task' ID: {id} task: {why:?}");
+	}
+	if let Err(why) = spawner.spawn(station_web_task(&sta_stack, station_app, webserver_config)){
+	    log::error!("Failed spawning 'station_web_task' ID: {id} task: {why:?}");
+	}
+    }
+
+
+}
+
+#[embassy_executor::task]
+async fn writer(tx: UartTx<'static, UART1>) {
+    let mut player = DfPlayer::new(tx, &DF_REPLIES);
+    // a dropped "play" means silence in front of everybody, so wait for the
+    // module to acknowledge each command and retry otherwise
+    player.set_reliable(Some(ReliableConfig::default()));
+
+    log::info!("Waiting for MP3 module initialization 2 seconds");
+    Timer::after(Duration::from_millis(2000)).await;
+
+    log::info!("Set MP3 playback source to TF card");
+    if let Err(why) = player.playback_source(2).await {
+        log::error!("MP3 command failed: {why}");
+    }
+    Timer::after(Duration::from_millis(2000)).await;
+
+    let settings = CONTROL.settings.get();
+    let mut volume = VolumeControl::restore(settings.volume, settings.max_volume);
+    log::info!("Set MP3 playback volume to '{}'", volume.level());
+    set_volume(&mut player, &volume).await;
+    Timer::after(Duration::from_millis(2000)).await;
+
+    log::info!("Set MP3 equalizer to {:?}", settings.eq);
+    if let Err(why) = player.eq_select(settings.eq.into()).await {
+        log::error!("MP3 command failed: {why}");
+    }
+    Timer::after(Duration::from_millis(2000)).await;
+
+    match player.query_version().await {
+	Ok(version) => {
+	    log::info!("MP3 module firmware version {version}");
+	    CONTROL.device.update(|info| info.firmware_version = Some(version));
+	}
+	Err(why) => log::warn!("MP3 module firmware version unknown: {why}"),
+    }
+    match player.query_status().await {
+	Ok(status) => CONTROL.device.update(|info| info.source = status.device),
+	Err(why) => log::warn!("MP3 module status unknown: {why}"),
+    }
+    match player.query_eq().await {
+	Ok(eq) => CONTROL.device.update(|info| info.eq = Some(eq)),
+	Err(why) => log::warn!("MP3 module equalizer unknown: {why}"),
+    }
+    match player.query_tf_files().await {
+	Ok(files) => log::info!("MP3 module found {files} files in the TF card"),
+	Err(why) => log::warn!("MP3 module didn't report the TF card files: {why}"),
+    }
+
+
+    let mut state = PlayerState::Idle;
+    let mut playlist = Playlist::new();
+
+    if let Some(welcome) = settings.welcome_track.and_then(catalog::find) {
+	log::info!("Play welcome message: Song {}", welcome.id);
+	match play(&mut player, welcome).await {
+	    Ok(()) => {
+		state.play(welcome, Instant::now());
+		notify(PanelEvent::TrackStarted(welcome));
+	    }
+	    Err(why) => log::error!("MP3 command failed: {why}"),
+	}
+	CONTROL.state.publish(state);
+    }
+    Timer::after(Duration::from_millis(2000)).await;
+
+    let receiver = CONTROL.commands.receiver();
+    let events = DF_EVENTS.receiver();
+    
+    loop {
+	log::info!("Awaiting for request for MP3 playback from channel incomming from HTTP");
+	let message = match select(receiver.receive(), events.receive()).await {
+	    Either::First(message) => message,
+	    Either::Second(event) => {
+		match event {
+		    DfEvent::TrackFinished{ track, .. } => {
+			log::info!("MP3 finished playing #{track}");
+			let finished = state.track();
+			if state.finish(track) {
+			    if let Some(finished) = finished {
+				notify(PanelEvent::TrackFinished(finished));
+			    }
+			    let queued = playlist.len();
+			    if let Some(next) = play_from_playlist(&mut player, &mut playlist).await {
+				state.play(next, Instant::now());
+			    }
+			    if playlist.len() != queued {
+				notify(PanelEvent::QueueChanged(playlist.len()));
+			    }
+			    CONTROL.playlist.publish(&playlist);
+			}
+		    },
+		    DfEvent::MediaInserted(device) => {
+			log::info!("MP3 media inserted {device:?}");
+			CONTROL.device.update(|info| info.card_removed = false);
+			state.recover();
+			notify(PanelEvent::MediaInserted(device));
+		    },
+		    DfEvent::MediaRemoved(device) => {
+			log::warn!("MP3 media removed {device:?}");
+			CONTROL.device.update(|info| info.card_removed = true);
+			notify(PanelEvent::MediaRemoved(device));
+		    },
+		    DfEvent::Online(media) => log::info!("MP3 module online, media {media:#x}"),
+		    DfEvent::Error(why) => {
+			if state.report(why) {
+			    log::error!("MP3 module error: {why}");
+			} else {
+			    log::warn!("MP3 module error: {why}");
+			}
+		    },
+		}
+		CONTROL.state.publish(state);
+		continue;
+	    }
+	};
+
+	let queued = playlist.clone();
+	match message{
+	    ControlMessages::Play(track) => {
+		log::info!("Playin MP3 file  #{} {}", track.id, track.title);
+		match play(&mut player, track).await {
+		    Ok(()) => {
+			state.play(track, Instant::now());
+			notify(PanelEvent::TrackStarted(track));
+		    }
+		    Err(why) => log::error!("MP3 command failed: {why}"),
+		}
+	    },
+	    ControlMessages::Pause => {
+		log::info!("MP3 Paused");
+		match player.pause().await {
+		    Ok(()) => {
+			state.pause(Instant::now());
+			notify(PanelEvent::Paused);
+		    }
+		    Err(why) => log::error!("MP3 command failed: {why}"),
+		}
+	    },
+	    ControlMessages::Resume if !state.is_paused() => {
+		log::warn!("MP3 not resumed, nothing is paused");
+	    }
+	    ControlMessages::Resume => {
+		log::info!("MP3 Resumed");
+		match player.resume().await {
+		    Ok(()) => {
+			state.resume(Instant::now());
+			notify(PanelEvent::Resumed);
+		    }
+		    Err(why) => log::error!("MP3 command failed: {why}"),
+		}
+	    }
+	    ControlMessages::Stop => {
+		log::info!("MP3 Stopped");
+		match player.stop().await {
+		    Ok(()) => {
+			state.stop();
+			notify(PanelEvent::Stopped);
+		    }
+		    Err(why) => log::error!("MP3 command failed: {why}"),
+		}
+	    }
+	    ControlMessages::IncVol
+	    | ControlMessages::DecVol
+	    | ControlMessages::AdjustVolume{ .. } => {
+		let before = volume;
+		match message {
+		    ControlMessages::IncVol => volume.increase(),
+		    ControlMessages::DecVol => volume.decrease(),
+		    ControlMessages::AdjustVolume{ level, muted, max } => volume.adjust(level, muted, max),
+		    _ => unreachable!(),
+		}
+		log::info!(
+		    "MP3 Vol {} of {}{}",
+		    volume.level(),
+		    volume.max(),
+		    if volume.is_muted() { ", muted" } else { "" },
+		);
+		if volume != before {
+		    set_volume(&mut player, &volume).await;
+		    if volume.is_muted() != before.is_muted() {
+			notify(PanelEvent::MuteChanged(volume.is_muted()));
+		    }
+		    CONTROL.settings.update(|settings| {
+			settings.volume = volume.level();
+			settings.max_volume = volume.max();
+		    });
+		}
+	    }
+	    ControlMessages::Enqueue(track) | ControlMessages::PlayNext(track) => {
+		let added = match message {
+		    ControlMessages::PlayNext(_) => playlist.play_next(track),
+		    _ => playlist.enqueue(track),
+		};
+		match added {
+		    Ok(()) => log::info!("Playlist got #{} {}", track.id, track.title),
+		    Err(why) => log::warn!("Playlist didn't take #{}: {why:?}", track.id),
+		}
+		if state.track().is_none() {
+		    if let Some(next) = play_from_playlist(&mut player, &mut playlist).await {
+			state.play(next, Instant::now());
+		    }
+		}
+	    }
+	    ControlMessages::Remove(position) => {
+		if let Err(why) = playlist.remove(position) {
+		    log::warn!("Playlist remove failed: {why:?}");
+		}
+	    }
+	    ControlMessages::Reorder{ from, to } => {
+		if let Err(why) = playlist.reorder(from, to) {
+		    log::warn!("Playlist move failed: {why:?}");
+		}
+	    }
+	    ControlMessages::ClearPlaylist => {
+		log::info!("Playlist cleared");
+		playlist.clear();
+	    }
+	    ControlMessages::Skip => {
+		log::info!("MP3 Skipped");
+		match play_from_playlist(&mut player, &mut playlist).await {
+		    Some(next) => state.play(next, Instant::now()),
+		    None => match player.stop().await {
+			Ok(()) => {
+			    state.stop();
+			    notify(PanelEvent::Stopped);
+			}
+			Err(why) => log::error!("MP3 command failed: {why}"),
+		    },
+		}
+	    }
+	}
+	if playlist != queued {
+	    notify(PanelEvent::QueueChanged(playlist.len()));
+	}
+	CONTROL.playlist.publish(&playlist);
+	CONTROL.state.publish(state);
+	// the module drops commands that arrive back to back
+	Timer::after(Duration::from_millis(100)).await;
+    }
+}
+
+async fn play(
+    player: &mut DfPlayer<'static, UartTx<'static, UART1>>,
+    track: &'static Track,
+) -> Result<(), DfPlayerError> {
+    player.play(track.id).await
+}
+
+/// Sets `volume` in the module and tells the pages.
+async fn set_volume(
+    player: &mut DfPlayer<'static, UartTx<'static, UART1>>,
+    volume: &VolumeControl,
+) {
+    match player.volume(volume.output()).await {
+	Ok(()) => {
+	    CONTROL.device.update(|info| {
+		info.volume = volume.level();
+		info.muted = volume.is_muted();
+		info.max_volume = volume.max();
+	    });
+	    notify(PanelEvent::VolumeChanged(volume.level()));
+	}
+	Err(why) => log::error!("MP3 command failed: {why}"),
+    }
+}
+
+#[embassy_executor::task]
+async fn settings_task(mut store: SettingsStore<FlashStorage>) {
+    loop {
+	CONTROL.settings.changed().await;
+	// wait for the changes to settle
+	while with_timeout(SETTINGS_SAVE_DELAY, CONTROL.settings.changed()).await.is_ok() {}
+	if let Err(why) = store.save(&CONTROL.settings.get()) {
+	    log::error!("Settings not saved: {why:?}");
+	}
+    }
+}
+
+/// Sends `event` to the pages following the events.
+fn notify(event: PanelEvent) {
+    pesebre_core::events::notify(&CONTROL.events, event);
+}
+
+/// Plays the next track of the playlist that the module takes, if any.
+async fn play_from_playlist(
+    player: &mut DfPlayer<'static, UartTx<'static, UART1>>,
+    playlist: &mut Playlist,
+) -> Option<&'static Track> {
+    while let Some(track) = playlist.pop_next() {
+	log::info!("Playin MP3 file  #{} {} from the playlist", track.id, track.title);
+	match play(player, track).await {
+	    Ok(()) => {
+		notify(PanelEvent::TrackStarted(track));
+		return Some(track);
+	    }
+	    Err(why) => log::error!("MP3 command failed: {why}"),
+	}
+    }
+    None
+}
+
+#[embassy_executor::task]
+async fn reader(mut rx: UartRx<'static, UART1>) {
+    const MAX_BUFFER_SIZE: usize = 10 * READ_BUF_SIZE + 16;
+    //const MAX_BUFFER_SIZE: usize = 10;
+
+    let mut rbuf: [u8; MAX_BUFFER_SIZE] = [0u8; MAX_BUFFER_SIZE];
+    let mut decoder = FrameDecoder::new();
+
+    loop {
+	log::info!("Waiting for incomming responses from MP3 module");
+        //let r = with_timeout(Duration::from_secs_floor(2),embedded_io_async::Read::read(&mut rx, &mut rbuf[offset..])).await;
+	let r = embedded_io_async::Read::read(&mut rx, &mut rbuf).await;
+        match r {
+            Ok(len) => {
+                log::debug!("MP3 module Read: {len}, data: {:x?}", &rbuf[..len]);
+		for response in decoder.decode(&rbuf[..len]) {
+		    match response {
+			Ok(response) => {
+			    log::info!("MP3 module response: {response:?}");
+			    dfplayer_mini::route(&DF_REPLIES, &DF_EVENTS, response);
+			}
+			Err(why) => log::warn!("MP3 module sent a broken frame: {}", DfPlayerError::from(why)),
+		    }
+		}
+            }
+            Err(e) => log::error!("MP3 RX Error: {:?}", e),
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn loop_luces(mut io12: GpioPin<Output<PushPull>, 12>) {
+
+    loop {
+	Timer::after(Duration::from_millis(1000)).await;
+
+	io12.set_low().unwrap();
+	Timer::after(Duration::from_millis(1000)).await;
+
+	io12.set_high().unwrap();
+    }
+    
+}
+
+#[derive(Clone, Copy)]
+struct SharedControl;
+struct ParseSharedControl;
+
+use core::str::FromStr;
+impl FromStr for SharedControl {
+    type Err = ParseSharedControl;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	log::info!("shared state generation {s}");
+	Ok(SharedControl)
+    }
+}
+
+struct AppState {
+    shared_control: SharedControl,
+}
+
+impl picoserve::extract::FromRef<AppState> for SharedControl {
+    fn from_ref(state: &AppState) -> Self {
+        state.shared_control
+    }
+}
+
+type AppRouter = impl picoserve::routing::PathRouter<()>;
+
+#[embassy_executor::task]
+async fn dns_server(
+        stack: &'static ApStack,
+){
+    let mut rx_buffer = [0; 512];
+    let mut tx_buffer = [0; 512];
+
+    let mut rx_meta: [PacketMetadata; 300] = [PacketMetadata::EMPTY; 300];
+    let mut tx_meta: [PacketMetadata; 300] = [PacketMetadata::EMPTY; 300];
+
+    loop {
+        if stack.is_link_up() {
+            break;
+        }
+        Timer::after(Duration::from_millis(500)).await;
+    }
+    log::info!("DNS server started!!!");
+
+    let mut responder = DnsResponder::new(&dns::ZONE);
+    let mut socket = UdpSocket::new(&stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    
+    loop{
+
+	if !socket.is_open() {
+            socket.bind(IpListenEndpoint::from(53)).unwrap();
+	}
+
+	let mut dns_request = [0u8 ;512];
+	let mut dns_response = [0u8 ;dns::MAX_MESSAGE_SIZE];
+	
+	match socket.recv_from(&mut dns_request).await{
+	    Ok((size, req_addr)) => {
+		responder.portal = PORTAL_MODE.load(Ordering::Relaxed);
+		if let Some(len) = responder.respond(&dns_request[..size], &mut dns_response) {
+		    if let Err(why) = socket.send_to(&dns_response[..len], req_addr).await {
+			log::error!("Failed answering DNS query: {why:?}");
+		    }
+		}
+	    },
+	    Err(why) => {
+		log::error!("Failed reading UDP DNS Socket: {why:?}");
+	    }
+	}
+    }
+}
+
+#[embassy_executor::task]
+async fn dhcp_server(
+        stack: &'static ApStack,
+){
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+
+    let mut rx_meta: [PacketMetadata; 4] = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta: [PacketMetadata; 4] = [PacketMetadata::EMPTY; 4];
+
+    loop {
+        if stack.is_link_up() {
+            break;
+        }
+        Timer::after(Duration::from_millis(500)).await;
+    }
+    log::info!("DHCP server started");
+
+    let mut server = DhcpServer::new(DhcpConfig::new());
+    let mut socket = UdpSocket::new(&stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+
+    loop {
+	if !socket.is_open() {
+	    socket.bind(IpListenEndpoint::from(dhcp::SERVER_PORT)).unwrap();
+	}
+
+	let mut request = [0u8; 576];
+	let mut response = [0u8; dhcp::MAX_REPLY_SIZE];
+
+	match socket.recv_from(&mut request).await {
+	    Ok((size, _)) => {
+		if let Some(reply) = server.respond(&request[..size], &mut response, Instant::now()) {
+		    let destination = IpEndpoint::new(
+			Ipv4Address::from_bytes(&reply.destination).into(),
+			dhcp::CLIENT_PORT,
+		    );
+		    if let Err(why) = socket.send_to(&response[..reply.len], destination).await {
+			log::error!("Failed answering DHCP request: {why:?}");
+		    }
+		}
+	    },
+	    Err(why) => {
+		log::error!("Failed reading UDP DHCP Socket: {why:?}");
+	    }
+	}
+    }
+}
+
+#[embassy_executor::task]
+async fn mdns_responder(
+        stack: &'static StaStack,
+){
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+
+    let mut rx_meta: [PacketMetadata; 4] = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta: [PacketMetadata; 4] = [PacketMetadata::EMPTY; 4];
+
+    let group = Ipv4Address::from_bytes(&mdns::MDNS_ADDRESS);
+    let mut responder = MdnsResponder::new([0; 4], 80, env!("CARGO_PKG_VERSION"));
+    let mut socket = UdpSocket::new(&stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    let multicast = IpEndpoint::new(group.into(), mdns::MDNS_PORT);
+
+    socket.bind(IpListenEndpoint::from(mdns::MDNS_PORT)).unwrap();
+    let mut request = [0u8; mdns::MAX_MESSAGE_SIZE];
+    let mut response = [0u8; mdns::MAX_MESSAGE_SIZE];
+
+    loop {
+	// each network joined learns of the pesebre
+	let address = local_address(stack);
+	if address == [0; 4] && responder.address != [0; 4] {
+	    // no stale address while disconnected, and all again on reconnecting
+	    log::info!("mDNS responder silent until the network is back");
+	    responder.address = address;
+	    if let Err(why) = stack.leave_multicast_group(group).await {
+		log::error!("Failed leaving the mDNS group: {why:?}");
+	    }
+	} else if address != [0; 4] && address != responder.address {
+	    responder.address = address;
+	    if let Err(why) = stack.join_multicast_group(group).await {
+		log::error!("Failed joining the mDNS group: {why:?}");
+	    }
+	    log::info!("mDNS responder announcing {} as {}", mdns::HOSTNAME, dotted(address));
+	    for _ in 0..MDNS_ANNOUNCEMENTS {
+		let len = responder.announce(&mut response);
+		if let Err(why) = socket.send_to(&response[..len], multicast).await {
+		    log::error!("Failed announcing over mDNS: {why:?}");
+		}
+		Timer::after(Duration::from_secs(1)).await;
+	    }
+	}
+
+	match select(socket.recv_from(&mut request), Timer::after(MDNS_ADDRESS_CHECK)).await {
+	    Either::First(Ok((size, source))) => {
+		let legacy = source.port != mdns::MDNS_PORT;
+		if let Some(reply) = responder.respond(&request[..size], &mut response, legacy) {
+		    let destination = if reply.unicast { source } else { multicast };
+		    if let Err(why) = socket.send_to(&response[..reply.len], destination).await {
+			log::error!("Failed answering mDNS query: {why:?}");
+		    }
+		}
+	    },
+	    Either::First(Err(why)) => {
+		log::error!("Failed reading UDP mDNS Socket: {why:?}");
+	    }
+	    Either::Second(()) => (),
+	}
+    }
+}
+
+/// IPv4 address leased by the household network, if any.
+fn local_address(stack: &StaStack) -> [u8; 4] {
+    stack.config_v4().map(|config| config.address.address().0).unwrap_or_default()
+}
+
+#[embassy_executor::task(pool_size = WEB_TASK_POOL_SIZE)]
+async fn web_task(
+    stack: &'static ApStack,
+    app: &'static picoserve::Router<AppRouter>,
+    config: &'static picoserve::Config<Duration>,
+){
+    serve_web(stack, app, config, "Connect to the AP `pesebre-navideño` and point your browser to http://192.168.2.1/").await
+}
+
+#[embassy_executor::task(pool_size = WEB_TASK_POOL_SIZE)]
+async fn station_web_task(
+    stack: &'static StaStack,
+    app: &'static picoserve::Router<AppRouter>,
+    config: &'static picoserve::Config<Duration>,
+){
+    serve_web(stack, app, config, "Point your browser to http://pesebre.local/").await
+}
+
+/// Serves the control panel on `stack`, one connection at a time, once the
+/// link is up.
+async fn serve_web<D: Driver>(
+    stack: &Stack<D>,
+    app: &'static picoserve::Router<AppRouter>,
+    config: &'static picoserve::Config<Duration>,
+    greeting: &str,
+){
+    let mut rx_buffer = [0; 1536];
+    let mut tx_buffer = [0; 1536];
+
+    loop {
+        if stack.is_link_up() {
+            break;
+        }
+        Timer::after(Duration::from_millis(500)).await;
+    }
+    log::info!("{greeting}");
+
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
+
+    
+    loop {
+        log::info!("Waiting for incomming HTTP connection...");
+        let r = socket
+            .accept(IpListenEndpoint {
+                addr: None,
+                port: 80,
+            })
+            .await;
+        log::info!("HTTP browser connected...");
+
+        if let Err(e) = r {
+            log::error!("connect error: {:?}", e);
+            continue;
+        }
+
+	let (socket_rx, socket_tx) = socket.split();
+
+        match picoserve::serve(
+            app,
+            EmbassyTimer,
+            config,
+            &mut [0; 2048],
+            socket_rx,
+            socket_tx,
+        )
+        .await
+        {
+            Ok(handled_requests_count) => {
+                log::info!(
+                    "{handled_requests_count} requests handled from {:?}",
+                    socket.remote_endpoint()
+                );
+            }
+            Err(err) => log::error!("Picoserver error: {err:?}"),
+        }
+	
+        socket.close();
+        socket.abort();
+    }
+}
+
+
+/// What woke the connection task up.
+enum Wake {
+    /// The household network went away.
+    Disconnected,
+    /// Stations came or went from the access point.
+    Stations,
+    /// The state machine has something due.
+    Deadline,
+    ButtonHeld,
+    NetworkChanged,
+    AccessPointChanged,
+    /// The setup page wants the networks around.
+    ScanRequested,
+    /// The setup page chose a network to try.
+    Candidate(Candidate),
+}
+
+#[embassy_executor::task]
+async fn connection(mut controller: WifiController<'static>, sta_stack: &'static StaStack) {
+    log::info!("start connection task");
+    log::info!("Device capabilities: {:?}", controller.get_capabilities());
+    let mut connectivity = Connectivity::new(!CONTROL.settings.get().wifi_ssid.is_empty());
+    // how the radio is set up, `None` when it has to be set up again
+    let mut mode = None;
+    loop {
+	if mode != Some(connectivity.mode()) {
+	    // the captive portal only makes sense on the access point
+	    PORTAL_MODE.store(connectivity.mode() == Mode::AccessPoint, Ordering::Relaxed);
+	    if set_up_wifi(&mut controller, connectivity.mode()).await {
+		mode = Some(connectivity.mode());
+	    } else {
+		continue;
+	    }
+	}
+
+	if let ConnectivityState::Joining{ attempt } = connectivity.state() {
+	    let ssid = CONTROL.settings.get().wifi_ssid;
+	    log::info!("Joining '{ssid}', attempt {attempt}");
+	    match join(&mut controller, sta_stack).await {
+		Ok(address) => {
+		    log::info!("Joined '{ssid}': point your browser to http://{}/", dotted(address));
+		    CONTROL.device.update(|info| info.station_address = Some(address));
+		    connectivity.joined();
+		}
+		Err(why) => {
+		    log::warn!("Failed joining '{ssid}': {why}");
+		    connectivity.join_failed(Instant::now());
+		    if connectivity.mode() == Mode::AccessPoint {
+			log::warn!("Falling back to the access point");
+		    }
+		}
+	    }
+	    continue;
+	}
+
+	let wifi = async {
+	    match connectivity.state() {
+		ConnectivityState::Connected => {
+		    controller.wait_for_event(WifiEvent::StaDisconnected).await;
+		    Wake::Disconnected
+		}
+		ConnectivityState::Fallback{ .. } => {
+		    // count the stations coming and going for the status
+		    let events = controller
+			.wait_for_events(WifiEvent::ApStaconnected | WifiEvent::ApStadisconnected, false)
+			.await;
+		    if events.contains(WifiEvent::ApStaconnected) {
+			log::info!("An station connected to the AP !!!!");
+			CONTROL.device.update(|info| info.stations = info.stations.saturating_add(1));
+		    }
+		    if events.contains(WifiEvent::ApStadisconnected) {
+			log::info!("An station left the AP");
+			CONTROL.device.update(|info| info.stations = info.stations.saturating_sub(1));
+		    }
+		    Wake::Stations
+		}
+		_ => pending().await,
+	    }
+	};
+	let deadline = async {
+	    match connectivity.deadline() {
+		Some(at) => Timer::at(at).await,
+		None => pending().await,
+	    }
+	    Wake::Deadline
+	};
+	let button = async {
+	    BUTTON_HELD.wait().await;
+	    Wake::ButtonHeld
+	};
+	let settings = async {
+	    match select4(
+		CONTROL.settings.network_changed(),
+		CONTROL.settings.access_point_changed(),
+		CONTROL.provisioning.scan_requested(),
+		CONTROL.provisioning.candidate(),
+	    ).await {
+		Either4::First(()) => Wake::NetworkChanged,
+		Either4::Second(()) => Wake::AccessPointChanged,
+		Either4::Third(()) => Wake::ScanRequested,
+		Either4::Fourth(candidate) => Wake::Candidate(candidate),
+	    }
+	};
+	let wake = match select4(wifi, deadline, button, settings).await {
+	    Either4::First(wake) | Either4::Second(wake) | Either4::Third(wake) | Either4::Fourth(wake) => wake,
+	};
+
+	match wake {
+	    Wake::Disconnected => {
+		log::warn!("Left the household network");
+		CONTROL.device.update(|info| info.station_address = None);
+		connectivity.disconnected();
+	    }
+	    Wake::Stations => (),
+	    Wake::Deadline => {
+		// look for the household network only while nobody is on the
+		// access point, as joining it takes the access point down
+		let seen = connectivity.mode() == Mode::AccessPoint
+		    && CONTROL.device.get().stations == 0
+		    && network_in_sight(&mut controller, &CONTROL.settings.get().wifi_ssid).await;
+		connectivity.tick(Instant::now(), seen);
+	    }
+	    Wake::ButtonHeld => {
+		log::info!("Button held");
+		connectivity.button_held();
+	    }
+	    Wake::NetworkChanged => {
+		let network = !CONTROL.settings.get().wifi_ssid.is_empty();
+		connectivity.network_changed(network);
+		mode = None;
+	    }
+	    Wake::AccessPointChanged => {
+		if mode == Some(Mode::AccessPoint) {
+		    // let the settings page get its answer first
+		    Timer::after(AP_RESTART_DELAY).await;
+		    mode = None;
+		}
+	    }
+	    Wake::ScanRequested => {
+		let networks = scan(&mut controller).await;
+		log::info!("{} networks around", networks.len());
+		CONTROL.provisioning.publish_networks(&networks);
+	    }
+	    Wake::Candidate(candidate) => {
+		log::info!("Trying '{}'", candidate.ssid);
+		let trial = try_network(&mut controller, sta_stack, connectivity.mode(), &candidate).await;
+		CONTROL.provisioning.report(trial);
+		match trial {
+		    Trial::Joined(address) => {
+			log::info!("Joined '{}' at {}, saving it", candidate.ssid, dotted(address));
+			// let the setup page get the address first
+			Timer::after(SETUP_SWITCH_DELAY).await;
+			CONTROL.settings.update_network(candidate.ssid, candidate.password);
+		    }
+		    _ => {
+			log::warn!("Failed trying '{}'", candidate.ssid);
+			if connectivity.mode() == Mode::Station {
+			    // back to the network saved
+			    CONTROL.device.update(|info| info.station_address = None);
+			    connectivity.disconnected();
+			    mode = None;
+			} else {
+			    let settings = CONTROL.settings.get();
+			    let config = Configuration::Mixed(client_configuration(&settings), access_point_configuration(&settings));
+			    if controller.set_configuration(&config).is_err() {
+				mode = None;
+			    }
+			}
+		    }
+		}
+	    }
+	}
+	log::info!("connection task loop...!");
+    }
+}
+
+/// Sets the radio up for `mode` as the settings say, and starts it.
+/// `false` when it failed, after a pause.
+async fn set_up_wifi(controller: &mut WifiController<'static>, mode: Mode) -> bool {
+    if matches!(controller.is_started(), Ok(true)) {
+	log::info!("Stopping wifi for the new settings");
+	if let Err(why) = controller.stop().await {
+	    log::error!("Failed stopping wifi: {why:?}");
+	}
+    }
+    CONTROL.device.update(|info| {
+	info.stations = 0;
+	info.station_address = None;
+    });
+
+    let settings = CONTROL.settings.get();
+    let config = match mode {
+	Mode::Station => Configuration::Client(client_configuration(&settings)),
+	Mode::AccessPoint => {
+	    let access_point = &settings.access_point;
+	    if access_point.is_open() {
+		log::warn!("Access point '{}' open until a password is set in /settings", access_point.ssid());
+	    }
+	    log::info!("Starting access point '{}' on channel {}", access_point.ssid(), access_point.channel);
+	    // the station stays up to look for the household network
+	    Configuration::Mixed(client_configuration(&settings), access_point_configuration(&settings))
+	}
+    };
+    if let Err(why) = controller.set_configuration(&config) {
+	log::error!("Failed setting up wifi: {why:?}");
+	Timer::after(Duration::from_secs(1)).await;
+	return false;
+    }
+    match controller.start().await {
+	Ok(()) => {
+	    log::info!("Wifi started as {mode:?}!");
+	    true
+	}
+	Err(why) => {
+	    log::error!("Failed starting wifi: {why:?}");
+	    Timer::after(Duration::from_secs(1)).await;
+	    false
+	}
+    }
+}
+
+fn client_configuration(settings: &Settings) -> ClientConfiguration {
+    network_configuration(&settings.wifi_ssid, &settings.wifi_password)
+}
+
+fn network_configuration(ssid: &str, password: &str) -> ClientConfiguration {
+    ClientConfiguration {
+	ssid: ssid.into(),
+	auth_method: if password.is_empty() { AuthMethod::None } else { AuthMethod::WPA2Personal },
+	password: password.into(),
+	..Default::default()
+    }
+}
+
+fn access_point_configuration(settings: &Settings) -> AccessPointConfiguration {
+    let access_point = &settings.access_point;
+    AccessPointConfiguration {
+	ssid: access_point.ssid().into(),
+	ssid_hidden: access_point.hidden,
+	channel: access_point.channel,
+	auth_method: if access_point.is_open() { AuthMethod::None } else { AuthMethod::WPA2Personal },
+	password: access_point.password.as_str().into(),
+	max_connections: access_point.max_clients.into(),
+	..Default::default()
+    }
+}
+
+/// Joins the household network set up, returning the address it leased.
+async fn join(controller: &mut WifiController<'static>, sta_stack: &StaStack) -> Result<[u8; 4], &'static str> {
+    let joined = match with_timeout(JOIN_TIMEOUT, controller.connect()).await {
+	Ok(Ok(())) => Ok(()),
+	Ok(Err(why)) => {
+	    log::debug!("Wifi connect error: {why:?}");
+	    Err("refused")
+	}
+	Err(_) => Err("no answer"),
+    };
+    let joined = match joined {
+	Ok(()) => with_timeout(DHCP_TIMEOUT, leased_address(sta_stack)).await.map_err(|_| "no address leased"),
+	Err(why) => Err(why),
+    };
+    if joined.is_err() {
+	// give up on it for good, for the next attempt to start afresh
+	let _ = controller.disconnect().await;
+    }
+    joined
+}
+
+/// Waits for the household network to lease an address.
+async fn leased_address(sta_stack: &StaStack) -> [u8; 4] {
+    loop {
+	if let Some(config) = sta_stack.config_v4() {
+	    return config.address.address().0;
+	}
+	Timer::after(Duration::from_millis(200)).await;
+    }
+}
+
+/// Tries the network chosen on the setup page, leaving the access point up
+/// in `mode` [`Mode::AccessPoint`].
+async fn try_network(
+    controller: &mut WifiController<'static>,
+    sta_stack: &StaStack,
+    mode: Mode,
+    candidate: &Candidate,
+) -> Trial {
+    if matches!(controller.is_connected(), Ok(true)) {
+	let _ = controller.disconnect().await;
+    }
+    let client = network_configuration(&candidate.ssid, &candidate.password);
+    let config = match mode {
+	Mode::Station => Configuration::Client(client),
+	Mode::AccessPoint => Configuration::Mixed(client, access_point_configuration(&CONTROL.settings.get())),
+    };
+    if let Err(why) = controller.set_configuration(&config) {
+	log::error!("Failed setting up wifi: {why:?}");
+	return Trial::Failed("wifi unavailable");
+    }
+    match join(controller, sta_stack).await {
+	Ok(address) => Trial::Joined(address),
+	Err(why) => Trial::Failed(why),
+    }
+}
+
+/// Networks around, strongest first.
+async fn scan(controller: &mut WifiController<'static>) -> Networks {
+    let mut networks = Networks::new();
+    match controller.scan_n::<SCAN_SIZE>().await {
+	Ok((seen, _)) => {
+	    for network in seen {
+		let security = match network.auth_method {
+		    AuthMethod::None => Security::Open,
+		    AuthMethod::WEP => Security::Wep,
+		    AuthMethod::WPA => Security::Wpa,
+		    AuthMethod::WPA2Personal | AuthMethod::WPAWPA2Personal | AuthMethod::WAPIPersonal => Security::Wpa2,
+		    AuthMethod::WPA3Personal | AuthMethod::WPA2WPA3Personal => Security::Wpa3,
+		    AuthMethod::WPA2Enterprise => Security::Enterprise,
+		};
+		let network = Network{ ssid: network.ssid.as_str().into(), rssi: network.signal_strength, security };
+		provisioning::add_network(&mut networks, network);
+	    }
+	}
+	Err(why) => log::error!("Failed looking for networks: {why:?}"),
+    }
+    networks
+}
+
+/// Whether the network `ssid` is around.
+async fn network_in_sight(controller: &mut WifiController<'static>, ssid: &str) -> bool {
+    scan(controller).await.iter().any(|network| network.ssid == ssid)
+}
+
+/// Tells the connection task when the button is held long enough.
+#[embassy_executor::task]
+async fn button_task(button: GpioPin<Input<PullUp>, 9>) {
+    let mut pressed_since = None;
+    let mut told = false;
+    loop {
+	Timer::after(BUTTON_POLL).await;
+	if button.is_high().unwrap() {
+	    pressed_since = None;
+	    told = false;
+	    continue;
+	}
+	let since = *pressed_since.get_or_insert_with(Instant::now);
+	if !told && since.elapsed() >= BUTTON_HOLD {
+	    BUTTON_HELD.signal(());
+	    told = true;
+	}
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static ApStack) {
+    log::info!("net_task before");
+    stack.run().await;
+    log::info!("net_task after");
+}
+
+#[embassy_executor::task]
+async fn station_net_task(stack: &'static StaStack) {
+    stack.run().await;
+}
@@ -0,0 +1,288 @@
+//! Generates the track table of `src/catalog.rs` from the recordings in
+//! `musica/`, the folder copied to the SD card of the DFPlayer Mini.
+//!
+//! Every `NNN Title.mp3` file becomes a track numbered `NNN`, titled `Title`
+//! and as long as its MP3 frames say. `musica/catalogo.txt` gives the
+//! category of the tracks that aren't villancicos, a title when the file name
+//! can't carry it, and, marked with `*`, the tracks only the card holds, of
+//! unknown length. The build fails when two files or lines share a number,
+//! a line names a track without its file, a marked line has a file or no
+//! title, or `catalog::WELCOME` isn't in the catalog.
+//!
+//! `PESEBRE_MUSICA` points the build to another copy of the card.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const CATALOG : &str = "catalogo.txt";
+
+struct Recording {
+    title: String,
+    file: PathBuf,
+    duration_ms: Option<u64>,
+}
+
+/// Line of `catalogo.txt`.
+struct Entry {
+    /// Name of the `Category` variant.
+    category: &'static str,
+    title: Option<String>,
+    /// Marked as only in the card.
+    card_only: bool,
+    /// Where the line is, for the errors.
+    at: String,
+}
+
+fn main() {
+    let musica = env::var_os("PESEBRE_MUSICA")
+	.map(PathBuf::from)
+	.unwrap_or_else(|| Path::new(&env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("../musica"));
+    println!("cargo:rerun-if-env-changed=PESEBRE_MUSICA");
+    println!("cargo:rerun-if-changed={}", musica.display());
+
+    let mut errors = Vec::new();
+    let recordings = scan(&musica, &mut errors);
+    let catalog = read_catalog(&musica.join(CATALOG), &mut errors);
+
+    // id, title, category and length in ms of every track
+    let mut tracks = BTreeMap::new();
+    for (id, recording) in &recordings {
+	tracks.insert(*id, (recording.title.as_str(), "Villancico", recording.duration_ms));
+    }
+    for (id, entry) in &catalog {
+	let recording = recordings.get(id);
+	let title = match (recording, entry.card_only) {
+	    (Some(recording), false) => entry.title.as_deref().unwrap_or(&recording.title),
+	    (None, true) => match &entry.title {
+		Some(title) => title,
+		None => {
+		    errors.push(format!("{}: track {id:03}, only in the card, has no title", entry.at));
+		    continue;
+		}
+	    },
+	    (Some(recording), true) => {
+		errors.push(format!("{}: track {id:03} is marked as only in the card, but {} has it", entry.at, recording.file.display()));
+		continue;
+	    }
+	    (None, false) => {
+		errors.push(format!("{}: track {id:03} has no file, mark it with * if only the card has it", entry.at));
+		continue;
+	    }
+	};
+	tracks.insert(*id, (title, entry.category, recording.and_then(|recording| recording.duration_ms)));
+    }
+
+    match welcome_track() {
+	Ok(welcome) if !tracks.contains_key(&welcome) => {
+	    errors.push(format!("track {welcome:03}, catalog::WELCOME, is neither a file nor marked in {CATALOG}"));
+	}
+	Ok(_) => (),
+	Err(why) => errors.push(why),
+    }
+
+    let mut table = String::new();
+    writeln!(table, "pub static TRACKS : [Track; {}] = [", tracks.len()).unwrap();
+    for (id, (title, category, duration_ms)) in &tracks {
+	let duration = match duration_ms {
+	    Some(ms) => format!("Some(Duration::from_millis({ms}))"),
+	    None => "None".into(),
+	};
+	writeln!(
+	    table,
+	    "    Track{{ id: {id}, title: {title:?}, category: Category::{category}, duration: {duration} }},",
+	).unwrap();
+    }
+    writeln!(table, "];").unwrap();
+
+    let out = Path::new(&env::var_os("OUT_DIR").unwrap()).join("tracks.rs");
+    if let Err(why) = fs::write(&out, table) {
+	errors.push(format!("can't write {}: {why}", out.display()));
+    }
+
+    if !errors.is_empty() {
+	for error in &errors {
+	    eprintln!("error: {error}");
+	}
+	process::exit(1);
+    }
+}
+
+/// Id of the track `catalog::WELCOME` names.
+fn welcome_track() -> Result<u16, String> {
+    const PREFIX : &str = "pub const WELCOME : u16 = ";
+    println!("cargo:rerun-if-changed=src/catalog.rs");
+    let source = fs::read_to_string("src/catalog.rs").map_err(|why| format!("can't read src/catalog.rs: {why}"))?;
+    source.lines()
+	.find_map(|line| line.strip_prefix(PREFIX)?.strip_suffix(';')?.parse().ok())
+	.ok_or_else(|| format!("no \"{PREFIX}NNN;\" in src/catalog.rs"))
+}
+
+/// Finds the `NNN Title.mp3` files in `dir`.
+fn scan(dir: &Path, errors: &mut Vec<String>) -> BTreeMap<u16, Recording> {
+    let mut recordings = BTreeMap::new();
+    let entries = match fs::read_dir(dir) {
+	Ok(entries) => entries,
+	Err(why) => {
+	    errors.push(format!("can't read {}: {why}", dir.display()));
+	    return recordings;
+	}
+    };
+
+    for entry in entries {
+	let file = match entry {
+	    Ok(entry) => entry.path(),
+	    Err(why) => {
+		errors.push(format!("can't read {}: {why}", dir.display()));
+		continue;
+	    }
+	};
+	let Some(name) = file.file_name().and_then(|name| name.to_str()) else {
+	    continue;
+	};
+	let Some(stem) = name.strip_suffix(".mp3").or_else(|| name.strip_suffix(".MP3")) else {
+	    continue;
+	};
+
+	let Some((id, title)) = stem.split_once(' ').and_then(|(id, title)| {
+	    let id = id.parse::<u16>().ok().filter(|id| (1..=3000).contains(id))?;
+	    Some((id, title.trim()))
+	}) else {
+	    errors.push(format!("{} isn't named \"NNN Title.mp3\"", file.display()));
+	    continue;
+	};
+
+	let duration_ms = match fs::read(&file) {
+	    Ok(data) => mp3_duration_ms(&data),
+	    Err(why) => {
+		errors.push(format!("can't read {}: {why}", file.display()));
+		continue;
+	    }
+	};
+	if duration_ms.is_none() {
+	    println!("cargo:warning=no MP3 frames found in {}", file.display());
+	}
+
+	let recording = Recording{ title: title.into(), file, duration_ms };
+	if let Some(other) = recordings.get(&id) {
+	    errors.push(format!(
+		"{} and {} are both track {id}",
+		other.file.display(),
+		recording.file.display(),
+	    ));
+	    continue;
+	}
+	recordings.insert(id, recording);
+    }
+
+    recordings
+}
+
+/// Reads the `[*]NNN category [Title]` lines of `catalogo.txt`.
+fn read_catalog(path: &Path, errors: &mut Vec<String>) -> BTreeMap<u16, Entry> {
+    let mut catalog = BTreeMap::new();
+    let Ok(text) = fs::read_to_string(path) else {
+	return catalog;
+    };
+
+    for (number, line) in text.lines().enumerate() {
+	let line = line.split('#').next().unwrap().trim();
+	if line.is_empty() {
+	    continue;
+	}
+	let at = format!("{}:{}", path.display(), number + 1);
+
+	let mut fields = line.splitn(3, char::is_whitespace);
+	let (Some(id), Some(category)) = (fields.next(), fields.next()) else {
+	    errors.push(format!("{at}: expected \"[*]NNN category [Title]\""));
+	    continue;
+	};
+	let title = fields.next().map(str::trim).filter(|title| !title.is_empty()).map(String::from);
+	let (id, card_only) = match id.strip_prefix('*') {
+	    Some(id) => (id, true),
+	    None => (id, false),
+	};
+	let Some(id) = id.parse::<u16>().ok().filter(|id| (1..=3000).contains(id)) else {
+	    errors.push(format!("{at}: {id} isn't a track number"));
+	    continue;
+	};
+	let category = match category {
+	    "villancico" => "Villancico",
+	    "novena" => "Novena",
+	    "historia" => "Story",
+	    "sonido" => "SoundEffect",
+	    "jingle" => "Jingle",
+	    "bienvenida" => "Welcome",
+	    other => {
+		errors.push(format!("{at}: unknown category {other}"));
+		continue;
+	    }
+	};
+
+	if let Some(other) = catalog.get(&id) {
+	    errors.push(format!("{at}: track {id:03} already listed at {}", other.at));
+	    continue;
+	}
+	catalog.insert(id, Entry{ category, title, card_only, at });
+    }
+
+    catalog
+}
+
+/// Adds up the length of the MPEG audio layer III frames in `data`.
+fn mp3_duration_ms(data: &[u8]) -> Option<u64> {
+    /** Bit rates in kbit/s, MPEG 1 and MPEG 2/2.5 */
+    const BITRATES : [[u32; 15]; 2] = [
+	[0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+	[0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ];
+    /** Sample rates in Hz, MPEG 1, MPEG 2 and MPEG 2.5 */
+    const SAMPLE_RATES : [[u32; 3]; 3] = [
+	[44100, 48000, 32000],
+	[22050, 24000, 16000],
+	[11025, 12000, 8000],
+    ];
+
+    let mut i = 0;
+    // skip the ID3v2 tag
+    if data.len() >= 10 && &data[..3] == b"ID3" {
+	let size = data[6..10].iter().fold(0usize, |size, b| (size << 7) | (*b & 0x7F) as usize);
+	i = 10 + size;
+    }
+
+    let mut samples_us : u64 = 0;
+    let mut frames = 0;
+    while i + 4 <= data.len() {
+	let header = &data[i..i + 4];
+	let version = (header[1] >> 3) & 0x3;
+	let layer = (header[1] >> 1) & 0x3;
+	let bitrate_index = (header[2] >> 4) as usize;
+	let rate_index = ((header[2] >> 2) & 0x3) as usize;
+	let padding = ((header[2] >> 1) & 0x1) as u32;
+
+	let is_frame = header[0] == 0xFF
+	    && header[1] & 0xE0 == 0xE0
+	    && version != 1
+	    && layer == 1
+	    && bitrate_index != 0 && bitrate_index != 15
+	    && rate_index != 3;
+	if !is_frame {
+	    i += 1;
+	    continue;
+	}
+
+	let mpeg1 = version == 3;
+	let bitrate = BITRATES[if mpeg1 { 0 } else { 1 }][bitrate_index] * 1000;
+	let sample_rate = SAMPLE_RATES[match version { 3 => 0, 2 => 1, _ => 2 }][rate_index];
+	let (samples, factor) = if mpeg1 { (1152, 144) } else { (576, 72) };
+
+	samples_us += samples * 1_000_000 / sample_rate as u64;
+	frames += 1;
+	i += (factor * bitrate / sample_rate + padding) as usize;
+    }
+
+    (frames > 0).then_some(samples_us / 1000)
+}
@@ -0,0 +1,434 @@
+//! Versioned REST interface of the control panel, under `/api/v1`.
+//!
+//! Commands change the player with `POST` and take their parameters as a
+//! JSON body, e.g. `{"track":12}`; resources such as the volume are read
+//! with `GET` and changed with `PUT`, e.g. `{"volume":20}`. Changes are
+//! answered with `204 No Content` once the player task has them, or with an
+//! error status and `{"error":"..."}`. The settings and the setup of the
+//! household network take the admin password, see [`crate::auth`].
+
+use picoserve::extract::FromRequest;
+use picoserve::request::Request;
+use picoserve::response::{IntoResponse, Json, ResponseWriter, StatusCode};
+use picoserve::routing::{MethodHandler, MethodNotAllowed, RequestHandler};
+use picoserve::ResponseSent;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use heapless::String;
+
+use crate::auth::{Credentials, REALM};
+use crate::catalog;
+use crate::control::{Control, ControlMessages};
+use crate::http::Interface;
+use crate::player::PlayerState;
+use crate::playlist::PLAYLIST_SIZE;
+use crate::provisioning::{Candidate, Trial};
+use crate::settings;
+use crate::volume::MAX_VOLUME;
+
+/// Longest string of a body, once unescaped.
+const MAX_STRING_SIZE : usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiError {
+    /// The body isn't the JSON the command takes.
+    BadRequest(&'static str),
+    /// The admin password is missing or wrong.
+    Unauthorized,
+    UnknownTrack(u16),
+    /// The command doesn't apply to what the player is doing.
+    InvalidState(&'static str),
+    /// The player task isn't taking commands, or the module is down.
+    Unavailable,
+}
+
+impl ApiError {
+    pub fn status_code(&self) -> StatusCode {
+	StatusCode::new(match self {
+	    Self::BadRequest(_) => 400,
+	    Self::Unauthorized => 401,
+	    Self::UnknownTrack(_) => 404,
+	    Self::InvalidState(_) => 409,
+	    Self::Unavailable => 503,
+	})
+    }
+
+    pub fn message(&self) -> &'static str {
+	match self {
+	    Self::BadRequest(message) | Self::InvalidState(message) => message,
+	    Self::Unauthorized => "unauthorized",
+	    Self::UnknownTrack(_) => "unknown track",
+	    Self::Unavailable => "player unavailable",
+	}
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorReply {
+    error: &'static str,
+}
+
+impl IntoResponse for ApiError {
+    async fn write_to<W: ResponseWriter>(self, response_writer: W) -> Result<ResponseSent, W::Error> {
+	log::warn!("API request rejected: {self:?}");
+	let response = Json(ErrorReply{ error: self.message() })
+	    .into_response()
+	    .with_status_code(self.status_code());
+	if self == Self::Unauthorized {
+	    // for the browser to ask for the password
+	    response
+		.with_header("WWW-Authenticate", format_args!("Basic realm=\"{REALM}\""))
+		.write_to(response_writer)
+		.await
+	} else {
+	    response.write_to(response_writer).await
+	}
+    }
+}
+
+/// The command was handed to the player task.
+pub struct Accepted;
+
+impl IntoResponse for Accepted {
+    async fn write_to<W: ResponseWriter>(self, response_writer: W) -> Result<ResponseSent, W::Error> {
+	(StatusCode::new(204), "").write_to(response_writer).await
+    }
+}
+
+/// Extracts the JSON body of a request.
+pub struct JsonBody<T>(pub T);
+
+impl<State, T: DeserializeOwned> FromRequest<State> for JsonBody<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(_state: &State, request: &Request<'_>) -> Result<Self, ApiError> {
+	let mut unescaped = [0; MAX_STRING_SIZE];
+	serde_json_core::from_slice_escaped(request.body(), &mut unescaped)
+	    .map(|(value, _)| Self(value))
+	    .map_err(|_| ApiError::BadRequest("malformed body"))
+    }
+}
+
+/// Body of `POST /api/v1/play`, and of `POST /api/v1/playlist` and
+/// `/api/v1/playlist/next`.
+#[derive(Deserialize)]
+pub struct PlayRequest {
+    pub track: u16,
+}
+
+/// Body of `POST /api/v1/playlist/remove`, 0 being the next track.
+#[derive(Deserialize)]
+pub struct PositionRequest {
+    pub position: usize,
+}
+
+/// Body of `POST /api/v1/playlist/move`.
+#[derive(Deserialize)]
+pub struct MoveRequest {
+    pub from: usize,
+    pub to: usize,
+}
+
+/// Volume, as read with `GET /api/v1/volume` and changed with `PUT`, with
+/// any of the fields.
+#[derive(Serialize, Deserialize)]
+pub struct VolumeRequest {
+    /// From 0 to 30, up to the cap.
+    pub volume: Option<u8>,
+    pub muted: Option<bool>,
+    /// Cap of the volume, from 0 to 30.
+    pub max: Option<u8>,
+}
+
+/// Access point, as read with `GET /api/v1/settings/access-point` and
+/// changed with `PUT`, with any of the fields. The password is never read
+/// back, only whether there is one.
+#[derive(Serialize, Deserialize)]
+pub struct AccessPointRequest {
+    pub ssid: Option<String<32>>,
+    /// WPA2 passphrase, of 8 to 63 characters.
+    #[serde(skip_serializing)]
+    pub password: Option<String<64>>,
+    #[serde(default, skip_deserializing)]
+    pub secured: bool,
+    pub channel: Option<u8>,
+    pub hidden: Option<bool>,
+    pub max_clients: Option<u8>,
+}
+
+/// Body of `POST /api/v1/settings/password`.
+#[derive(Deserialize)]
+pub struct PasswordRequest {
+    pub password: String<64>,
+}
+
+/// Hands `message` to the player task, without waiting for room.
+fn send(control: &Control, message: ControlMessages) -> Result<Accepted, ApiError> {
+    control.commands.try_send(message).map_err(|_| ApiError::Unavailable)?;
+    Ok(Accepted)
+}
+
+/// Checks the module can play: it didn't fail, and has its card in. Stopping
+/// is left out, for it clears the failure once the module answers again.
+fn check_module(control: &Control) -> Result<(), ApiError> {
+    if matches!(control.state.get(), PlayerState::Error(_)) || control.device.get().card_removed {
+	return Err(ApiError::Unavailable);
+    }
+    Ok(())
+}
+
+pub(crate) fn play(control: &Control, id: u16) -> Result<Accepted, ApiError> {
+    let track = catalog::find(id).ok_or(ApiError::UnknownTrack(id))?;
+    check_module(control)?;
+    log::info!("Cancion solicitada #{} {}", track.id, track.title);
+    send(control, ControlMessages::Play(track))
+}
+
+pub(crate) fn pause(control: &Control) -> Result<Accepted, ApiError> {
+    check_module(control)?;
+    if !matches!(control.state.get(), PlayerState::Playing{ .. }) {
+	return Err(ApiError::InvalidState("nothing playing"));
+    }
+    log::info!("Pausa solicitada");
+    send(control, ControlMessages::Pause)
+}
+
+pub(crate) fn resume(control: &Control) -> Result<Accepted, ApiError> {
+    check_module(control)?;
+    if !control.state.get().is_paused() {
+	return Err(ApiError::InvalidState("nothing paused"));
+    }
+    log::info!("Reanudar solicitado");
+    send(control, ControlMessages::Resume)
+}
+
+pub(crate) fn stop(control: &Control) -> Result<Accepted, ApiError> {
+    log::info!("Parada solicitada");
+    send(control, ControlMessages::Stop)
+}
+
+/// Adds a track at the end of the playlist.
+pub(crate) fn enqueue(control: &Control, id: u16) -> Result<Accepted, ApiError> {
+    let track = catalog::find(id).ok_or(ApiError::UnknownTrack(id))?;
+    check_room(control)?;
+    log::info!("Cancion encolada #{} {}", track.id, track.title);
+    send(control, ControlMessages::Enqueue(track))
+}
+
+/// Adds a track in front of the playlist.
+pub(crate) fn play_next(control: &Control, id: u16) -> Result<Accepted, ApiError> {
+    let track = catalog::find(id).ok_or(ApiError::UnknownTrack(id))?;
+    check_room(control)?;
+    log::info!("Cancion siguiente #{} {}", track.id, track.title);
+    send(control, ControlMessages::PlayNext(track))
+}
+
+pub(crate) fn remove(control: &Control, position: usize) -> Result<Accepted, ApiError> {
+    check_position(control, position)?;
+    send(control, ControlMessages::Remove(position))
+}
+
+pub(crate) fn reorder(control: &Control, from: usize, to: usize) -> Result<Accepted, ApiError> {
+    check_position(control, from)?;
+    check_position(control, to)?;
+    send(control, ControlMessages::Reorder{ from, to })
+}
+
+pub(crate) fn clear_playlist(control: &Control) -> Result<Accepted, ApiError> {
+    send(control, ControlMessages::ClearPlaylist)
+}
+
+pub(crate) fn skip(control: &Control) -> Result<Accepted, ApiError> {
+    check_module(control)?;
+    send(control, ControlMessages::Skip)
+}
+
+/// Checks the playlist, as last published, takes another track.
+fn check_room(control: &Control) -> Result<(), ApiError> {
+    if control.playlist.get().len() >= PLAYLIST_SIZE {
+	return Err(ApiError::InvalidState("playlist full"));
+    }
+    Ok(())
+}
+
+/// Checks the playlist, as last published, has a track at `position`.
+fn check_position(control: &Control, position: usize) -> Result<(), ApiError> {
+    if position >= control.playlist.get().len() {
+	return Err(ApiError::InvalidState("no track at that position"));
+    }
+    Ok(())
+}
+
+/// Turns the volume one step up or down, `IncVol` or `DecVol`, within the
+/// cap.
+pub(crate) fn step_volume(control: &Control, step: ControlMessages) -> Result<Accepted, ApiError> {
+    check_module(control)?;
+    send(control, step)
+}
+
+pub(crate) fn set_volume(control: &Control, request: VolumeRequest) -> Result<Accepted, ApiError> {
+    let VolumeRequest{ volume, muted, max } = request;
+    if volume.is_none() && muted.is_none() && max.is_none() {
+	return Err(ApiError::BadRequest("nothing to change"));
+    }
+    if [volume, max].into_iter().flatten().any(|level| level > MAX_VOLUME) {
+	return Err(ApiError::BadRequest("volume out of range"));
+    }
+    check_module(control)?;
+    // in one message, for the player to take all of it or none
+    send(control, ControlMessages::AdjustVolume{ level: volume, muted, max })
+}
+
+pub(crate) fn access_point(control: &Control) -> AccessPointRequest {
+    let access_point = control.settings.get().access_point;
+    AccessPointRequest{
+	ssid: Some(access_point.ssid().into()),
+	password: None,
+	secured: !access_point.is_open(),
+	channel: Some(access_point.channel),
+	hidden: Some(access_point.hidden),
+	max_clients: Some(access_point.max_clients),
+    }
+}
+
+pub(crate) fn set_access_point(control: &Control, request: AccessPointRequest) -> Result<Accepted, ApiError> {
+    let AccessPointRequest{ ssid, password, channel, hidden, max_clients, .. } = request;
+    let mut access_point = control.settings.get().access_point;
+    if let Some(ssid) = ssid {
+	if ssid.is_empty() {
+	    return Err(ApiError::BadRequest("ssid missing"));
+	}
+	access_point.ssid = ssid;
+    }
+    access_point.password = password.unwrap_or(access_point.password);
+    access_point.channel = channel.unwrap_or(access_point.channel);
+    access_point.hidden = hidden.unwrap_or(access_point.hidden);
+    access_point.max_clients = max_clients.unwrap_or(access_point.max_clients);
+    // open only until the first time it is set up
+    if access_point.is_open() {
+	return Err(ApiError::BadRequest("password missing"));
+    }
+    access_point.check().map_err(ApiError::BadRequest)?;
+    log::info!("Access point now '{}' on channel {}", access_point.ssid(), access_point.channel);
+    control.settings.update_access_point(access_point);
+    Ok(Accepted)
+}
+
+/// Sets the admin password. The first one is only taken over the access
+/// point of the pesebre while it is still open, see [`crate::auth`].
+pub(crate) fn set_admin_password(
+    control: &Control,
+    interface: Interface,
+    credentials: Credentials,
+    request: PasswordRequest,
+) -> Result<Accepted, ApiError> {
+    let settings = control.settings.get();
+    if !settings.admin_password.is_empty() {
+	credentials.check(&settings.admin_password)?;
+    } else if interface != Interface::AccessPoint {
+	return Err(ApiError::InvalidState("admin password only set first on the access point"));
+    } else if !settings.access_point.is_open() {
+	return Err(ApiError::InvalidState("access point secured without an admin password"));
+    }
+    if !settings::is_passphrase(&request.password) {
+	return Err(ApiError::BadRequest("password must be 8 to 63 printable ASCII characters"));
+    }
+    log::info!("Admin password changed");
+    control.settings.update(|settings| settings.admin_password = request.password);
+    Ok(Accepted)
+}
+
+pub(crate) fn try_network(control: &Control, credentials: Credentials, candidate: Candidate) -> Result<Accepted, ApiError> {
+    credentials.check(&control.settings.get().admin_password)?;
+    if candidate.ssid.is_empty() {
+	return Err(ApiError::BadRequest("ssid missing"));
+    }
+    if !candidate.password.is_empty() && !settings::is_passphrase(&candidate.password) {
+	return Err(ApiError::BadRequest("password must be 8 to 63 printable ASCII characters"));
+    }
+    if control.provisioning.trial() == Trial::Testing {
+	return Err(ApiError::InvalidState("already trying a network"));
+    }
+    log::info!("Trying network '{}'", candidate.ssid);
+    control.provisioning.try_network(candidate);
+    Ok(Accepted)
+}
+
+/// What the REST interface reads with `GET` and changes with `PUT`.
+pub trait Resource {
+    /// As read, and as changed.
+    type Body: Serialize + DeserializeOwned;
+
+    /// Checks that `request` may read or change it.
+    fn authorize(&self, _request: &Request<'_>) -> Result<(), ApiError> {
+	Ok(())
+    }
+
+    fn get(&self) -> Self::Body;
+
+    fn put(&self, body: Self::Body) -> Result<Accepted, ApiError>;
+}
+
+/// Routes `GET` and `PUT` to a [`Resource`], the latter being a method the
+/// picoserve method router doesn't route.
+pub struct Rest<R>(pub R);
+
+impl<State, PathParameters, R: Resource> MethodHandler<State, PathParameters> for Rest<R> {
+    async fn call_method_handler<W: ResponseWriter>(
+	&self,
+	state: &State,
+	path_parameters: PathParameters,
+	request: Request<'_>,
+	response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+	if let Err(why) = self.0.authorize(&request) {
+	    return why.write_to(response_writer).await;
+	}
+	match request.method() {
+	    "GET" => Json(self.0.get()).write_to(response_writer).await,
+	    "PUT" => match JsonBody::<R::Body>::from_request(state, &request).await {
+		Ok(JsonBody(body)) => self.0.put(body).write_to(response_writer).await,
+		Err(why) => why.write_to(response_writer).await,
+	    },
+	    _ => MethodNotAllowed
+		.call_request_handler(state, path_parameters, request, response_writer)
+		.await,
+	}
+    }
+}
+
+/// `/api/v1/volume`
+pub struct Volume(pub &'static Control);
+
+impl Resource for Volume {
+    type Body = VolumeRequest;
+
+    fn get(&self) -> VolumeRequest {
+	let info = self.0.device.get();
+	VolumeRequest{ volume: Some(info.volume), muted: Some(info.muted), max: Some(info.max_volume) }
+    }
+
+    fn put(&self, body: VolumeRequest) -> Result<Accepted, ApiError> {
+	set_volume(self.0, body)
+    }
+}
+
+/// `/api/v1/settings/access-point`
+pub struct AccessPoint(pub &'static Control);
+
+impl Resource for AccessPoint {
+    type Body = AccessPointRequest;
+
+    fn authorize(&self, request: &Request<'_>) -> Result<(), ApiError> {
+	Credentials::of(request).check(&self.0.settings.get().admin_password)
+    }
+
+    fn get(&self) -> AccessPointRequest {
+	access_point(self.0)
+    }
+
+    fn put(&self, body: AccessPointRequest) -> Result<Accepted, ApiError> {
+	set_access_point(self.0, body)
+    }
+}
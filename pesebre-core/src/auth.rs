@@ -0,0 +1,121 @@
+//! HTTP Basic authentication of the settings, with the admin password. The
+//! user name isn't checked: there is only the one.
+//!
+//! Nothing is open until the password is set, but the settings page served
+//! on the access point of the pesebre, to set it, and only while the access
+//! point is still open. The household network can't claim the settings:
+//! nobody but whoever unboxed the pesebre joins its first-boot network.
+
+use heapless::String;
+use picoserve::extract::FromRequest;
+use picoserve::request::Request;
+
+use crate::api::ApiError;
+use crate::http::Interface;
+
+/// Realm the browser shows when asking for the password.
+pub const REALM : &str = "pesebre";
+
+/// Why the settings are closed before the admin password is set.
+pub const NO_PASSWORD : &str = "admin password not set";
+
+/// Password sent with a request, if any.
+pub struct Credentials(Option<String<64>>);
+
+impl Credentials {
+
+    /// Credentials in the `Authorization` header of `request`.
+    pub fn of(request: &Request<'_>) -> Self {
+	Self(request.headers().get("Authorization").and_then(basic_password))
+    }
+
+    /// Whether they open what `password` protects. Nothing does while the
+    /// password is empty.
+    pub fn check(&self, password: &str) -> Result<(), ApiError> {
+	match &self.0 {
+	    _ if password.is_empty() => Err(ApiError::InvalidState(NO_PASSWORD)),
+	    Some(given) if same(given, password) => Ok(()),
+	    _ => Err(ApiError::Unauthorized),
+	}
+    }
+
+    /// Whether they open a page of the settings served on `interface`.
+    /// Anything does on the access point while the password is empty, for
+    /// the page to set it.
+    pub fn check_page(&self, password: &str, interface: Interface) -> Result<(), ApiError> {
+	if password.is_empty() && interface == Interface::AccessPoint {
+	    Ok(())
+	} else {
+	    self.check(password)
+	}
+    }
+}
+
+/// Compares in a time that doesn't tell how much of `given` is right.
+fn same(given: &str, password: &str) -> bool {
+    let (given, password) = (given.as_bytes(), password.as_bytes());
+    let mut difference = given.len() ^ password.len();
+    for i in 0..given.len().max(password.len()) {
+	let (a, b) = (given.get(i).unwrap_or(&0), password.get(i).unwrap_or(&0));
+	difference |= usize::from(a ^ b);
+    }
+    difference == 0
+}
+
+impl<State> FromRequest<State> for Credentials {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request(_state: &State, request: &Request<'_>) -> Result<Self, Self::Rejection> {
+	Ok(Self::of(request))
+    }
+}
+
+/// Password of a `Basic` authorization header.
+fn basic_password(header: &str) -> Option<String<64>> {
+    let (scheme, encoded) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+	return None;
+    }
+    let mut decoded = [0; 128];
+    let len = decode_base64(encoded.trim(), &mut decoded)?;
+    let (_user, password) = core::str::from_utf8(&decoded[..len]).ok()?.split_once(':')?;
+    let mut text = String::new();
+    text.push_str(password).ok()?;
+    Some(text)
+}
+
+/// Decodes padded base64 into `out`, returning the length.
+fn decode_base64(input: &str, out: &mut [u8]) -> Option<usize> {
+    fn sextet(c: u8) -> Option<u32> {
+	Some(match c {
+	    b'A'..=b'Z' => c - b'A',
+	    b'a'..=b'z' => c - b'a' + 26,
+	    b'0'..=b'9' => c - b'0' + 52,
+	    b'+' => 62,
+	    b'/' => 63,
+	    _ => return None,
+	} as u32)
+    }
+
+    let input = input.as_bytes();
+    if input.len() % 4 != 0 {
+	return None;
+    }
+    let mut len = 0;
+    for (i, chunk) in input.chunks(4).enumerate() {
+	let last = (i + 1) * 4 == input.len();
+	let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+	if padding > 2 || (padding > 0 && !last) {
+	    return None;
+	}
+	let mut bits = 0;
+	for c in &chunk[..4 - padding] {
+	    bits = (bits << 6) | sextet(*c)?;
+	}
+	bits <<= 6 * padding;
+	let bytes = &bits.to_be_bytes()[1..4 - padding];
+	out.get_mut(len..len + bytes.len())?.copy_from_slice(bytes);
+	len += bytes.len();
+    }
+    Some(len)
+}
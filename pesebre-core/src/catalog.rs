@@ -0,0 +1,73 @@
+//! Songs, novenas and sounds stored in the SD card of the DFPlayer Mini.
+//!
+//! The table is generated from the `NNN Title.mp3` files in `musica/` and
+//! the lines of `musica/catalogo.txt`, the control panel is rendered from it
+//! and `POST /api/v1/play` only accepts ids found in it: adding a recording
+//! means dropping its file in `musica/`, or marking it in `catalogo.txt`
+//! when only the card holds it.
+
+use embassy_time::Duration;
+
+/// Kind of recording, the control panel groups tracks by it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Villancico,
+    Novena,
+    Story,
+    SoundEffect,
+    Jingle,
+    /// Played when the pesebre starts, not listed in the control panel.
+    Welcome,
+}
+
+impl Category {
+    /// Categories in the order the control panel lists them.
+    pub const MENU : [Category; 5] = [
+	Category::Novena,
+	Category::Story,
+	Category::SoundEffect,
+	Category::Villancico,
+	Category::Jingle,
+    ];
+
+    /// Heading of the category in the control panel.
+    pub fn title(&self) -> &'static str {
+	match self {
+	    Category::Villancico => "Villancicos",
+	    Category::Novena => "Novena de Aguinaldos",
+	    Category::Story => "Historias",
+	    Category::SoundEffect => "Sonidos del pesebre",
+	    Category::Jingle => "Jingles",
+	    Category::Welcome => "Bienvenida",
+	}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Track {
+    /// Number of the file in the card, the `NNN` of `NNN Title.mp3`.
+    pub id: u16,
+    pub title: &'static str,
+    pub category: Category,
+    /// Length of the recording, `None` when it isn't known.
+    pub duration: Option<Duration>,
+}
+
+/// Track played when the pesebre starts.
+pub const WELCOME : u16 = 37;
+
+// Every recording in the card, sorted by id:
+// `pub static TRACKS : [Track; N]`, generated by build.rs from musica/.
+include!(concat!(env!("OUT_DIR"), "/tracks.rs"));
+
+/// The track with the given id, if the card has it.
+pub fn find(id: u16) -> Option<&'static Track> {
+    TRACKS.binary_search_by_key(&id, |track| track.id)
+	.ok()
+	.map(|index| &TRACKS[index])
+}
+
+/// Tracks of the given category, sorted by id.
+pub fn by_category(category: Category) -> impl Iterator<Item = &'static Track> {
+    TRACKS.iter().filter(move |track| track.category == category)
+}
@@ -0,0 +1,142 @@
+//! What the Wi-Fi of the pesebre does: join the network of the household
+//! when one is saved, trying again a few times with growing pauses when it
+//! can't, and fall back to its own access point otherwise, from where it
+//! looks for the household network every now and then. Holding the button
+//! brings the access point up, or back down, by hand.
+
+use embassy_time::{Duration, Instant};
+
+/// Joins failed in a row before falling back to the access point.
+pub const MAX_JOIN_ATTEMPTS : u8 = 5;
+
+/** Pause after the first failed join, doubled after each one up to the last */
+pub const FIRST_BACKOFF : Duration = Duration::from_secs(2);
+pub const MAX_BACKOFF : Duration = Duration::from_secs(30);
+
+/// Time between looks for the household network while falling back.
+pub const FALLBACK_RETRY : Duration = Duration::from_secs(120);
+
+/// How the radio is set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A station of the household network.
+    Station,
+    /// The access point of the pesebre, with the station free to look
+    /// around.
+    AccessPoint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Joining the household network, for the `attempt`th time in a row.
+    Joining{ attempt: u8 },
+    /// On the household network.
+    Connected,
+    /// Waiting to join again `at`.
+    Backoff{ attempt: u8, at: Instant },
+    /// Access point up, looking for the household network again `retry`,
+    /// if ever: not without a network saved, nor when the button asked for
+    /// the access point.
+    Fallback{ retry: Option<Instant> },
+}
+
+/// Connectivity state machine. The firmware does what the state says and
+/// reports back how it went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connectivity {
+    state: State,
+    /// A household network is saved.
+    network: bool,
+}
+
+impl Connectivity {
+
+    /// Starts joining the household network, when there is one saved.
+    pub const fn new(network: bool) -> Self {
+	let state = if network { State::Joining{ attempt: 1 } } else { State::Fallback{ retry: None } };
+	Self{ state, network }
+    }
+
+    pub fn state(&self) -> State {
+	self.state
+    }
+
+    pub fn mode(&self) -> Mode {
+	match self.state {
+	    State::Fallback{ .. } => Mode::AccessPoint,
+	    _ => Mode::Station,
+	}
+    }
+
+    /// When something is due without anything happening.
+    pub fn deadline(&self) -> Option<Instant> {
+	match self.state {
+	    State::Backoff{ at, .. } => Some(at),
+	    State::Fallback{ retry } => retry,
+	    _ => None,
+	}
+    }
+
+    /// The join worked.
+    pub fn joined(&mut self) {
+	if let State::Joining{ .. } = self.state {
+	    self.state = State::Connected;
+	}
+    }
+
+    /// The join failed at `now`: try again later, or fall back once it
+    /// failed too many times.
+    pub fn join_failed(&mut self, now: Instant) {
+	if let State::Joining{ attempt } = self.state {
+	    self.state = if attempt >= MAX_JOIN_ATTEMPTS {
+		State::Fallback{ retry: Some(now + FALLBACK_RETRY) }
+	    } else {
+		State::Backoff{ attempt: attempt + 1, at: now + backoff(attempt) }
+	    };
+	}
+    }
+
+    /// The household network went away: join it again right away.
+    pub fn disconnected(&mut self) {
+	if self.state == State::Connected {
+	    self.state = State::Joining{ attempt: 1 };
+	}
+    }
+
+    /// Time passed up to `now`, with the household network `seen` around
+    /// by the last look while falling back.
+    pub fn tick(&mut self, now: Instant, seen: bool) {
+	match self.state {
+	    State::Backoff{ attempt, at } if at <= now => self.state = State::Joining{ attempt },
+	    State::Fallback{ retry: Some(retry) } if retry <= now => {
+		self.state = if seen {
+		    State::Joining{ attempt: 1 }
+		} else {
+		    State::Fallback{ retry: Some(now + FALLBACK_RETRY) }
+		};
+	    }
+	    _ => (),
+	}
+    }
+
+    /// The button was held: up with the access point, for good, or back to
+    /// the household network when it was up already.
+    pub fn button_held(&mut self) {
+	self.state = match self.state {
+	    State::Fallback{ .. } if self.network => State::Joining{ attempt: 1 },
+	    _ => State::Fallback{ retry: None },
+	};
+    }
+
+    /// Another household network was saved, or none when `network` is
+    /// false.
+    pub fn network_changed(&mut self, network: bool) {
+	*self = Self::new(network);
+    }
+}
+
+/// Pause after the `attempt`th failed join.
+pub fn backoff(attempt: u8) -> Duration {
+    let factor = 1_u64 << (attempt.max(1) - 1).min(16);
+    Duration::from_ticks(FIRST_BACKOFF.as_ticks().saturating_mul(factor)).min(MAX_BACKOFF)
+}
@@ -0,0 +1,82 @@
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::Channel,
+    pubsub::PubSubChannel,
+};
+
+use crate::catalog::Track;
+use crate::events::PanelEventChannel;
+use crate::player::StateView;
+use crate::playlist::PlaylistView;
+use crate::provisioning::Provisioning;
+use crate::settings::SettingsView;
+use crate::status::DeviceView;
+
+/// Number of requests from the control panel that can wait for the player.
+pub const CONTROL_QUEUE_SIZE : usize = 10;
+
+/// Requests from the control panel on their way to the player task.
+pub type ControlChannel = Channel<CriticalSectionRawMutex, ControlMessages, CONTROL_QUEUE_SIZE>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMessages {
+    /// Play a track of the catalog right away.
+    Play(&'static Track),
+    Pause,
+    Resume,
+    Stop,
+    IncVol,
+    DecVol,
+    /// Change any of the volume and its cap, from 0 to 30, and the mute at
+    /// once. Muting keeps the volume for later.
+    AdjustVolume { level: Option<u8>, muted: Option<bool>, max: Option<u8> },
+    /// Add a track at the end of the playlist.
+    Enqueue(&'static Track),
+    /// Add a track in front of the playlist.
+    PlayNext(&'static Track),
+    /// Take out the track at the position of the playlist.
+    Remove(usize),
+    /// Move a track of the playlist to another position.
+    Reorder { from: usize, to: usize },
+    ClearPlaylist,
+    /// Cut the current track and play the next of the playlist.
+    Skip,
+}
+
+/// What the web server shares with the player task.
+pub struct Control {
+    /// Requests on their way to the player task.
+    pub commands: ControlChannel,
+    /// Playlist as last published by the player task.
+    pub playlist: PlaylistView,
+    /// Player state as last published by the player task.
+    pub state: StateView,
+    /// Module and board information gathered by the firmware tasks.
+    pub device: DeviceView,
+    /// Changes streamed to the open pages.
+    pub events: PanelEventChannel,
+    /// Settings kept across reboots, saved by the firmware when changed.
+    pub settings: SettingsView,
+    /// Networks around and the one being tried, for the `/setup` page.
+    pub provisioning: Provisioning,
+}
+
+impl Control {
+    pub const fn new() -> Self {
+	Self{
+	    commands: Channel::new(),
+	    playlist: PlaylistView::new(),
+	    state: StateView::new(),
+	    device: DeviceView::new(),
+	    events: PubSubChannel::new(),
+	    settings: SettingsView::new(),
+	    provisioning: Provisioning::new(),
+	}
+    }
+}
+
+impl Default for Control {
+    fn default() -> Self {
+	Self::new()
+    }
+}
@@ -0,0 +1,135 @@
+use core::fmt;
+
+use embedded_io_async::ErrorKind;
+
+use super::FrameError;
+
+/// Error code the module sends back in a 0x40 frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleError {
+    /// 0x01: the module is still initializing.
+    Busy,
+    /// 0x02: the module is sleeping.
+    Sleeping,
+    /// 0x03: the module got an incomplete frame.
+    SerialError,
+    /// 0x04: the module computed a different checksum for our frame.
+    Checksum,
+    /// 0x05: the track number is outside the tracks on the media.
+    TrackOutOfScope,
+    /// 0x06: the track couldn't be found.
+    NotFound,
+    /// 0x07: an advertisement can only be inserted while a track plays.
+    InsertionError,
+    /// 0x08: reading the SD card failed.
+    SdCard,
+    /// 0x0A: the module entered sleep mode.
+    EnteredSleep,
+    Unknown(u16),
+}
+
+impl From<u16> for ModuleError {
+    fn from(code: u16) -> Self {
+	match code {
+	    0x01 => Self::Busy,
+	    0x02 => Self::Sleeping,
+	    0x03 => Self::SerialError,
+	    0x04 => Self::Checksum,
+	    0x05 => Self::TrackOutOfScope,
+	    0x06 => Self::NotFound,
+	    0x07 => Self::InsertionError,
+	    0x08 => Self::SdCard,
+	    0x0A => Self::EnteredSleep,
+	    code => Self::Unknown(code),
+	}
+    }
+}
+
+impl ModuleError {
+    /// Whether sending the same command again may succeed.
+    pub fn is_transient(&self) -> bool {
+	matches!(self, Self::Busy | Self::SerialError | Self::Checksum)
+    }
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Self::Busy => f.write_str("module busy initializing"),
+	    Self::Sleeping => f.write_str("module sleeping"),
+	    Self::SerialError => f.write_str("module received an incomplete frame"),
+	    Self::Checksum => f.write_str("module found a checksum mismatch"),
+	    Self::TrackOutOfScope => f.write_str("track out of scope"),
+	    Self::NotFound => f.write_str("track not found"),
+	    Self::InsertionError => f.write_str("advertisement insertion error"),
+	    Self::SdCard => f.write_str("SD card read error"),
+	    Self::EnteredSleep => f.write_str("module entered sleep"),
+	    Self::Unknown(code) => write!(f, "unknown module error {code:#x}"),
+	}
+    }
+}
+
+/// Argument rejected before anything was sent to the module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argument {
+    /// Playback source outside 1 ..= 5.
+    Source(u8),
+    /// Volume gain above 31.
+    Gain(u8),
+    /// Track numbers start at 1.
+    Track(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DfPlayerError {
+    /// Writing a frame to the transport failed.
+    Write(ErrorKind),
+    /// Flushing a frame to the transport failed.
+    Flush(ErrorKind),
+    InvalidArgument(Argument),
+    /// The module didn't answer in time.
+    Timeout,
+    /// A frame from the module had a wrong checksum.
+    Checksum,
+    /// A frame from the module wasn't closed by the end byte.
+    Framing,
+    /// The module never acknowledged the command, retries included.
+    NotAcknowledged { command: u8, attempts: u16 },
+    /// The module answered with something the driver can't make sense of.
+    UnexpectedReply { command: u8, param: u16 },
+    /// The module reported an error.
+    Module(ModuleError),
+}
+
+impl From<FrameError> for DfPlayerError {
+    fn from(why: FrameError) -> Self {
+	match why {
+	    FrameError::Checksum => Self::Checksum,
+	    FrameError::EndByte => Self::Framing,
+	}
+    }
+}
+
+impl From<ModuleError> for DfPlayerError {
+    fn from(why: ModuleError) -> Self {
+	Self::Module(why)
+    }
+}
+
+impl fmt::Display for DfPlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Self::Write(kind) => write!(f, "UART write failed: {kind:?}"),
+	    Self::Flush(kind) => write!(f, "UART flush failed: {kind:?}"),
+	    Self::InvalidArgument(Argument::Source(source)) => write!(f, "invalid playback source {source}, expected 1 to 5"),
+	    Self::InvalidArgument(Argument::Gain(gain)) => write!(f, "invalid volume gain {gain}, expected at most 31"),
+	    Self::InvalidArgument(Argument::Track(track)) => write!(f, "invalid track {track}, tracks start at 1"),
+	    Self::Timeout => f.write_str("MP3 module didn't answer in time"),
+	    Self::Checksum => f.write_str("checksum mismatch in MP3 module frame"),
+	    Self::Framing => f.write_str("MP3 module frame without end byte"),
+	    Self::NotAcknowledged{ command, attempts } => write!(f, "MP3 command {command:#x} not acknowledged after {attempts} attempts"),
+	    Self::UnexpectedReply{ command, param } => write!(f, "unexpected MP3 module reply {command:#x} with {param:#x}"),
+	    Self::Module(why) => write!(f, "MP3 module error: {why}"),
+	}
+    }
+}
@@ -0,0 +1,56 @@
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::Channel,
+};
+
+use super::{Device, ModuleError, Response};
+
+/// Number of notifications that can wait for the application before the
+/// reader starts dropping them.
+pub const EVENT_QUEUE_SIZE : usize = 8;
+
+/// Stream of notifications the module sends without being asked.
+pub type DfEventChannel = Channel<CriticalSectionRawMutex, DfEvent, EVENT_QUEUE_SIZE>;
+
+/// Notification the DFPlayer Mini sends on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DfEvent {
+    /// A track ended on the given device.
+    TrackFinished { device: Device, track: u16 },
+    /// A media got plugged in.
+    MediaInserted(Device),
+    /// A media got pulled out.
+    MediaRemoved(Device),
+    /// The module finished its initialization. The parameter is the bit
+    /// mask of the media found: 0x01 U disk, 0x02 TF card, 0x04 PC, 0x08 flash.
+    Online(u16),
+    /// The module reported an error.
+    Error(ModuleError),
+}
+
+impl Response {
+    /// The notification carried by the frame, if the frame is one.
+    pub fn event(&self) -> Option<DfEvent> {
+	match *self {
+	    Response::TrackFinished{ device, track } => Some(DfEvent::TrackFinished{ device, track }),
+	    Response::MediaInserted(mask) => Device::from_mask(mask).map(DfEvent::MediaInserted),
+	    Response::MediaRemoved(mask) => Device::from_mask(mask).map(DfEvent::MediaRemoved),
+	    Response::Online(mask) => Some(DfEvent::Online(mask)),
+	    Response::Error(code) => Some(DfEvent::Error(code)),
+	    _ => None,
+	}
+    }
+}
+
+/// Publishes the notification found in `response`, if any.
+///
+/// Meant to be called by the task reading the UART. It never waits for the
+/// application: when the channel is full the event is logged and dropped, so
+/// the UART keeps being drained.
+pub fn publish(events: &DfEventChannel, response: &Response) {
+    if let Some(event) = response.event() {
+	if events.try_send(event).is_err() {
+	    log::warn!("MP3 event queue full, dropping {event:?}");
+	}
+    }
+}
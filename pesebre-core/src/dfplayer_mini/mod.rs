@@ -0,0 +1,410 @@
+use embedded_io_async::{Error, Write};
+
+use embassy_time::Duration;
+
+mod error;
+mod events;
+mod query;
+mod reliable;
+mod response;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+
+pub use error::{Argument, DfPlayerError, ModuleError};
+pub use events::{publish, DfEvent, DfEventChannel, EVENT_QUEUE_SIZE};
+pub use query::{
+    route, DfReplyChannel, Equalizer, Playback, PlaybackMode, PlayerStatus, Volume,
+    DEFAULT_REPLY_TIMEOUT, REPLY_QUEUE_SIZE,
+};
+pub use reliable::ReliableConfig;
+pub use response::{Device, Frame, FrameDecoder, FrameError, Response, FRAME_SIZE};
+
+
+
+const SB : u8              = 0x7E; // start byte
+const VER : u8             = 0xFF; // version
+const LEN : u8             = 0x6;  // number of bytes after "LEN" (except for checksum data and EB)
+const FEEDBACK : u8        = 1;    // feedback requested
+#[allow(dead_code)]
+const NO_FEEDBACK : u8     = 0;    // no feedback requested
+const EB : u8              = 0xEF; // end byte
+
+/** Control Command Values */
+const  NEXT : u8            = 0x01;
+const  PREV : u8            = 0x02;
+const  PLAY : u8            = 0x03;
+const  INC_VOL : u8         = 0x04;
+const  DEC_VOL : u8         = 0x05;
+const  VOLUME : u8          = 0x06;
+const  EQ : u8              = 0x07;
+const  PLAYBACK_MODE : u8   = 0x08;
+const  PLAYBACK_SRC : u8    = 0x09;
+const  STANDBY : u8         = 0x0A;
+const  NORMAL : u8          = 0x0B;
+const  RESET : u8           = 0x0C;
+const  PLAYBACK : u8        = 0x0D;
+const  PAUSE : u8           = 0x0E;
+const  SPEC_FOLDER : u8     = 0x0F;
+const  VOL_ADJ : u8         = 0x10;
+const  REPEAT_PLAY : u8     = 0x11;
+const  USE_MP3_FOLDER : u8  = 0x12;
+const  INSERT_ADVERT : u8   = 0x13;
+const  SPEC_TRACK_3000 : u8 = 0x14;
+const  STOP_ADVERT : u8     = 0x15;
+const  STOP : u8            = 0x16;
+const  REPEAT_FOLDER : u8   = 0x17;
+const  RANDOM_ALL : u8      = 0x18;
+const  REPEAT_CURRENT : u8  = 0x19;
+const  SET_DAC : u8         = 0x1A;
+
+/** Query Command Values */
+const SEND_INIT :  u8        = 0x3F;
+const RETRANSMIT :  u8       = 0x40;
+const REPLY :  u8            = 0x41;
+const GET_STATUS_ :  u8      = 0x42;
+const GET_VOL :  u8          = 0x43;
+const GET_EQ :  u8           = 0x44;
+const GET_MODE :  u8         = 0x45;
+const GET_VERSION :  u8      = 0x46;
+const GET_TF_FILES :  u8     = 0x47;
+const GET_U_FILES :  u8      = 0x48;
+const GET_FLASH_FILES :  u8  = 0x49;
+#[allow(dead_code)]
+const KEEP_ON :  u8          = 0x4A;
+const GET_TF_TRACK :  u8     = 0x4B;
+const GET_U_TRACK :  u8      = 0x4C;
+const GET_FLASH_TRACK :  u8  = 0x4D;
+const GET_FOLDER_FILES :  u8 = 0x4E;
+const GET_FOLDERS :  u8      = 0x4F;
+
+/** EQ Values */
+const EQ_NORMAL : u8       = 0;
+const EQ_POP : u8          = 1;
+const EQ_ROCK : u8         = 2;
+const EQ_JAZZ : u8         = 3;
+const EQ_CLASSIC : u8      = 4;
+const EQ_BASE : u8         = 5;
+
+/** Mode Values */
+const REPEAT : u8          = 0;
+const FOLDER_REPEAT : u8   = 1;
+const SINGLE_REPEAT : u8   = 2;
+const RANDOM : u8          = 3;
+
+/** Playback Source Values */
+#[allow(dead_code)]
+const U : u8               = 1;
+const TF : u8              = 2;
+#[allow(dead_code)]
+const AUX : u8             = 3;
+const SLEEP : u8           = 4;
+#[allow(dead_code)]
+const FLASH : u8           = 5;
+
+/** Base Volume Adjust Value */
+const VOL_ADJUST : u8      = 0x10;
+
+/** Repeat Play Values */
+const STOP_REPEAT : u8     = 0;
+const START_REPEAT : u8    = 1;
+
+#[derive(Clone, Copy)]
+struct Message{
+    command_value: u8,
+    feedback_value: u8,
+    param_msb: u8,
+    param_lsb: u8,
+}
+
+impl Message{
+    fn build(command_value: u8, feedback_value:u8, param_msb: u8, param_lsb: u8) -> Self {
+	Self{
+	    command_value,
+	    feedback_value,
+	    param_msb,
+	    param_lsb,
+	}	
+    }
+
+    fn into_buffer(self) -> [u8; (LEN + 4) as usize] {
+
+	let (checksum_msb, checksum_lsb) = self.find_checksum();
+	
+	[
+	    SB,
+	    VER,
+	    LEN,
+	    self.command_value,
+	    self.feedback_value,
+	    self.param_msb,
+	    self.param_lsb,
+	    checksum_msb,
+	    checksum_lsb,
+	    EB,
+	]
+    }
+
+    fn find_checksum(&self) -> (u8,u8) {
+	let cs : u16 = 0_u16
+	    .overflowing_sub(VER as u16).0
+	    .overflowing_sub(LEN as u16).0
+	    .overflowing_sub(self.command_value as u16).0
+	    .overflowing_sub(self.feedback_value as u16).0
+	    .overflowing_sub(self.param_msb as u16).0
+	    .overflowing_sub(self.param_lsb as u16).0;
+	
+	let msv :u8 = (cs >> 8) as u8;
+	let lsv :u8 = (cs & 0xFF) as u8;
+	(msv,lsv)
+    }
+
+}
+
+
+/// Driver for the DFPlayer Mini (MP3-TF-16P) module.
+///
+/// The driver owns the transport used to talk to the module. Any
+/// `embedded_io_async::Write` implementation works, so the same driver can
+/// run over `UartTx` on the ESP32-C3 or over an in-memory buffer on a host.
+///
+/// Answers from the module are read elsewhere (the UART reader task) and
+/// handed to the driver through `replies`, see [`route`].
+///
+/// Every command borrows the driver mutably until it completes, acknowledge
+/// included in reliable mode, so whoever owns the driver can't get a second
+/// command interleaved with the first one. Tasks wanting to share it must go
+/// through the owner (the `writer` task) or a mutex.
+pub struct DfPlayer<'a, W> {
+    tx: W,
+    replies: &'a DfReplyChannel,
+    reply_timeout: Duration,
+    reliable: Option<ReliableConfig>,
+}
+
+impl<'a, W: Write> DfPlayer<'a, W> {
+
+    pub fn new(tx: W, replies: &'a DfReplyChannel) -> Self {
+	Self{
+	    tx,
+	    replies,
+	    reply_timeout: DEFAULT_REPLY_TIMEOUT,
+	    reliable: None,
+	}
+    }
+
+    /// Turns on waiting for the module to acknowledge every command, with
+    /// the given retry policy, or turns it off with `None`.
+    pub fn set_reliable(&mut self, config: Option<ReliableConfig>) {
+	self.reliable = config;
+    }
+
+    /// Sets how long a query waits for the module to answer.
+    pub fn set_reply_timeout(&mut self, timeout: Duration) {
+	self.reply_timeout = timeout;
+    }
+
+    /// Gives back the transport owned by the driver.
+    pub fn release(self) -> W {
+	self.tx
+    }
+
+    async fn send(&mut self, message: Message) -> Result<(), DfPlayerError> {
+	match self.reliable {
+	    Some(config) => self.send_reliable(config, message).await,
+	    None => self.write_frame(&message.into_buffer()).await,
+	}
+    }
+
+    async fn write_frame(&mut self, buff: &[u8; FRAME_SIZE]) -> Result<(), DfPlayerError> {
+	self.tx.write_all(buff).await.map_err(|why|{
+	    log::info!("Failed sending MP3 module the sequence {buff:x?}. Reason {why:?}");
+	    DfPlayerError::Write(why.kind())
+	})?;
+	self.tx.flush().await.map_err(|why|{
+	    log::info!("Failed flushing buffer when sending MP3 module the sequence {buff:x?}. Reason {why:?}");
+	    DfPlayerError::Flush(why.kind())
+	})?;
+
+	Ok(())
+    }
+
+    pub async fn play_next(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(NEXT, FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn play_previous(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(PREV, FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn play(&mut self, track_num: u16) -> Result<(), DfPlayerError> {
+	check_track(track_num)?;
+	self.send(Message::build(
+	    PLAY,
+	    FEEDBACK,
+	    ((track_num >> 8) & 0xFF) as u8,
+	    (track_num & 0xFF) as u8,
+	)).await
+    }
+
+    pub async fn stop(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(STOP, FEEDBACK, 0, 0)).await
+    }
+
+    pub async fn play_from_mp3_folder(&mut self, track_num: u16) -> Result<(), DfPlayerError> {
+	check_track(track_num)?;
+	self.send(Message::build(
+	    USE_MP3_FOLDER,
+	    FEEDBACK,
+	    ((track_num >> 8) & 0xFF) as u8,
+	    (track_num & 0xFF) as u8,
+	)).await
+    }
+
+    pub async fn play_advertisement(&mut self, track_num: u16) -> Result<(), DfPlayerError> {
+	check_track(track_num)?;
+	self.send(Message::build(
+	    INSERT_ADVERT,
+	    FEEDBACK,
+	    ((track_num >> 8) & 0xFF) as u8,
+	    (track_num & 0xFF) as u8,
+	)).await
+    }
+
+    pub async fn stop_advertisement(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(STOP_ADVERT, FEEDBACK, 0, 0)).await
+    }
+
+    pub async fn inc_volume(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(INC_VOL, FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn dec_volume(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(DEC_VOL, FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn volume(&mut self, volume: u8) -> Result<(), DfPlayerError> {
+	self.send(Message::build(VOLUME, FEEDBACK, 0, volume)).await
+    }
+
+    pub async fn eq_select(&mut self, setting: u8) -> Result<(), DfPlayerError> {
+	self.send(Message::build(EQ, FEEDBACK, 0, setting)).await
+    }
+
+    pub async fn r#loop(&mut self, track: u16) -> Result<(), DfPlayerError> {
+	self.send(Message::build(
+	    PLAYBACK_MODE,
+	    FEEDBACK,
+	    ((track >> 8) & 0xFF) as u8,
+	    (track & 0xFF) as u8,
+	)).await
+    }
+
+    pub async fn playback_source(&mut self, source: u8) -> Result<(), DfPlayerError> {
+	if (source > 0) && (source <= 5) {
+	    self.send(Message::build(PLAYBACK_SRC, FEEDBACK, 0, source)).await
+	} else {
+	    log::info!("incorrect source number {source}. The value should be between 1 and 5");
+	    Err(DfPlayerError::InvalidArgument(Argument::Source(source)))
+	}
+    }
+
+    pub async fn standby_mode(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(STANDBY, FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn normal_mode(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(NORMAL, FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn reset(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(RESET, FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn resume(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(PLAYBACK, NO_FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn pause(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(PAUSE, NO_FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn play_folder(&mut self, folder_num: u8, track_num: u8) -> Result<(), DfPlayerError> {
+	check_track(track_num as u16)?;
+	self.send(Message::build(SPEC_FOLDER, FEEDBACK, folder_num, track_num)).await
+    }
+
+    pub async fn play_large_folder(&mut self, folder_num: u8, track_num: u16) -> Result<(), DfPlayerError> {
+	check_track(track_num)?;
+	let arg: u16 = ((folder_num as u16) << 12) | (track_num & 0xfff);
+
+	self.send(Message::build(
+	    SPEC_TRACK_3000,
+	    FEEDBACK,
+	    (arg >> 8) as u8,
+	    (arg & 0xff) as u8,
+	)).await
+    }
+
+    pub async fn volume_adjust_set(&mut self, gain: u8) -> Result<(), DfPlayerError> {
+	if gain <= 31 {
+	    self.send(Message::build(VOL_ADJ, FEEDBACK, 0, VOL_ADJUST + gain)).await
+	} else {
+	    log::info!("volume gaim must be specified lower or equal to 31");
+	    Err(DfPlayerError::InvalidArgument(Argument::Gain(gain)))
+	}
+    }
+
+    pub async fn start_repeat_play(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(REPEAT_PLAY, FEEDBACK, 0, START_REPEAT)).await
+    }
+
+    pub async fn stop_repeat_play(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(REPEAT_PLAY, FEEDBACK, 0, STOP_REPEAT)).await
+    }
+
+    pub async fn repeat_folder(&mut self, folder: u16) -> Result<(), DfPlayerError> {
+	self.send(Message::build(
+	    REPEAT_FOLDER,
+	    FEEDBACK,
+	    ((folder >> 8) & 0xFF) as u8,
+	    (folder & 0xFF) as u8,
+	)).await
+    }
+
+    pub async fn random_all(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(RANDOM_ALL, FEEDBACK, 0, 0)).await
+    }
+
+    pub async fn start_repeat(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(REPEAT_CURRENT, FEEDBACK, 0, 0)).await
+    }
+
+    pub async fn stop_repeat(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(REPEAT_CURRENT, FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn start_dac(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(SET_DAC, FEEDBACK, 0, 0)).await
+    }
+
+    pub async fn stop_dac(&mut self) -> Result<(), DfPlayerError> {
+	self.send(Message::build(SET_DAC, FEEDBACK, 0, 1)).await
+    }
+
+    pub async fn sleep(&mut self) -> Result<(), DfPlayerError> {
+	self.playback_source(SLEEP).await
+    }
+
+    pub async fn wake_up(&mut self) -> Result<(), DfPlayerError> {
+	self.playback_source(TF).await
+    }
+}
+
+fn check_track(track_num: u16) -> Result<(), DfPlayerError> {
+    if track_num == 0 {
+	log::info!("incorrect track number 0. Tracks start at 1");
+	Err(DfPlayerError::InvalidArgument(Argument::Track(track_num)))
+    } else {
+	Ok(())
+    }
+}
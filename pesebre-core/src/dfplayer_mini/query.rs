@@ -0,0 +1,273 @@
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::Channel,
+};
+use embassy_time::{with_timeout, Duration};
+use embedded_io_async::Write;
+
+use super::{
+    publish, DfEventChannel, DfPlayer, DfPlayerError, Device, Message, Response,
+    NO_FEEDBACK,
+    GET_STATUS_, GET_VOL, GET_EQ, GET_MODE, GET_VERSION,
+    GET_TF_FILES, GET_U_FILES, GET_FLASH_FILES,
+    GET_TF_TRACK, GET_U_TRACK, GET_FLASH_TRACK,
+    GET_FOLDER_FILES, GET_FOLDERS,
+    EQ_NORMAL, EQ_POP, EQ_ROCK, EQ_JAZZ, EQ_CLASSIC, EQ_BASE,
+    REPEAT, FOLDER_REPEAT, SINGLE_REPEAT, RANDOM,
+};
+
+/// Number of answers that can wait for the driver before the reader starts
+/// dropping them.
+pub const REPLY_QUEUE_SIZE : usize = 4;
+
+/// How long a query waits for its answer unless told otherwise.
+pub const DEFAULT_REPLY_TIMEOUT : Duration = Duration::from_millis(500);
+
+/// Answers from the module on their way from the UART reader to the driver.
+pub type DfReplyChannel = Channel<CriticalSectionRawMutex, Response, REPLY_QUEUE_SIZE>;
+
+/// Hands a decoded frame to whoever is waiting for it.
+///
+/// Meant to be called by the task reading the UART for every frame. Answers
+/// go to the driver through `replies`, notifications go to the application
+/// through `events`; error frames go to both, as the module uses them for
+/// rejected commands and for problems found on its own. It never waits:
+/// stale answers nobody asked for are dropped when the queue is full.
+pub fn route(replies: &DfReplyChannel, events: &DfEventChannel, response: Response) {
+    publish(events, &response);
+
+    if response.is_reply() && replies.try_send(response).is_err() {
+	log::debug!("MP3 reply queue full, dropping {response:?}");
+    }
+}
+
+impl Response {
+    /// Whether the frame answers something the driver sent.
+    pub fn is_reply(&self) -> bool {
+	!matches!(
+	    self,
+	    Response::Online(_)
+		| Response::MediaInserted(_)
+		| Response::MediaRemoved(_)
+		| Response::TrackFinished{ .. }
+	)
+    }
+}
+
+/// What the module is doing right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Playback {
+    Stopped,
+    Playing,
+    Paused,
+    Other(u8),
+}
+
+/// Answer to the status query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerStatus {
+    /// Device the module plays from, `None` while sleeping.
+    pub device: Option<Device>,
+    pub playback: Playback,
+}
+
+impl From<u16> for PlayerStatus {
+    fn from(param: u16) -> Self {
+	let playback = match (param & 0xFF) as u8 {
+	    0 => Playback::Stopped,
+	    1 => Playback::Playing,
+	    2 => Playback::Paused,
+	    other => Playback::Other(other),
+	};
+
+	Self{
+	    device: Device::from_mask(param >> 8),
+	    playback,
+	}
+    }
+}
+
+/// Volume level as reported by the module, from 0 to 30.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Volume(pub u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Equalizer {
+    Normal,
+    Pop,
+    Rock,
+    Jazz,
+    Classic,
+    Bass,
+}
+
+impl TryFrom<u8> for Equalizer {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+	match v {
+	    EQ_NORMAL => Ok(Self::Normal),
+	    EQ_POP => Ok(Self::Pop),
+	    EQ_ROCK => Ok(Self::Rock),
+	    EQ_JAZZ => Ok(Self::Jazz),
+	    EQ_CLASSIC => Ok(Self::Classic),
+	    EQ_BASE => Ok(Self::Bass),
+	    _ => Err(()),
+	}
+    }
+}
+
+impl From<Equalizer> for u8 {
+    fn from(eq: Equalizer) -> u8 {
+	match eq {
+	    Equalizer::Normal => EQ_NORMAL,
+	    Equalizer::Pop => EQ_POP,
+	    Equalizer::Rock => EQ_ROCK,
+	    Equalizer::Jazz => EQ_JAZZ,
+	    Equalizer::Classic => EQ_CLASSIC,
+	    Equalizer::Bass => EQ_BASE,
+	}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackMode {
+    Repeat,
+    FolderRepeat,
+    SingleRepeat,
+    Random,
+}
+
+impl TryFrom<u8> for PlaybackMode {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+	match v {
+	    REPEAT => Ok(Self::Repeat),
+	    FOLDER_REPEAT => Ok(Self::FolderRepeat),
+	    SINGLE_REPEAT => Ok(Self::SingleRepeat),
+	    RANDOM => Ok(Self::Random),
+	    _ => Err(()),
+	}
+    }
+}
+
+impl<'a, W: Write> DfPlayer<'a, W> {
+
+    /// Sends the query and waits for the answer `expected` picks the
+    /// parameter from.
+    async fn query(
+	&mut self,
+	command_value: u8,
+	param: u16,
+	expected: fn(Response) -> Option<u16>,
+    ) -> Result<u16, DfPlayerError> {
+	self.discard_replies();
+
+	self.write_frame(&Message::build(
+	    command_value,
+	    NO_FEEDBACK,
+	    ((param >> 8) & 0xFF) as u8,
+	    (param & 0xFF) as u8,
+	).into_buffer()).await?;
+
+	self.wait_reply(command_value, self.reply_timeout, expected).await
+    }
+
+    /// Drops queued answers, they belong to older requests.
+    pub(super) fn discard_replies(&mut self) {
+	while self.replies.try_receive().is_ok() {}
+    }
+
+    /// Waits up to `timeout` for the answer `expected` picks the parameter
+    /// from. An error frame from the module ends the wait.
+    pub(super) async fn wait_reply(
+	&self,
+	command_value: u8,
+	timeout: Duration,
+	expected: fn(Response) -> Option<u16>,
+    ) -> Result<u16, DfPlayerError> {
+	let replies = self.replies;
+	let answer = async {
+	    loop {
+		let response = replies.receive().await;
+		if let Some(value) = expected(response) {
+		    return Ok(value);
+		}
+		if let Response::Error(why) = response {
+		    log::info!("MP3 module rejected {command_value:#x}: {why}");
+		    return Err(DfPlayerError::Module(why));
+		}
+		log::debug!("Ignoring MP3 module answer {response:?} while waiting for {command_value:#x}");
+	    }
+	};
+
+	with_timeout(timeout, answer).await.map_err(|_| {
+	    log::info!("MP3 module didn't answer {command_value:#x} in {} ms", timeout.as_millis());
+	    DfPlayerError::Timeout
+	})?
+    }
+
+    pub async fn query_status(&mut self) -> Result<PlayerStatus, DfPlayerError> {
+	self.query(GET_STATUS_, 0, |r| match r { Response::Status(v) => Some(v), _ => None })
+	    .await
+	    .map(PlayerStatus::from)
+    }
+
+    pub async fn query_volume(&mut self) -> Result<Volume, DfPlayerError> {
+	self.query(GET_VOL, 0, |r| match r { Response::Volume(v) => Some(v), _ => None })
+	    .await
+	    .map(|v| Volume(v as u8))
+    }
+
+    pub async fn query_eq(&mut self) -> Result<Equalizer, DfPlayerError> {
+	let eq = self.query(GET_EQ, 0, |r| match r { Response::Equalizer(v) => Some(v), _ => None }).await?;
+	Equalizer::try_from(eq as u8).map_err(|_| {
+	    DfPlayerError::UnexpectedReply{ command: GET_EQ, param: eq }
+	})
+    }
+
+    pub async fn query_playback_mode(&mut self) -> Result<PlaybackMode, DfPlayerError> {
+	let mode = self.query(GET_MODE, 0, |r| match r { Response::PlaybackMode(v) => Some(v), _ => None }).await?;
+	PlaybackMode::try_from(mode as u8).map_err(|_| {
+	    DfPlayerError::UnexpectedReply{ command: GET_MODE, param: mode }
+	})
+    }
+
+    pub async fn query_version(&mut self) -> Result<u16, DfPlayerError> {
+	self.query(GET_VERSION, 0, |r| match r { Response::Version(v) => Some(v), _ => None }).await
+    }
+
+    pub async fn query_tf_files(&mut self) -> Result<u16, DfPlayerError> {
+	self.query(GET_TF_FILES, 0, |r| match r { Response::TfFiles(v) => Some(v), _ => None }).await
+    }
+
+    pub async fn query_u_files(&mut self) -> Result<u16, DfPlayerError> {
+	self.query(GET_U_FILES, 0, |r| match r { Response::UFiles(v) => Some(v), _ => None }).await
+    }
+
+    pub async fn query_flash_files(&mut self) -> Result<u16, DfPlayerError> {
+	self.query(GET_FLASH_FILES, 0, |r| match r { Response::FlashFiles(v) => Some(v), _ => None }).await
+    }
+
+    pub async fn query_folder_files(&mut self, folder: u8) -> Result<u16, DfPlayerError> {
+	self.query(GET_FOLDER_FILES, folder as u16, |r| match r { Response::FolderFiles(v) => Some(v), _ => None }).await
+    }
+
+    pub async fn query_folders(&mut self) -> Result<u16, DfPlayerError> {
+	self.query(GET_FOLDERS, 0, |r| match r { Response::Folders(v) => Some(v), _ => None }).await
+    }
+
+    pub async fn query_tf_track(&mut self) -> Result<u16, DfPlayerError> {
+	self.query(GET_TF_TRACK, 0, |r| match r { Response::TfTrack(v) => Some(v), _ => None }).await
+    }
+
+    pub async fn query_u_track(&mut self) -> Result<u16, DfPlayerError> {
+	self.query(GET_U_TRACK, 0, |r| match r { Response::UTrack(v) => Some(v), _ => None }).await
+    }
+
+    pub async fn query_flash_track(&mut self) -> Result<u16, DfPlayerError> {
+	self.query(GET_FLASH_TRACK, 0, |r| match r { Response::FlashTrack(v) => Some(v), _ => None }).await
+    }
+}
@@ -0,0 +1,63 @@
+use embassy_time::{Duration, Timer};
+use embedded_io_async::Write;
+
+use super::{DfPlayer, DfPlayerError, Message, Response, FEEDBACK};
+
+/// Retry policy for commands sent in reliable mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReliableConfig {
+    /// How long to wait for the 0x41 acknowledge of each attempt.
+    pub ack_timeout: Duration,
+    /// Attempts made after the first one before giving up.
+    pub retries: u8,
+    /// Pause before the first retry, doubled before every following one.
+    pub backoff: Duration,
+}
+
+impl Default for ReliableConfig {
+    fn default() -> Self {
+	Self{
+	    ack_timeout: Duration::from_millis(200),
+	    retries: 3,
+	    backoff: Duration::from_millis(50),
+	}
+    }
+}
+
+impl<'a, W: Write> DfPlayer<'a, W> {
+
+    /// Sends the command asking for feedback and waits for the acknowledge,
+    /// retrying as `config` says.
+    ///
+    /// Module errors that would happen again, like a missing track, are
+    /// returned right away; a busy module, a mangled frame or a missing
+    /// acknowledge are retried.
+    pub(super) async fn send_reliable(&mut self, config: ReliableConfig, mut message: Message) -> Result<(), DfPlayerError> {
+	message.feedback_value = FEEDBACK;
+	let command_value = message.command_value;
+	let buff = message.into_buffer();
+	let mut backoff = config.backoff;
+
+	for attempt in 0..=config.retries {
+	    if attempt > 0 {
+		log::info!("Retrying MP3 command {command_value:#x} in {} ms, attempt {}", backoff.as_millis(), attempt + 1);
+		Timer::after(backoff).await;
+		backoff *= 2;
+	    }
+
+	    self.discard_replies();
+	    self.write_frame(&buff).await?;
+
+	    match self.wait_reply(command_value, config.ack_timeout, |r| match r { Response::Ack => Some(0), _ => None }).await {
+		Ok(_) => return Ok(()),
+		Err(DfPlayerError::Module(why)) if !why.is_transient() => return Err(DfPlayerError::Module(why)),
+		Err(why) => log::warn!("MP3 command {command_value:#x} not acknowledged: {why}"),
+	    }
+	}
+
+	Err(DfPlayerError::NotAcknowledged{
+	    command: command_value,
+	    attempts: config.retries as u16 + 1,
+	})
+    }
+}
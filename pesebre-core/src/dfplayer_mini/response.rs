@@ -0,0 +1,227 @@
+use super::{
+    Message, ModuleError,
+    SB, VER, LEN, EB,
+    SEND_INIT, RETRANSMIT, REPLY,
+    GET_STATUS_, GET_VOL, GET_EQ, GET_MODE, GET_VERSION,
+    GET_TF_FILES, GET_U_FILES, GET_FLASH_FILES,
+    GET_TF_TRACK, GET_U_TRACK, GET_FLASH_TRACK,
+    GET_FOLDER_FILES, GET_FOLDERS,
+};
+
+/** Notification Command Values */
+pub(super) const U_INSERTED : u8      = 0x3A;
+pub(super) const U_REMOVED : u8       = 0x3B;
+pub(super) const U_FINISHED : u8      = 0x3C;
+pub(super) const TF_FINISHED : u8     = 0x3D;
+pub(super) const FLASH_FINISHED : u8  = 0x3E;
+
+/// Size of a complete frame on the wire, start and end bytes included.
+pub const FRAME_SIZE : usize = (LEN + 4) as usize;
+
+/// Storage the module plays from or reports about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Device {
+    U,
+    Tf,
+    Pc,
+    Flash,
+}
+
+impl Device {
+    /// Decodes the device bit mask used by the insert and remove notices.
+    pub fn from_mask(mask: u16) -> Option<Self> {
+	match mask {
+	    0x01 => Some(Self::U),
+	    0x02 => Some(Self::Tf),
+	    0x04 => Some(Self::Pc),
+	    0x08 => Some(Self::Flash),
+	    _ => None,
+	}
+    }
+}
+
+/// Decoded frame sent by the DFPlayer Mini, either as answer to a command or
+/// on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    /// 0x41: the module accepted the last command.
+    Ack,
+    /// 0x40: the module rejected the last command or hit a problem.
+    Error(ModuleError),
+    /// 0x3F: the module finished its initialization. The parameter is the
+    /// bit mask of the available media.
+    Online(u16),
+    /// 0x3A: a media got inserted, with the bit mask of the device.
+    MediaInserted(u16),
+    /// 0x3B: a media got removed, with the bit mask of the device.
+    MediaRemoved(u16),
+    /// 0x3C, 0x3D, 0x3E: the track finished playing.
+    TrackFinished { device: Device, track: u16 },
+    Status(u16),
+    Volume(u16),
+    Equalizer(u16),
+    PlaybackMode(u16),
+    Version(u16),
+    TfFiles(u16),
+    UFiles(u16),
+    FlashFiles(u16),
+    TfTrack(u16),
+    UTrack(u16),
+    FlashTrack(u16),
+    FolderFiles(u16),
+    Folders(u16),
+    /// Frame well formed but with a command this driver doesn't know.
+    Unknown { command: u8, param: u16 },
+}
+
+impl Response {
+    fn from_frame(command: u8, param: u16) -> Self {
+	match command {
+	    REPLY => Self::Ack,
+	    RETRANSMIT => Self::Error(ModuleError::from(param)),
+	    SEND_INIT => Self::Online(param),
+	    U_INSERTED => Self::MediaInserted(param),
+	    U_REMOVED => Self::MediaRemoved(param),
+	    U_FINISHED => Self::TrackFinished{ device: Device::U, track: param },
+	    TF_FINISHED => Self::TrackFinished{ device: Device::Tf, track: param },
+	    FLASH_FINISHED => Self::TrackFinished{ device: Device::Flash, track: param },
+	    GET_STATUS_ => Self::Status(param),
+	    GET_VOL => Self::Volume(param),
+	    GET_EQ => Self::Equalizer(param),
+	    GET_MODE => Self::PlaybackMode(param),
+	    GET_VERSION => Self::Version(param),
+	    GET_TF_FILES => Self::TfFiles(param),
+	    GET_U_FILES => Self::UFiles(param),
+	    GET_FLASH_FILES => Self::FlashFiles(param),
+	    GET_TF_TRACK => Self::TfTrack(param),
+	    GET_U_TRACK => Self::UTrack(param),
+	    GET_FLASH_TRACK => Self::FlashTrack(param),
+	    GET_FOLDER_FILES => Self::FolderFiles(param),
+	    GET_FOLDERS => Self::Folders(param),
+	    command => Self::Unknown{ command, param },
+	}
+    }
+}
+
+/// Reasons a frame that started fine had to be dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The checksum in the frame doesn't match its content.
+    Checksum,
+    /// The frame wasn't closed by the 0xEF end byte.
+    EndByte,
+}
+
+/// Frame as found on the wire, whoever sent it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub command: u8,
+    pub feedback: bool,
+    pub param: u16,
+}
+
+/// Streaming decoder for the frames the DFPlayer Mini sends over the UART.
+///
+/// Bytes can be pushed as they arrive, no matter how the UART reads split
+/// them: a frame may come in several reads, and a read may carry several
+/// frames. Garbage before a start byte is skipped, and on a broken frame the
+/// decoder resyncs on the next 0x7E it has already buffered.
+pub struct FrameDecoder {
+    buf: [u8; FRAME_SIZE],
+    len: usize,
+}
+
+impl FrameDecoder {
+
+    pub const fn new() -> Self {
+	Self{
+	    buf: [0u8; FRAME_SIZE],
+	    len: 0,
+	}
+    }
+
+    /// Feeds one byte, returning the frame it completed, if any.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Response, FrameError>> {
+	self.push_frame(byte)
+	    .map(|frame| frame.map(|frame| Response::from_frame(frame.command, frame.param)))
+    }
+
+    /// Same as [`FrameDecoder::push`] but without interpreting the frame, for
+    /// when the bytes come from the host side.
+    pub fn push_frame(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+	self.buf[self.len] = byte;
+	self.len += 1;
+
+	loop {
+	    if self.len == 0 {
+		return None;
+	    }
+
+	    // a header that is not a frame header means we are out of sync
+	    let in_sync = self.buf[0] == SB
+		&& (self.len < 2 || self.buf[1] == VER)
+		&& (self.len < 3 || self.buf[2] == LEN);
+
+	    if !in_sync {
+		self.resync();
+		continue;
+	    }
+
+	    if self.len < FRAME_SIZE {
+		return None;
+	    }
+
+	    let result = self.check_frame();
+	    if result.is_ok() {
+		self.len = 0;
+	    } else {
+		self.resync();
+	    }
+	    return Some(result);
+	}
+    }
+
+    /// Feeds a chunk of bytes as read from the UART, yielding every frame
+    /// completed by it.
+    pub fn decode<'a>(&'a mut self, data: &'a [u8]) -> impl Iterator<Item = Result<Response, FrameError>> + 'a {
+	data.iter().filter_map(move |byte| self.push(*byte))
+    }
+
+    fn check_frame(&self) -> Result<Frame, FrameError> {
+	let frame = &self.buf;
+
+	if frame[9] != EB {
+	    return Err(FrameError::EndByte);
+	}
+
+	let message = Message::build(frame[3], frame[4], frame[5], frame[6]);
+	if message.find_checksum() != (frame[7], frame[8]) {
+	    return Err(FrameError::Checksum);
+	}
+
+	Ok(Frame{
+	    command: frame[3],
+	    feedback: frame[4] != 0,
+	    param: ((frame[5] as u16) << 8) | frame[6] as u16,
+	})
+    }
+
+    /// Drops the current start byte and moves to the next buffered one.
+    fn resync(&mut self) {
+	let next_start = self.buf[1..self.len]
+	    .iter()
+	    .position(|b| *b == SB)
+	    .map(|p| p + 1)
+	    .unwrap_or(self.len);
+
+	self.buf.copy_within(next_start..self.len, 0);
+	self.len -= next_start;
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+	Self::new()
+    }
+}
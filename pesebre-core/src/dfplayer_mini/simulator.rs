@@ -0,0 +1,482 @@
+//! Software model of the DFPlayer Mini, for exercising the driver and the
+//! application without the MP3-TF-16P board.
+//!
+//! The simulator speaks the same serial protocol as the module: the driver
+//! writes frames into it, and the answers come out of its reader side byte
+//! by byte, ready for the [`FrameDecoder`]. Time only moves when the test says
+//! so through [`Simulator::advance`].
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::Duration;
+use heapless::Deque;
+
+use super::response::{TF_FINISHED, U_INSERTED, U_REMOVED};
+use super::{
+    Frame, FrameDecoder, FrameError, Message, Playback,
+    FRAME_SIZE, NO_FEEDBACK,
+    NEXT, PREV, PLAY, INC_VOL, DEC_VOL, VOLUME, EQ, PLAYBACK_MODE, PLAYBACK_SRC,
+    STANDBY, NORMAL, RESET, PLAYBACK, PAUSE, SPEC_FOLDER, VOL_ADJ, REPEAT_PLAY,
+    USE_MP3_FOLDER, INSERT_ADVERT, SPEC_TRACK_3000, STOP_ADVERT, STOP,
+    REPEAT_FOLDER, RANDOM_ALL, REPEAT_CURRENT, SET_DAC,
+    SEND_INIT, RETRANSMIT, REPLY, KEEP_ON,
+    GET_STATUS_, GET_VOL, GET_EQ, GET_MODE, GET_VERSION,
+    GET_TF_FILES, GET_U_FILES, GET_FLASH_FILES,
+    GET_TF_TRACK, GET_U_TRACK, GET_FLASH_TRACK,
+    GET_FOLDER_FILES, GET_FOLDERS,
+    EQ_NORMAL, REPEAT, FOLDER_REPEAT, SINGLE_REPEAT, RANDOM,
+    TF, SLEEP,
+};
+
+/// Firmware version the simulated module reports.
+pub const SIMULATED_VERSION : u16 = 8;
+
+/// Answer frames that can wait to be read before the simulator drops them.
+const OUTPUT_FRAMES : usize = 32;
+
+/// Device bit mask of the TF card, the only media simulated.
+const TF_MASK : u16 = 0x02;
+
+/** Error codes sent in 0x40 frames */
+const ERR_SLEEPING : u16      = 0x02;
+const ERR_SERIAL : u16        = 0x03;
+const ERR_CHECKSUM : u16      = 0x04;
+const ERR_NOT_FOUND : u16     = 0x06;
+const ERR_INSERTION : u16     = 0x07;
+const ERR_SD_CARD : u16       = 0x08;
+
+const MAX_VOLUME : u8 = 30;
+
+/// What the simulated TF card holds.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout<'a> {
+    /// Length of the tracks in the root folder, track N at index N - 1.
+    pub tracks: &'a [Duration],
+    /// Number of files in folders 01, 02, ...
+    pub folders: &'a [u16],
+    /// Length of every track inside a numbered folder.
+    pub folder_track_length: Duration,
+}
+
+/// Track the simulated module is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Track {
+    Root(u16),
+    Folder { folder: u8, track: u16 },
+}
+
+impl Track {
+    fn number(&self) -> u16 {
+	match *self {
+	    Track::Root(track) => track,
+	    Track::Folder{ track, .. } => track,
+	}
+    }
+}
+
+/// Snapshot of the simulated module state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimState {
+    pub track: Option<Track>,
+    pub playback: Playback,
+    /// Time played of the current track.
+    pub elapsed: Duration,
+    pub volume: u8,
+    pub eq: u8,
+    pub mode: u8,
+    pub source: u8,
+    pub sleeping: bool,
+    pub card_present: bool,
+}
+
+impl SimState {
+    const fn power_on() -> Self {
+	Self{
+	    track: None,
+	    playback: Playback::Stopped,
+	    elapsed: Duration::from_ticks(0),
+	    volume: MAX_VOLUME,
+	    eq: EQ_NORMAL,
+	    mode: REPEAT,
+	    source: TF,
+	    sleeping: false,
+	    card_present: true,
+	}
+    }
+}
+
+struct Model<'a> {
+    layout: Layout<'a>,
+    state: SimState,
+    decoder: FrameDecoder,
+    output: Deque<u8, { OUTPUT_FRAMES * FRAME_SIZE }>,
+    acks_to_drop: u8,
+}
+
+/// Simulated DFPlayer Mini.
+///
+/// The driver writes into `&Simulator` and the UART reader reads from
+/// `&Simulator`, both through the `embedded_io_async` traits, so the same
+/// code paths as on the board are exercised.
+pub struct Simulator<'a> {
+    model: Mutex<CriticalSectionRawMutex, RefCell<Model<'a>>>,
+    output_ready: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'a> Simulator<'a> {
+
+    pub fn new(layout: Layout<'a>) -> Self {
+	Self{
+	    model: Mutex::new(RefCell::new(Model{
+		layout,
+		state: SimState::power_on(),
+		decoder: FrameDecoder::new(),
+		output: Deque::new(),
+		acks_to_drop: 0,
+	    })),
+	    output_ready: Signal::new(),
+	}
+    }
+
+    fn with_model<R>(&self, f: impl FnOnce(&mut Model<'a>) -> R) -> R {
+	let result = self.model.lock(|model| f(&mut model.borrow_mut()));
+	self.output_ready.signal(());
+	result
+    }
+
+    /// Current state of the simulated module.
+    pub fn state(&self) -> SimState {
+	self.model.lock(|model| model.borrow().state)
+    }
+
+    /// Sends the notice the module sends once it finished booting.
+    pub fn power_on(&self) {
+	self.with_model(|model| model.emit(SEND_INIT, TF_MASK));
+    }
+
+    /// Lets `elapsed` time go by, finishing the current track if it ends in
+    /// that time.
+    pub fn advance(&self, elapsed: Duration) {
+	self.with_model(|model| model.advance(elapsed));
+    }
+
+    /// Pulls the TF card out.
+    pub fn remove_card(&self) {
+	self.with_model(|model| {
+	    model.state.card_present = false;
+	    model.state.track = None;
+	    model.state.playback = Playback::Stopped;
+	    model.emit(U_REMOVED, TF_MASK);
+	});
+    }
+
+    /// Puts the TF card back.
+    pub fn insert_card(&self) {
+	self.with_model(|model| {
+	    model.state.card_present = true;
+	    model.emit(U_INSERTED, TF_MASK);
+	});
+    }
+
+    /// Makes the module swallow the acknowledge of the next `count` commands,
+    /// as if they got lost on the line.
+    pub fn drop_acks(&self, count: u8) {
+	self.with_model(|model| model.acks_to_drop = count);
+    }
+}
+
+impl<'a> Model<'a> {
+
+    fn emit(&mut self, command: u8, param: u16) {
+	let buff = Message::build(command, NO_FEEDBACK, (param >> 8) as u8, (param & 0xFF) as u8).into_buffer();
+	if self.output.capacity() - self.output.len() < buff.len() {
+	    log::warn!("Simulated MP3 module output full, dropping {command:#x}");
+	    return;
+	}
+	for byte in buff {
+	    let _ = self.output.push_back(byte);
+	}
+    }
+
+    fn receive(&mut self, byte: u8) {
+	match self.decoder.push_frame(byte) {
+	    Some(Ok(frame)) => self.handle(frame),
+	    Some(Err(FrameError::Checksum)) => self.emit(RETRANSMIT, ERR_CHECKSUM),
+	    Some(Err(FrameError::EndByte)) => self.emit(RETRANSMIT, ERR_SERIAL),
+	    None => {}
+	}
+    }
+
+    fn advance(&mut self, elapsed: Duration) {
+	if self.state.playback != Playback::Playing {
+	    return;
+	}
+	let Some(track) = self.state.track else {
+	    return;
+	};
+
+	self.state.elapsed += elapsed;
+	if self.state.elapsed < self.length(track) {
+	    return;
+	}
+
+	self.emit(TF_FINISHED, track.number());
+	self.state.elapsed = Duration::from_ticks(0);
+	if self.state.mode != SINGLE_REPEAT {
+	    self.state.playback = Playback::Stopped;
+	}
+    }
+
+    fn length(&self, track: Track) -> Duration {
+	match track {
+	    Track::Root(n) => self.layout.tracks[n as usize - 1],
+	    Track::Folder{ .. } => self.layout.folder_track_length,
+	}
+    }
+
+    fn start(&mut self, track: Track) -> Result<Option<(u8, u16)>, u16> {
+	if !self.state.card_present {
+	    return Err(ERR_SD_CARD);
+	}
+
+	let exists = match track {
+	    Track::Root(n) => n >= 1 && n as usize <= self.layout.tracks.len(),
+	    Track::Folder{ folder, track } => folder >= 1
+		&& track >= 1
+		&& self.layout.folders.get(folder as usize - 1).is_some_and(|files| track <= *files),
+	};
+	if !exists {
+	    return Err(ERR_NOT_FOUND);
+	}
+
+	self.state.track = Some(track);
+	self.state.playback = Playback::Playing;
+	self.state.elapsed = Duration::from_ticks(0);
+	Ok(None)
+    }
+
+    fn status(&self) -> u16 {
+	let playback = match self.state.playback {
+	    Playback::Stopped => 0,
+	    Playback::Playing => 1,
+	    Playback::Paused => 2,
+	    Playback::Other(other) => other as u16,
+	};
+	let device = if self.state.sleeping { 0x10 } else { TF_MASK };
+	(device << 8) | playback
+    }
+
+    fn handle(&mut self, frame: Frame) {
+	let param = frame.param;
+	let lsb = (param & 0xFF) as u8;
+	let tracks = self.layout.tracks.len() as u16;
+
+	let wakes_up = matches!(frame.command, PLAYBACK_SRC | NORMAL | RESET)
+	    || (GET_STATUS_..=GET_FOLDERS).contains(&frame.command);
+	let result = if self.state.sleeping && !wakes_up {
+	    Err(ERR_SLEEPING)
+	} else {
+	    match frame.command {
+		NEXT | PREV if tracks == 0 => Err(ERR_NOT_FOUND),
+		NEXT | PREV => {
+		    let current = match self.state.track {
+			Some(Track::Root(n)) => n,
+			_ => 0,
+		    };
+		    let next = if frame.command == NEXT {
+			current % tracks + 1
+		    } else if current <= 1 {
+			tracks
+		    } else {
+			current - 1
+		    };
+		    self.start(Track::Root(next))
+		},
+		PLAY | USE_MP3_FOLDER => self.start(Track::Root(param)),
+		SPEC_FOLDER => self.start(Track::Folder{ folder: (param >> 8) as u8, track: param & 0xFF }),
+		SPEC_TRACK_3000 => self.start(Track::Folder{ folder: (param >> 12) as u8, track: param & 0xFFF }),
+		REPEAT_FOLDER => {
+		    let started = self.start(Track::Folder{ folder: lsb, track: 1 });
+		    if started.is_ok() {
+			self.state.mode = FOLDER_REPEAT;
+		    }
+		    started
+		},
+		RANDOM_ALL => {
+		    let started = self.start(Track::Root(1));
+		    if started.is_ok() {
+			self.state.mode = RANDOM;
+		    }
+		    started
+		},
+		PLAYBACK_MODE => {
+		    let started = self.start(Track::Root(param));
+		    if started.is_ok() {
+			self.state.mode = SINGLE_REPEAT;
+		    }
+		    started
+		},
+		REPEAT_CURRENT => {
+		    self.state.mode = if param == 0 { SINGLE_REPEAT } else { REPEAT };
+		    Ok(None)
+		},
+		INC_VOL => {
+		    self.state.volume = (self.state.volume + 1).min(MAX_VOLUME);
+		    Ok(None)
+		},
+		DEC_VOL => {
+		    self.state.volume = self.state.volume.saturating_sub(1);
+		    Ok(None)
+		},
+		VOLUME => {
+		    self.state.volume = lsb.min(MAX_VOLUME);
+		    Ok(None)
+		},
+		EQ => {
+		    self.state.eq = lsb;
+		    Ok(None)
+		},
+		PLAYBACK_SRC => {
+		    if lsb == SLEEP {
+			self.state.sleeping = true;
+		    } else {
+			self.state.source = lsb;
+			self.state.sleeping = false;
+		    }
+		    self.state.playback = Playback::Stopped;
+		    Ok(None)
+		},
+		STANDBY => {
+		    self.state.sleeping = true;
+		    Ok(None)
+		},
+		NORMAL => {
+		    self.state.sleeping = false;
+		    Ok(None)
+		},
+		RESET => {
+		    let card_present = self.state.card_present;
+		    self.state = SimState::power_on();
+		    self.state.card_present = card_present;
+		    Ok(Some((SEND_INIT, TF_MASK)))
+		},
+		PLAYBACK => {
+		    match (self.state.playback, self.state.track) {
+			(Playback::Paused, Some(_)) => {
+			    self.state.playback = Playback::Playing;
+			    Ok(None)
+			},
+			(Playback::Stopped, Some(track)) => self.start(track),
+			_ => Ok(None),
+		    }
+		},
+		PAUSE => {
+		    if self.state.playback == Playback::Playing {
+			self.state.playback = Playback::Paused;
+		    }
+		    Ok(None)
+		},
+		STOP => {
+		    self.state.playback = Playback::Stopped;
+		    self.state.track = None;
+		    self.state.elapsed = Duration::from_ticks(0);
+		    Ok(None)
+		},
+		INSERT_ADVERT => {
+		    if self.state.playback == Playback::Playing {
+			Ok(None)
+		    } else {
+			Err(ERR_INSERTION)
+		    }
+		},
+		STOP_ADVERT | VOL_ADJ | REPEAT_PLAY | SET_DAC | KEEP_ON => Ok(None),
+		GET_STATUS_ => Ok(Some((GET_STATUS_, self.status()))),
+		GET_VOL => Ok(Some((GET_VOL, self.state.volume as u16))),
+		GET_EQ => Ok(Some((GET_EQ, self.state.eq as u16))),
+		GET_MODE => Ok(Some((GET_MODE, self.state.mode as u16))),
+		GET_VERSION => Ok(Some((GET_VERSION, SIMULATED_VERSION))),
+		GET_TF_FILES => Ok(Some((GET_TF_FILES, tracks))),
+		GET_U_FILES => Ok(Some((GET_U_FILES, 0))),
+		GET_FLASH_FILES => Ok(Some((GET_FLASH_FILES, 0))),
+		GET_TF_TRACK => Ok(Some((GET_TF_TRACK, self.state.track.map(|t| t.number()).unwrap_or(0)))),
+		GET_U_TRACK => Ok(Some((GET_U_TRACK, 0))),
+		GET_FLASH_TRACK => Ok(Some((GET_FLASH_TRACK, 0))),
+		GET_FOLDER_FILES => match self.layout.folders.get((lsb as usize).wrapping_sub(1)) {
+		    Some(files) => Ok(Some((GET_FOLDER_FILES, *files))),
+		    None => Err(ERR_NOT_FOUND),
+		},
+		GET_FOLDERS => Ok(Some((GET_FOLDERS, self.layout.folders.len() as u16))),
+		command => {
+		    log::warn!("Simulated MP3 module got unknown command {command:#x}");
+		    Err(ERR_SERIAL)
+		},
+	    }
+	};
+
+	match result {
+	    Ok(answer) => {
+		if frame.feedback {
+		    if self.acks_to_drop > 0 {
+			self.acks_to_drop -= 1;
+		    } else {
+			self.emit(REPLY, 0);
+		    }
+		}
+		if let Some((command, param)) = answer {
+		    self.emit(command, param);
+		}
+	    },
+	    Err(code) => self.emit(RETRANSMIT, code),
+	}
+    }
+}
+
+impl<'s, 'a> embedded_io_async::ErrorType for &'s Simulator<'a> {
+    type Error = Infallible;
+}
+
+impl<'s, 'a> embedded_io_async::Write for &'s Simulator<'a> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+	self.with_model(|model| buf.iter().for_each(|byte| model.receive(*byte)));
+	Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+	Ok(())
+    }
+}
+
+impl<'s, 'a> embedded_io_async::Read for &'s Simulator<'a> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+	if buf.is_empty() {
+	    return Ok(0);
+	}
+
+	loop {
+	    let len = self.model.lock(|model| {
+		let mut model = model.borrow_mut();
+		let mut len = 0;
+		while len < buf.len() {
+		    match model.output.pop_front() {
+			Some(byte) => {
+			    buf[len] = byte;
+			    len += 1;
+			},
+			None => break,
+		    }
+		}
+		len
+	    });
+
+	    if len > 0 {
+		return Ok(len);
+	    }
+	    self.output_ready.wait().await;
+	}
+    }
+}
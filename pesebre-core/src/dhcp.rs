@@ -0,0 +1,404 @@
+//! DHCP server of the access point, so that phones joining it get an
+//! address without anybody typing one in.
+//!
+//! Addresses are leased from a pool to the hardware address of each
+//! client, which gets the same address back while nobody else took it. The
+//! pesebre is handed out as router and DNS server. Replies go out as
+//! broadcasts, unless the client has an address already: the stack can't
+//! reach a client by an address it doesn't have yet.
+
+use core::fmt;
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::dns::CONTROL_PANEL_ADDRESS;
+use crate::status::dotted;
+
+/** UDP ports of the server and the clients */
+pub const SERVER_PORT : u16 = 67;
+pub const CLIENT_PORT : u16 = 68;
+
+/// Clients remembered at once.
+pub const MAX_LEASES : usize = 16;
+
+/// Room a reply needs.
+pub const MAX_REPLY_SIZE : usize = 300;
+
+/// Client holding the addresses declined, which nobody leased.
+const DECLINED : [u8; 6] = [0; 6];
+
+/// Time an offer is held for the client to request it.
+const OFFER_TIME : Duration = Duration::from_secs(60);
+
+/// Address replies go to while the client has none.
+const BROADCAST : [u8; 4] = [255, 255, 255, 255];
+
+/** Fields of the fixed part of a message */
+const OP_REQUEST : u8      = 1;
+const OP_REPLY : u8        = 2;
+const HTYPE_ETHERNET : u8  = 1;
+const MAGIC_COOKIE : [u8; 4] = [99, 130, 83, 99];
+const CIADDR : usize       = 12;
+const YIADDR : usize       = 16;
+const GIADDR : usize       = 24;
+const CHADDR : usize       = 28;
+const COOKIE : usize       = 236;
+const OPTIONS : usize      = 240;
+
+/** Options */
+const OPTION_PAD : u8               = 0;
+const OPTION_SUBNET_MASK : u8       = 1;
+const OPTION_ROUTER : u8            = 3;
+const OPTION_DNS : u8               = 6;
+const OPTION_REQUESTED_ADDRESS : u8 = 50;
+const OPTION_LEASE_TIME : u8        = 51;
+const OPTION_MESSAGE_TYPE : u8      = 53;
+const OPTION_SERVER_ID : u8         = 54;
+const OPTION_END : u8               = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Discover = 1,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
+	Ok(match value {
+	    1 => Self::Discover,
+	    2 => Self::Offer,
+	    3 => Self::Request,
+	    4 => Self::Decline,
+	    5 => Self::Ack,
+	    6 => Self::Nak,
+	    7 => Self::Release,
+	    8 => Self::Inform,
+	    _ => return Err(value),
+	})
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DhcpConfig {
+    /// Address of the pesebre, handed out as router and DNS server.
+    pub server: [u8; 4],
+    pub netmask: [u8; 4],
+    /// First address leased.
+    pub pool_start: [u8; 4],
+    /// Addresses leased, from `pool_start` on.
+    pub pool_size: u8,
+    pub lease_time: Duration,
+}
+
+impl DhcpConfig {
+
+    /// 192.168.2.100 to 192.168.2.149 for two hours, in the network of the
+    /// control panel.
+    pub const fn new() -> Self {
+	Self{
+	    server: CONTROL_PANEL_ADDRESS,
+	    netmask: [255, 255, 255, 0],
+	    pool_start: [192, 168, 2, 100],
+	    pool_size: 50,
+	    lease_time: Duration::from_secs(2 * 60 * 60),
+	}
+    }
+
+    fn in_subnet(&self, address: [u8; 4]) -> bool {
+	let mask = u32::from_be_bytes(self.netmask);
+	u32::from_be_bytes(address) & mask == u32::from_be_bytes(self.server) & mask
+    }
+
+    fn in_pool(&self, address: [u8; 4]) -> bool {
+	let offset = u32::from_be_bytes(address).wrapping_sub(u32::from_be_bytes(self.pool_start));
+	offset < self.pool_size as u32
+    }
+
+    fn pool(&self) -> impl Iterator<Item = [u8; 4]> {
+	let start = u32::from_be_bytes(self.pool_start);
+	(0..self.pool_size as u32).map(move |offset| (start + offset).to_be_bytes())
+    }
+}
+
+impl Default for DhcpConfig {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lease {
+    client: [u8; 6],
+    address: [u8; 4],
+    expires: Instant,
+    /// Acknowledged, rather than only offered.
+    bound: bool,
+}
+
+/// Reply to a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    /// Bytes of the reply at the start of the buffer.
+    pub len: usize,
+    /// Address to send it to, on `CLIENT_PORT`.
+    pub destination: [u8; 4],
+}
+
+/// Message from a client, with the fields the server looks at.
+struct Request {
+    kind: MessageType,
+    ciaddr: [u8; 4],
+    client: [u8; 6],
+    requested: Option<[u8; 4]>,
+    server_id: Option<[u8; 4]>,
+}
+
+impl Request {
+
+    /// `None` when `message` isn't a DHCP request from an Ethernet client.
+    fn parse(message: &[u8]) -> Option<Self> {
+	if message.len() < OPTIONS
+	    || message[0] != OP_REQUEST
+	    || message[1] != HTYPE_ETHERNET
+	    || message[2] != 6
+	    || message[COOKIE..OPTIONS] != MAGIC_COOKIE
+	{
+	    return None;
+	}
+	let mut kind = None;
+	let mut requested = None;
+	let mut server_id = None;
+	let mut options = &message[OPTIONS..];
+	loop {
+	    match options {
+		[OPTION_END, ..] | [] => break,
+		[OPTION_PAD, rest @ ..] => options = rest,
+		[code, len, rest @ ..] if rest.len() >= *len as usize => {
+		    let (value, rest) = rest.split_at(*len as usize);
+		    match (*code, value) {
+			(OPTION_MESSAGE_TYPE, &[kind_byte]) => kind = MessageType::try_from(kind_byte).ok(),
+			(OPTION_REQUESTED_ADDRESS, &[a, b, c, d]) => requested = Some([a, b, c, d]),
+			(OPTION_SERVER_ID, &[a, b, c, d]) => server_id = Some([a, b, c, d]),
+			_ => (),
+		    }
+		    options = rest;
+		}
+		_ => return None,
+	    }
+	}
+	Some(Self{
+	    kind: kind?,
+	    ciaddr: address_at(message, CIADDR),
+	    client: message[CHADDR..CHADDR + 6].try_into().ok()?,
+	    requested,
+	    server_id,
+	})
+    }
+}
+
+fn address_at(message: &[u8], offset: usize) -> [u8; 4] {
+    [message[offset], message[offset + 1], message[offset + 2], message[offset + 3]]
+}
+
+/// Leases of the access point.
+pub struct DhcpServer {
+    config: DhcpConfig,
+    leases: Vec<Lease, MAX_LEASES>,
+}
+
+impl DhcpServer {
+
+    /// Server leasing from the pool of `config`, which has to be in the
+    /// subnet of the server and leave the server out.
+    pub fn new(config: DhcpConfig) -> Self {
+	assert!(config.pool().all(|address| config.in_subnet(address)), "DHCP pool out of the subnet");
+	assert!(!config.in_pool(config.server), "DHCP pool takes the server address");
+	Self{ config, leases: Vec::new() }
+    }
+
+    /// Address acknowledged to `client` and still leased at `now`.
+    pub fn lease_of(&self, client: [u8; 6], now: Instant) -> Option<[u8; 4]> {
+	self.leases.iter()
+	    .find(|lease| lease.client == client && lease.bound && lease.expires > now)
+	    .map(|lease| lease.address)
+    }
+
+    /// Handles the message received in `request` at `now`, writing the
+    /// reply, if any, into `response`, which takes `MAX_REPLY_SIZE` bytes.
+    pub fn respond(&mut self, request: &[u8], response: &mut [u8], now: Instant) -> Option<Reply> {
+	let Some(message) = Request::parse(request) else {
+	    log::warn!("Failed decoding DHCP message");
+	    return None;
+	};
+	let client = Mac(message.client);
+	match message.kind {
+	    MessageType::Discover => {
+		let Some(address) = self.address_for(&message, now) else {
+		    log::warn!("No address left for {client}");
+		    return None;
+		};
+		if self.lease_of(message.client, now) != Some(address) {
+		    self.lease(message.client, address, now, OFFER_TIME, false)?;
+		}
+		log::info!("DHCP offer of {} to {client}", dotted(address));
+		self.reply(request, response, MessageType::Offer, address, true)
+	    }
+	    MessageType::Request => {
+		if let Some(server_id) = message.server_id.filter(|id| *id != self.config.server) {
+		    // the client took the offer of another server
+		    log::info!("{client} went with {}", dotted(server_id));
+		    self.leases.retain(|lease| lease.client != message.client || lease.bound);
+		    return None;
+		}
+		let address = message.requested
+		    .or((message.ciaddr != [0; 4]).then_some(message.ciaddr))?;
+		let owned = self.leases.iter()
+		    .any(|lease| lease.client == message.client && lease.address == address);
+		if self.config.in_pool(address) && (owned || self.is_free(address, now))
+		    && self.lease(message.client, address, now, self.config.lease_time, true).is_some()
+		{
+		    log::info!("DHCP lease of {} to {client}", dotted(address));
+		    self.reply(request, response, MessageType::Ack, address, true)
+		} else {
+		    log::info!("DHCP lease of {} refused to {client}", dotted(address));
+		    self.reply(request, response, MessageType::Nak, [0; 4], false)
+		}
+	    }
+	    MessageType::Release => {
+		log::info!("DHCP lease of {} released by {client}", dotted(message.ciaddr));
+		self.leases.retain(|lease| lease.client != message.client || lease.address != message.ciaddr);
+		None
+	    }
+	    MessageType::Inform => self.reply(request, response, MessageType::Ack, [0; 4], false),
+	    MessageType::Decline => {
+		// somebody uses the address without a lease: keep it out of
+		// the pool for a while
+		let address = message.requested?;
+		log::warn!("DHCP address {} in use, declined by {client}", dotted(address));
+		self.leases.retain(|lease| lease.client != message.client);
+		self.lease(DECLINED, address, now, self.config.lease_time, true);
+		None
+	    }
+	    kind => {
+		log::warn!("DHCP {kind:?} from {client} ignored");
+		None
+	    }
+	}
+    }
+
+    /// Address to offer: the one the client has, the one it asks for if it
+    /// is free, or the first free one.
+    fn address_for(&self, message: &Request, now: Instant) -> Option<[u8; 4]> {
+	if let Some(lease) = self.leases.iter().find(|lease| lease.client == message.client) {
+	    return Some(lease.address);
+	}
+	message.requested
+	    .filter(|address| self.config.in_pool(*address) && self.is_free(*address, now))
+	    .or_else(|| self.config.pool().find(|address| self.is_free(*address, now)))
+    }
+
+    fn is_free(&self, address: [u8; 4], now: Instant) -> bool {
+	!self.leases.iter().any(|lease| lease.address == address && lease.expires > now)
+    }
+
+    /// Leases `address` to `client` for `time` from `now`, taking the
+    /// place of its lease before, or of an expired one. `None` when every
+    /// lease is still running. The addresses declined only take the place
+    /// of the same address, whoever held it.
+    fn lease(&mut self, client: [u8; 6], address: [u8; 4], now: Instant, time: Duration, bound: bool) -> Option<()> {
+	self.leases.retain(|lease| lease.address != address && (client == DECLINED || lease.client != client));
+	if self.leases.is_full() {
+	    let (expired, _) = self.leases.iter()
+		.enumerate()
+		.filter(|(_, lease)| lease.expires <= now)
+		.min_by_key(|(_, lease)| lease.expires)?;
+	    self.leases.swap_remove(expired);
+	}
+	self.leases.push(Lease{ client, address, expires: now + time, bound }).ok()
+    }
+
+    /// Writes the reply of `kind` to `request`, giving `yiaddr`, with the
+    /// lease time if `lease`.
+    fn reply(&self, request: &[u8], response: &mut [u8], kind: MessageType, yiaddr: [u8; 4], lease: bool) -> Option<Reply> {
+	let response = response.get_mut(..MAX_REPLY_SIZE)?;
+	response.fill(0);
+	response[..4].copy_from_slice(&[OP_REPLY, HTYPE_ETHERNET, 6, 0]);
+	// transaction id, seconds and flags
+	response[4..12].copy_from_slice(&request[4..12]);
+	response[8..10].fill(0);
+	let ciaddr = address_at(request, CIADDR);
+	if kind == MessageType::Ack {
+	    response[CIADDR..CIADDR + 4].copy_from_slice(&ciaddr);
+	}
+	response[YIADDR..YIADDR + 4].copy_from_slice(&yiaddr);
+	response[GIADDR..CHADDR + 16].copy_from_slice(&request[GIADDR..CHADDR + 16]);
+	response[COOKIE..OPTIONS].copy_from_slice(&MAGIC_COOKIE);
+
+	let mut options = Options{ buffer: &mut response[OPTIONS..], len: 0 };
+	options.put(OPTION_MESSAGE_TYPE, &[kind as u8])?;
+	options.put(OPTION_SERVER_ID, &self.config.server)?;
+	if kind != MessageType::Nak {
+	    options.put(OPTION_SUBNET_MASK, &self.config.netmask)?;
+	    options.put(OPTION_ROUTER, &self.config.server)?;
+	    options.put(OPTION_DNS, &self.config.server)?;
+	}
+	if lease {
+	    options.put(OPTION_LEASE_TIME, &(self.config.lease_time.as_secs() as u32).to_be_bytes())?;
+	}
+	options.put(OPTION_END, &[])?;
+
+	let giaddr = address_at(request, GIADDR);
+	let destination = if giaddr != [0; 4] {
+	    giaddr
+	} else if kind != MessageType::Nak && ciaddr != [0; 4] {
+	    ciaddr
+	} else {
+	    BROADCAST
+	};
+	// the options end well before the 300 bytes BOOTP clients expect at
+	// least
+	Some(Reply{ len: MAX_REPLY_SIZE, destination })
+    }
+}
+
+/// Options being written into a reply.
+struct Options<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Options<'_> {
+    fn put(&mut self, code: u8, value: &[u8]) -> Option<()> {
+	if code == OPTION_END {
+	    *self.buffer.get_mut(self.len)? = OPTION_END;
+	    self.len += 1;
+	    return Some(());
+	}
+	let end = self.len + 2 + value.len();
+	let option = self.buffer.get_mut(self.len..end)?;
+	option[0] = code;
+	option[1] = value.len() as u8;
+	option[2..].copy_from_slice(value);
+	self.len = end;
+	Some(())
+    }
+}
+
+/// Hardware address, for the logs.
+struct Mac([u8; 6]);
+
+impl fmt::Display for Mac {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	let [a, b, c, d, e, g] = self.0;
+	write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
@@ -0,0 +1,318 @@
+//! DNS responder of the access point, authoritative for the names of the
+//! pesebre in its zone table.
+//!
+//! Questions for names of the zone get their A record, or no data for the
+//! other types, AAAA included: the pesebre only has an IPv4 address. Other
+//! names under the zone domain don't exist; names elsewhere are refused, as
+//! the pesebre doesn't resolve the rest of the internet, except in portal
+//! mode, where they all lead to the control panel. Replies carry an EDNS
+//! record when the query does.
+
+use heapless::{String, Vec};
+
+/// Domain the pesebre answers for.
+pub const DOMAIN : &str = "pesebre.co";
+
+/// Name the control panel is published under.
+pub const CONTROL_PANEL_NAME : &str = "control-panel.pesebre.co";
+
+/// Address of the pesebre in its own access point.
+pub const CONTROL_PANEL_ADDRESS : [u8; 4] = [192, 168, 2, 1];
+
+/// Names of the pesebre.
+pub const ZONE : [Record; 2] = [
+    Record{ name: CONTROL_PANEL_NAME, address: CONTROL_PANEL_ADDRESS },
+    Record{ name: DOMAIN, address: CONTROL_PANEL_ADDRESS },
+];
+
+/// Time clients may keep the answers.
+pub const TTL : u32 = 60;
+
+/// Longest reply, as plain DNS over UDP allows.
+pub const MAX_MESSAGE_SIZE : usize = 512;
+
+/// Questions answered in a query, more than any client asks.
+const MAX_QUESTIONS : usize = 4;
+
+pub(crate) const HEADER_SIZE : usize = 12;
+
+/** Header flags */
+const FLAG_QR : u16 = 0x8000;
+const FLAG_AA : u16 = 0x0400;
+const FLAG_TC : u16 = 0x0200;
+const FLAG_RD : u16 = 0x0100;
+const FLAG_RA : u16 = 0x0080;
+const OPCODE_MASK : u16 = 0x7800;
+
+/** Response codes */
+const NO_ERROR : u8 = 0;
+const FORMAT_ERROR : u8 = 1;
+const NAME_ERROR : u8 = 3;
+const NOT_IMPLEMENTED : u8 = 4;
+const REFUSED : u8 = 5;
+/// Extended, in the EDNS record.
+const BAD_VERSION : u16 = 16;
+
+/** Record types and classes */
+const TYPE_A : u16 = 1;
+const TYPE_OPT : u16 = 41;
+const TYPE_ANY : u16 = 255;
+const CLASS_IN : u16 = 1;
+const CLASS_ANY : u16 = 255;
+
+/// Name of the zone and its address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub name: &'static str,
+    pub address: [u8; 4],
+}
+
+/// Question of a query.
+struct Question {
+    /// Name in dotted form, as written.
+    name: String<253>,
+    /// Where the name starts in the query, for the answers to point to.
+    offset: u16,
+    kind: u16,
+    class: u16,
+}
+
+/// Answers the queries of the access point with the records of `zone`.
+pub struct DnsResponder<'a> {
+    zone: &'a [Record],
+    /// Every name leads to the control panel, so that phones find the
+    /// captive portal whatever they look up.
+    pub portal: bool,
+}
+
+impl<'a> DnsResponder<'a> {
+
+    pub const fn new(zone: &'a [Record]) -> Self {
+	Self{ zone, portal: false }
+    }
+
+    /// Handles the DNS query received in `request`, writing the reply into
+    /// `response` and returning its length. `None` when the datagram isn't
+    /// a DNS query, or too short to tell who asked.
+    pub fn respond(&self, request: &[u8], response: &mut [u8]) -> Option<usize> {
+	let header = request.get(..HEADER_SIZE)?;
+	let flags = u16::from_be_bytes([header[2], header[3]]);
+	if flags & FLAG_QR != 0 {
+	    // a reply, not for us
+	    return None;
+	}
+	let response = response.get_mut(..MAX_MESSAGE_SIZE)?;
+	let count = |at: usize| u16::from_be_bytes([header[at], header[at + 1]]);
+
+	let mut reply = Reply{ buffer: response, len: HEADER_SIZE, answers: 0, additional: 0 };
+	reply.buffer[..2].copy_from_slice(&header[..2]);
+	let mut reply_flags = FLAG_QR | (flags & (OPCODE_MASK | FLAG_RD));
+	if self.portal {
+	    reply_flags |= FLAG_RA;
+	}
+
+	let Some((questions, end)) = read_questions(request, count(4)) else {
+	    log::warn!("Malformed DNS query");
+	    return Some(reply.finish(reply_flags, FORMAT_ERROR, 0));
+	};
+	// the questions go back as they came
+	let echoed = &request[HEADER_SIZE..end];
+	reply.buffer.get_mut(HEADER_SIZE..end)?.copy_from_slice(echoed);
+	reply.len = end;
+	let question_count = questions.len() as u16;
+
+	let edns = match read_edns(request, end, count(6), count(8), count(10)) {
+	    Ok(edns) => edns,
+	    Err(()) => {
+		log::warn!("Malformed DNS query");
+		return Some(reply.finish(reply_flags, FORMAT_ERROR, question_count));
+	    }
+	};
+	if let Some(version) = edns {
+	    if version > 0 {
+		reply.put_opt(BAD_VERSION)?;
+		return Some(reply.finish(reply_flags, NO_ERROR, question_count));
+	    }
+	}
+	if flags & OPCODE_MASK != 0 {
+	    return Some(reply.finish(reply_flags, NOT_IMPLEMENTED, question_count));
+	}
+
+	let mut code = NO_ERROR;
+	for question in &questions {
+	    log::info!("DNS question for {} type {}", question.name, question.kind);
+	    if question.class != CLASS_IN && question.class != CLASS_ANY {
+		code = code.max(REFUSED);
+		continue;
+	    }
+	    let address = match self.find(&question.name) {
+		Some(record) => record.address,
+		None if self.portal => CONTROL_PANEL_ADDRESS,
+		None if in_domain(&question.name) => {
+		    code = code.max(NAME_ERROR);
+		    continue;
+		}
+		None => {
+		    code = code.max(REFUSED);
+		    continue;
+		}
+	    };
+	    if (question.kind == TYPE_A || question.kind == TYPE_ANY) && reply.put_a(question.offset, address).is_none() {
+		// no room for all the answers: the client can ask again over
+		// TCP, or one question at a time
+		reply_flags |= FLAG_TC;
+		break;
+	    }
+	}
+	if code != REFUSED {
+	    reply_flags |= FLAG_AA;
+	}
+	if edns.is_some() && reply.put_opt(0).is_none() {
+	    reply_flags |= FLAG_TC;
+	}
+	Some(reply.finish(reply_flags, code, question_count))
+    }
+
+    fn find(&self, name: &str) -> Option<&Record> {
+	self.zone.iter().find(|record| record.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Whether `name` is the zone domain or under it.
+fn in_domain(name: &str) -> bool {
+    let name = name.as_bytes();
+    let domain = DOMAIN.as_bytes();
+    name.len() >= domain.len()
+	&& name[name.len() - domain.len()..].eq_ignore_ascii_case(domain)
+	&& (name.len() == domain.len() || name[name.len() - domain.len() - 1] == b'.')
+}
+
+/// The questions of `message`, and where they end.
+fn read_questions(message: &[u8], count: u16) -> Option<(Vec<Question, MAX_QUESTIONS>, usize)> {
+    let mut questions = Vec::new();
+    let mut position = HEADER_SIZE;
+    for _ in 0..count {
+	let offset = position as u16;
+	let (name, end) = read_name(message, position)?;
+	let fixed = message.get(end..end + 4)?;
+	questions.push(Question{
+	    name,
+	    offset,
+	    kind: u16::from_be_bytes([fixed[0], fixed[1]]),
+	    class: u16::from_be_bytes([fixed[2], fixed[3]]),
+	}).ok()?;
+	position = end + 4;
+    }
+    Some((questions, position))
+}
+
+/// Version of the EDNS record of `message`, if any, skipping the answer and
+/// authority records from `position`.
+fn read_edns(message: &[u8], mut position: usize, answers: u16, authority: u16, additional: u16) -> Result<Option<u8>, ()> {
+    let mut version = None;
+    for index in 0..answers as usize + authority as usize + additional as usize {
+	let (_, end) = read_name(message, position).ok_or(())?;
+	let fixed = message.get(end..end + 10).ok_or(())?;
+	let kind = u16::from_be_bytes([fixed[0], fixed[1]]);
+	let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+	if index >= answers as usize + authority as usize && kind == TYPE_OPT {
+	    if version.is_some() {
+		// only one allowed
+		return Err(());
+	    }
+	    version = Some(fixed[5]);
+	}
+	position = end + 10 + len;
+	if position > message.len() {
+	    return Err(());
+	}
+    }
+    Ok(version)
+}
+
+/// The name at `position` of `message` in dotted form, and where it ends.
+pub(crate) fn read_name(message: &[u8], position: usize) -> Option<(String<253>, usize)> {
+    let mut name = String::new();
+    let mut at = position;
+    // where the name ends, once a pointer was followed
+    let mut end = None;
+    // pointers go backwards, so a loop can't go on for longer than this
+    for _ in 0..message.len() {
+	let len = *message.get(at)? as usize;
+	match len {
+	    0 => return Some((name, end.unwrap_or(at + 1))),
+	    1..=63 => {
+		let label = core::str::from_utf8(message.get(at + 1..at + 1 + len)?).ok()?;
+		if !name.is_empty() {
+		    name.push('.').ok()?;
+		}
+		name.push_str(label).ok()?;
+		at += 1 + len;
+	    }
+	    0xc0..=0xff => {
+		let target = u16::from_be_bytes([len as u8 & 0x3f, *message.get(at + 1)?]) as usize;
+		if target >= at {
+		    return None;
+		}
+		end.get_or_insert(at + 2);
+		at = target;
+	    }
+	    _ => return None,
+	}
+    }
+    None
+}
+
+/// Reply being written.
+struct Reply<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    answers: u16,
+    additional: u16,
+}
+
+impl Reply<'_> {
+
+    fn put(&mut self, bytes: &[u8]) -> Option<()> {
+	self.buffer.get_mut(self.len..self.len + bytes.len())?.copy_from_slice(bytes);
+	self.len += bytes.len();
+	Some(())
+    }
+
+    /// A record for the name of the question at `offset`.
+    fn put_a(&mut self, offset: u16, address: [u8; 4]) -> Option<()> {
+	let mut record = [0; 16];
+	record[..2].copy_from_slice(&(0xc000 | offset).to_be_bytes());
+	record[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+	record[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+	record[6..10].copy_from_slice(&TTL.to_be_bytes());
+	record[10..12].copy_from_slice(&4_u16.to_be_bytes());
+	record[12..].copy_from_slice(&address);
+	self.put(&record)?;
+	self.answers += 1;
+	Some(())
+    }
+
+    /// EDNS record, with the upper bits of the response `code`.
+    fn put_opt(&mut self, code: u16) -> Option<()> {
+	let mut record = [0; 11];
+	record[1..3].copy_from_slice(&TYPE_OPT.to_be_bytes());
+	// largest reply taken
+	record[3..5].copy_from_slice(&(MAX_MESSAGE_SIZE as u16).to_be_bytes());
+	record[5] = (code >> 4) as u8;
+	self.put(&record)?;
+	self.additional += 1;
+	Some(())
+    }
+
+    /// Writes the header, returning the length of the reply.
+    fn finish(self, flags: u16, code: u8, questions: u16) -> usize {
+	let header = &mut self.buffer[2..HEADER_SIZE];
+	header[..2].copy_from_slice(&(flags | code as u16).to_be_bytes());
+	header[2..4].copy_from_slice(&questions.to_be_bytes());
+	header[4..6].copy_from_slice(&self.answers.to_be_bytes());
+	header[6..8].copy_from_slice(&0_u16.to_be_bytes());
+	header[8..10].copy_from_slice(&self.additional.to_be_bytes());
+	self.len
+    }
+}
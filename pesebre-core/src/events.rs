@@ -0,0 +1,95 @@
+//! Changes the control panel pages follow live, streamed as Server-Sent
+//! Events by `GET /api/events`.
+
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    pubsub::PubSubChannel,
+};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::catalog::Track;
+use crate::dfplayer_mini::Device;
+
+/// Events kept for the slowest page before it starts missing them.
+pub const PANEL_EVENT_QUEUE_SIZE : usize = 8;
+
+/// Pages that can follow the events at once. Each one holds a web task for
+/// as long as it stays open.
+pub const EVENT_STREAMS : usize = 2;
+
+/// Events on their way to the open pages.
+pub type PanelEventChannel =
+    PubSubChannel<CriticalSectionRawMutex, PanelEvent, PANEL_EVENT_QUEUE_SIZE, EVENT_STREAMS, 0>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanelEvent {
+    TrackStarted(&'static Track),
+    TrackFinished(&'static Track),
+    Paused,
+    Resumed,
+    Stopped,
+    /// Volume set in the module, from 0 to 30.
+    VolumeChanged(u8),
+    /// The module was muted or unmuted.
+    MuteChanged(bool),
+    /// The playlist changed; it holds this many tracks now.
+    QueueChanged(usize),
+    MediaRemoved(Device),
+    MediaInserted(Device),
+    /// The lights switched to this blinking program, as numbered in
+    /// `Settings::light_program`.
+    LightSceneChanged(u8),
+}
+
+impl PanelEvent {
+    /// Name of the event in the stream.
+    pub fn name(&self) -> &'static str {
+	match self {
+	    Self::TrackStarted(_) => "track-started",
+	    Self::TrackFinished(_) => "track-finished",
+	    Self::Paused => "paused",
+	    Self::Resumed => "resumed",
+	    Self::Stopped => "stopped",
+	    Self::VolumeChanged(_) => "volume",
+	    Self::MuteChanged(_) => "mute",
+	    Self::QueueChanged(_) => "queue",
+	    Self::MediaRemoved(_) => "media-removed",
+	    Self::MediaInserted(_) => "media-inserted",
+	    Self::LightSceneChanged(_) => "light-scene",
+	}
+    }
+}
+
+/// The data of the event, named in `event`. Pausing, resuming and stopping
+/// carry the player state named as in the status.
+impl Serialize for PanelEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+	let fields = match self {
+	    Self::TrackStarted(_) | Self::TrackFinished(_) => 3,
+	    _ => 2,
+	};
+	let mut data = serializer.serialize_struct("PanelEvent", fields)?;
+	data.serialize_field("event", self.name())?;
+	match *self {
+	    Self::TrackStarted(track) | Self::TrackFinished(track) => {
+		data.serialize_field("id", &track.id)?;
+		data.serialize_field("title", track.title)?;
+	    }
+	    Self::VolumeChanged(volume) => data.serialize_field("volume", &volume)?,
+	    Self::MuteChanged(muted) => data.serialize_field("muted", &muted)?,
+	    Self::QueueChanged(length) => data.serialize_field("length", &length)?,
+	    Self::MediaRemoved(device) | Self::MediaInserted(device) => data.serialize_field("device", &device)?,
+	    Self::LightSceneChanged(program) => data.serialize_field("program", &program)?,
+	    Self::Paused => data.serialize_field("state", "paused")?,
+	    Self::Resumed => data.serialize_field("state", "playing")?,
+	    Self::Stopped => data.serialize_field("state", "stopped")?,
+	}
+	data.end()
+    }
+}
+
+/// Sends `event` to the open pages. Never waits: a page that fell behind
+/// misses the oldest events.
+pub fn notify(events: &PanelEventChannel, event: PanelEvent) {
+    events.immediate_publisher().publish_immediate(event);
+}
@@ -0,0 +1,281 @@
+use core::fmt;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::{Subscriber, WaitResult};
+use embassy_time::{with_timeout, Duration, Instant};
+
+use picoserve::io::{Read, Write};
+use picoserve::response::sse::{EventSource, EventStream, EventWriter};
+use picoserve::response::{Connection, Content, File, Json, Response, StatusCode, WebSocketUpgrade};
+use picoserve::routing::{get, post, PathRouter};
+
+use crate::api::{self, AccessPoint, JsonBody, MoveRequest, PasswordRequest, PlayRequest, PositionRequest, Rest, Volume};
+use crate::auth::Credentials;
+use crate::catalog::{self, Category};
+use crate::control::{Control, ControlMessages};
+use crate::events::{PanelEvent, EVENT_STREAMS, PANEL_EVENT_QUEUE_SIZE};
+use crate::player::PlayerState;
+use crate::portal::{ConnectivityProbes, PortalRedirect};
+use crate::provisioning::Candidate;
+use crate::status::Status;
+use crate::ws::ControlSocket;
+
+const INDEX : &str = include_str!("index.html");
+const SETTINGS : File = File::html(include_str!("settings.html"));
+const SETUP : File = File::html(include_str!("setup.html"));
+
+/// Pause in the event stream after which a keep-alive goes out, well within
+/// the socket timeout of the web tasks.
+const KEEPALIVE_PERIOD : Duration = Duration::from_secs(5);
+
+/** Lines of `index.html` replaced by the track playing and the track lists */
+const NOW_PLAYING_MARKER : &str = "      <!-- now playing -->\n";
+const CATALOG_MARKER : &str = "      <!-- catalog -->\n";
+
+/// Network the control panel is served on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interface {
+    /// The access point of the pesebre.
+    AccessPoint,
+    /// The household network.
+    Station,
+}
+
+/// Routes of the control panel served on `interface`. Requests are handed to
+/// the player task through `control`.
+pub fn make_app(control: &'static Control, interface: Interface) -> picoserve::Router<impl PathRouter<()>, ()> {
+    picoserve::Router::new()
+	.route(
+	    "/",
+	    get(move || async move { Response::ok(IndexPage(control.state.get())) })
+	)
+	.route(
+	    "/api/status",
+	    get(move || async move { Json(Status::new(control, Instant::now())) }),
+	)
+	.route(
+	    "/api/events",
+	    get(move || async move {
+		match control.events.subscriber() {
+		    Ok(subscriber) => Ok(EventStream(PanelEvents(subscriber))),
+		    Err(_) => {
+			log::warn!("Too many event streams open");
+			Err((StatusCode::new(503), "Too many event streams open\n"))
+		    }
+		}
+	    }),
+	)
+	.route(
+	    "/ws",
+	    get(move |upgrade: WebSocketUpgrade| async move {
+		match control.events.subscriber() {
+		    Ok(events) => Ok(upgrade.on_upgrade(ControlSocket{ control, events })),
+		    Err(_) => {
+			log::warn!("Too many event streams open");
+			Err((StatusCode::new(503), "Too many event streams open\n"))
+		    }
+		}
+	    }),
+	)
+	.route(ConnectivityProbes, get(|| async { PortalRedirect }))
+	.route(
+	    "/settings",
+	    get(move |credentials: Credentials| async move {
+		credentials.check_page(&control.settings.get().admin_password, interface)?;
+		Ok::<_, api::ApiError>(SETTINGS.into_response())
+	    }),
+	)
+	.route(
+	    "/setup",
+	    get(move |credentials: Credentials| async move {
+		credentials.check_page(&control.settings.get().admin_password, interface)?;
+		Ok::<_, api::ApiError>(SETUP.into_response())
+	    }),
+	)
+	.route(
+	    "/api/v1/play",
+	    post(move |JsonBody(request): JsonBody<PlayRequest>| async move { api::play(control, request.track) }),
+	)
+	.route("/api/v1/pause", post(move || async move { api::pause(control) }))
+	.route("/api/v1/resume", post(move || async move { api::resume(control) }))
+	.route("/api/v1/stop", post(move || async move { api::stop(control) }))
+	.route("/api/v1/volume", Rest(Volume(control)))
+	.route("/api/v1/volume/up", post(move || async move { api::step_volume(control, ControlMessages::IncVol) }))
+	.route("/api/v1/volume/down", post(move || async move { api::step_volume(control, ControlMessages::DecVol) }))
+	.route("/api/v1/settings/access-point", Rest(AccessPoint(control)))
+	.route(
+	    "/api/v1/settings/password",
+	    post(move |credentials: Credentials, JsonBody(request): JsonBody<PasswordRequest>| async move {
+		api::set_admin_password(control, interface, credentials, request)
+	    }),
+	)
+	.route(
+	    "/api/v1/setup/networks",
+	    get(move |credentials: Credentials| async move {
+		credentials.check(&control.settings.get().admin_password)?;
+		Ok::<_, api::ApiError>(Json(control.provisioning.scan().await))
+	    }),
+	)
+	.route(
+	    "/api/v1/setup/network",
+	    get(move |credentials: Credentials| async move {
+		credentials.check(&control.settings.get().admin_password)?;
+		Ok::<_, api::ApiError>(Json(control.provisioning.trial()))
+	    })
+	    .post(move |credentials: Credentials, JsonBody(candidate): JsonBody<Candidate>| async move {
+		api::try_network(control, credentials, candidate)
+	    }),
+	)
+	.route(
+	    "/api/v1/playlist",
+	    get(move || async move { Json(control.playlist.get()) })
+	    .post(move |JsonBody(request): JsonBody<PlayRequest>| async move { api::enqueue(control, request.track) }),
+	)
+	.route(
+	    "/api/v1/playlist/next",
+	    post(move |JsonBody(request): JsonBody<PlayRequest>| async move { api::play_next(control, request.track) }),
+	)
+	.route(
+	    "/api/v1/playlist/remove",
+	    post(move |JsonBody(request): JsonBody<PositionRequest>| async move { api::remove(control, request.position) }),
+	)
+	.route(
+	    "/api/v1/playlist/move",
+	    post(move |JsonBody(request): JsonBody<MoveRequest>| async move { api::reorder(control, request.from, request.to) }),
+	)
+	.route("/api/v1/playlist/clear", post(move || async move { api::clear_playlist(control) }))
+	.route("/api/v1/playlist/skip", post(move || async move { api::skip(control) }))
+}
+
+/// Events streamed to one page.
+struct PanelEvents(
+    Subscriber<'static, CriticalSectionRawMutex, PanelEvent, PANEL_EVENT_QUEUE_SIZE, EVENT_STREAMS, 0>,
+);
+
+impl EventSource for PanelEvents {
+    async fn write_events<W: Write>(mut self, mut writer: EventWriter<W>) -> Result<(), W::Error> {
+	loop {
+	    match with_timeout(KEEPALIVE_PERIOD, self.0.next_message()).await {
+		Ok(WaitResult::Message(event)) => writer.write_event(event.name(), Json(event)).await?,
+		Ok(WaitResult::Lagged(missed)) => {
+		    log::warn!("Event stream missed {missed} events");
+		    // the page has to reload the status to catch up
+		    writer.write_event("lagged", r#"{"event":"lagged"}"#).await?;
+		}
+		Err(_) => writer.write_keepalive().await?,
+	    }
+	}
+    }
+}
+
+/// Control panel page, with the track playing and the track lists rendered
+/// from the catalog.
+struct IndexPage(PlayerState);
+
+impl IndexPage {
+    /// The page before the track playing, between it and the track lists,
+    /// and after them.
+    fn parts() -> (&'static str, &'static str, &'static str) {
+	let (head, rest) = INDEX.split_once(NOW_PLAYING_MARKER).unwrap_or((INDEX, ""));
+	let (middle, tail) = rest.split_once(CATALOG_MARKER).unwrap_or((rest, ""));
+	(head, middle, tail)
+    }
+}
+
+/// Track playing or paused in the control panel.
+struct NowPlaying(PlayerState);
+
+impl fmt::Display for NowPlaying {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	write!(f, "      <p class=\"now-playing\" id=\"now-playing\">")?;
+	match self.0 {
+	    PlayerState::Playing{ track, .. } => write!(f, "Sonando: {}", track.title)?,
+	    PlayerState::Paused{ track, .. } => write!(f, "En pausa: {}", track.title)?,
+	    _ => (),
+	}
+	writeln!(f, "</p>")
+    }
+}
+
+/// Track list of a category in the control panel.
+struct Menu(Category);
+
+impl fmt::Display for Menu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	if catalog::by_category(self.0).next().is_none() {
+	    return Ok(());
+	}
+	writeln!(f, "      <h2>{}</h2>", self.0.title())?;
+	writeln!(f, "      <ul class=\"menu_list\">")?;
+	for track in catalog::by_category(self.0) {
+	    writeln!(
+		f,
+		"\t<li onclick=\"reproducir({id})\">{} <a class=\"add\" onclick=\"encolar(event, {id})\">+</a></li>",
+		track.title,
+		id = track.id,
+	    )?;
+	}
+	writeln!(f, "      </ul>")
+    }
+}
+
+/// Counts the bytes written to it.
+struct MeasureFormatSize(usize);
+
+impl fmt::Write for MeasureFormatSize {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+	self.0 += s.len();
+	Ok(())
+    }
+}
+
+impl Content for IndexPage {
+    fn content_type(&self) -> &'static str {
+	"text/html; charset=utf-8"
+    }
+
+    fn content_length(&self) -> usize {
+	use fmt::Write;
+
+	let (head, middle, tail) = Self::parts();
+	let mut rendered = MeasureFormatSize(0);
+	let _ = write!(rendered, "{}", NowPlaying(self.0));
+	for category in Category::MENU {
+	    let _ = write!(rendered, "{}", Menu(category));
+	}
+	head.len() + middle.len() + rendered.0 + tail.len()
+    }
+
+    async fn write_content<R: Read, W: Write<Error = R::Error>>(
+	self,
+	_connection: Connection<R>,
+	mut writer: W,
+    ) -> Result<(), W::Error> {
+	use picoserve::io::WriteExt;
+
+	let (head, middle, tail) = Self::parts();
+	writer.write_all(head.as_bytes()).await?;
+	write!(writer, "{}", NowPlaying(self.0)).await?;
+	writer.write_all(middle.as_bytes()).await?;
+	for category in Category::MENU {
+	    write!(writer, "{}", Menu(category)).await?;
+	}
+	writer.write_all(tail.as_bytes()).await
+    }
+}
+
+/// Lets picoserve time out requests with embassy timers.
+pub struct EmbassyTimer;
+
+impl picoserve::Timer for EmbassyTimer {
+    type Duration = embassy_time::Duration;
+    type TimeoutError = embassy_time::TimeoutError;
+
+    async fn run_with_timeout<F: core::future::Future>(
+        &mut self,
+        duration: Self::Duration,
+        future: F,
+    ) -> Result<F::Output, Self::TimeoutError> {
+        embassy_time::with_timeout(duration, future).await
+    }
+}
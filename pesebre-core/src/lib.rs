@@ -0,0 +1,29 @@
+//! Hardware independent part of the pesebre: the DFPlayer Mini protocol,
+//! the catalog of recordings, the playlist, the player state, the volume,
+//! the commands the control panel can request, the status and events it can
+//! show, the settings kept in flash and who may change them, the Wi-Fi
+//! connectivity and its setup, the DHCP server, the DNS responder, the captive portal,
+//! the mDNS advertisement, the HTTP routes, the REST interface and the
+//! WebSocket. It builds for the ESP32-C3 firmware as well as for the host,
+//! where it is tested.
+#![no_std]
+
+pub mod api;
+pub mod auth;
+pub mod catalog;
+pub mod connectivity;
+pub mod control;
+pub mod dhcp;
+pub mod dfplayer_mini;
+pub mod dns;
+pub mod events;
+pub mod http;
+pub mod mdns;
+pub mod player;
+pub mod playlist;
+pub mod portal;
+pub mod provisioning;
+pub mod settings;
+pub mod status;
+pub mod volume;
+pub mod ws;
@@ -0,0 +1,355 @@
+//! Multicast DNS responder, for the pesebre to be found as `pesebre.local`
+//! on the home network, and DNS-SD advertisement of its control panel as an
+//! `_http._tcp` service, which phones, laptops and Home Assistant browse.
+//!
+//! Questions for the names of the pesebre get their records, along with the
+//! ones a browser would ask for next; anything else goes unanswered, as
+//! mDNS wants. Queries from ports other than 5353, as
+//! `dig -p 5353 @224.0.0.251 pesebre.local` sends, are legacy unicast ones
+//! and get a plain DNS reply.
+
+use crate::dns::{read_name, HEADER_SIZE};
+
+/// Group and port of mDNS.
+pub const MDNS_ADDRESS : [u8; 4] = [224, 0, 0, 251];
+pub const MDNS_PORT : u16 = 5353;
+
+/// Name of the pesebre on the home network.
+pub const HOSTNAME : &str = "pesebre.local";
+
+/// Service the control panel is advertised as.
+pub const SERVICE_TYPE : &str = "_http._tcp.local";
+
+/// Name of the control panel among the services of the network.
+pub const SERVICE_NAME : &str = "Pesebre Navideño._http._tcp.local";
+
+/// Where the REST interface is, for the clients browsing the service.
+pub const API_PATH : &str = "/api/v1";
+
+/// Longest message sent or taken.
+pub const MAX_MESSAGE_SIZE : usize = 512;
+
+/// Service listing the service types of the network.
+const SERVICE_TYPES : &str = "_services._dns-sd._udp.local";
+
+/** Time to live of the records, as RFC 6762 recommends */
+const HOST_TTL : u32 = 120;
+const SERVICE_TTL : u32 = 4500;
+/// Longest for legacy unicast replies, which go to plain DNS caches.
+const LEGACY_TTL : u32 = 10;
+
+/** Header flags */
+const FLAG_RESPONSE : u16 = 0x8400;
+/// Replies, other opcodes and response codes aren't queries to answer.
+const NOT_A_QUERY : u16 = 0xf80f;
+
+/** Record types and classes */
+const TYPE_A : u16 = 1;
+const TYPE_PTR : u16 = 12;
+const TYPE_TXT : u16 = 16;
+const TYPE_SRV : u16 = 33;
+const TYPE_ANY : u16 = 255;
+const CLASS_IN : u16 = 1;
+const CLASS_ANY : u16 = 255;
+/// Top bit of the class: the question wants a unicast reply, the record
+/// replaces what caches have for its name.
+const CLASS_TOP_BIT : u16 = 0x8000;
+
+/// Records of the pesebre.
+#[derive(Debug, Clone, Copy)]
+enum Record {
+    /// `_http._tcp` among the service types.
+    ServiceType,
+    /// The control panel among the `_http._tcp` services.
+    Service,
+    /// Host and port of the control panel.
+    Location,
+    /// TXT record of the control panel.
+    Details,
+    /// Address of the host.
+    Address,
+}
+
+const RECORDS : [Record; 5] = [Record::ServiceType, Record::Service, Record::Location, Record::Details, Record::Address];
+
+impl Record {
+
+    fn bit(self) -> u8 {
+	1 << self as u8
+    }
+
+    fn name(self) -> &'static str {
+	match self {
+	    Self::ServiceType => SERVICE_TYPES,
+	    Self::Service => SERVICE_TYPE,
+	    Self::Location | Self::Details => SERVICE_NAME,
+	    Self::Address => HOSTNAME,
+	}
+    }
+
+    fn kind(self) -> u16 {
+	match self {
+	    Self::ServiceType | Self::Service => TYPE_PTR,
+	    Self::Location => TYPE_SRV,
+	    Self::Details => TYPE_TXT,
+	    Self::Address => TYPE_A,
+	}
+    }
+
+    fn ttl(self) -> u32 {
+	match self {
+	    Self::Location | Self::Address => HOST_TTL,
+	    _ => SERVICE_TTL,
+	}
+    }
+
+    /// Name a pointer points to.
+    fn target(self) -> Option<&'static str> {
+	match self {
+	    Self::ServiceType => Some(SERVICE_TYPE),
+	    Self::Service => Some(SERVICE_NAME),
+	    _ => None,
+	}
+    }
+
+    /// Records sent along, which the client would ask for next.
+    fn additional(self) -> u8 {
+	match self {
+	    Self::Service => Self::Location.bit() | Self::Details.bit() | Self::Address.bit(),
+	    Self::Location => Self::Address.bit(),
+	    _ => 0,
+	}
+    }
+
+    /// Records answering a question for `name` and `kind`.
+    fn answering(name: &str, kind: u16) -> u8 {
+	RECORDS
+	    .into_iter()
+	    .filter(|record| kind == record.kind() || kind == TYPE_ANY)
+	    .filter(|record| record.name().eq_ignore_ascii_case(name))
+	    .fold(0, |records, record| records | record.bit())
+    }
+}
+
+/// Reply to a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    pub len: usize,
+    /// To the querier rather than to the group.
+    pub unicast: bool,
+}
+
+/// Answers the mDNS queries for the pesebre and its control panel.
+pub struct MdnsResponder<'a> {
+    /// Address of the pesebre in the network it joined, `[0; 4]` while it
+    /// has none.
+    pub address: [u8; 4],
+    port: u16,
+    version: &'a str,
+}
+
+impl<'a> MdnsResponder<'a> {
+
+    /// Responder for the control panel served on `port`, of the firmware
+    /// `version`.
+    pub const fn new(address: [u8; 4], port: u16, version: &'a str) -> Self {
+	Self{ address, port, version }
+    }
+
+    /// Handles the query received in `request`, from another port than
+    /// the mDNS one when `legacy`, writing the reply into `response`. `None`
+    /// when there is nothing to answer, as while there is no address.
+    pub fn respond(&self, request: &[u8], response: &mut [u8], legacy: bool) -> Option<Reply> {
+	if self.address == [0; 4] {
+	    return None;
+	}
+	let header = request.get(..HEADER_SIZE)?;
+	let count = |at: usize| u16::from_be_bytes([header[at], header[at + 1]]);
+	if count(2) & NOT_A_QUERY != 0 {
+	    return None;
+	}
+
+	let mut answers = 0;
+	let mut unicast = true;
+	let mut position = HEADER_SIZE;
+	for _ in 0..count(4) {
+	    let (name, end) = read_name(request, position)?;
+	    let fixed = request.get(end..end + 4)?;
+	    let kind = u16::from_be_bytes([fixed[0], fixed[1]]);
+	    let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+	    position = end + 4;
+	    if class & !CLASS_TOP_BIT != CLASS_IN && class & !CLASS_TOP_BIT != CLASS_ANY {
+		continue;
+	    }
+	    let answering = Record::answering(&name, kind);
+	    if answering != 0 && class & CLASS_TOP_BIT == 0 {
+		unicast = false;
+	    }
+	    answers |= answering;
+	}
+	if answers == 0 {
+	    return None;
+	}
+	let questions_end = position;
+
+	// no need to tell what the querier already knows for long enough
+	for _ in 0..count(6) {
+	    let (name, end) = read_name(request, position)?;
+	    let fixed = request.get(end..end + 10)?;
+	    let kind = u16::from_be_bytes([fixed[0], fixed[1]]);
+	    let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+	    let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+	    if kind == TYPE_PTR {
+		let (target, _) = read_name(request, end + 10)?;
+		for record in RECORDS {
+		    if record.kind() == kind
+			&& record.name().eq_ignore_ascii_case(&name)
+			&& record.target().is_some_and(|known| known.eq_ignore_ascii_case(&target))
+			&& ttl >= record.ttl() / 2
+		    {
+			answers &= !record.bit();
+		    }
+		}
+	    }
+	    position = end + 10 + len;
+	    if position > request.len() {
+		return None;
+	    }
+	}
+	if answers == 0 {
+	    return None;
+	}
+
+	let mut writer = Writer{ buffer: response, len: HEADER_SIZE, legacy };
+	let questions = if legacy {
+	    // a plain DNS client wants its question back
+	    writer.put(&request[HEADER_SIZE..questions_end])?;
+	    count(4)
+	} else {
+	    0
+	};
+	let id = if legacy { [header[0], header[1]] } else { [0, 0] };
+	let additional = RECORDS
+	    .into_iter()
+	    .filter(|record| answers & record.bit() != 0)
+	    .fold(0, |records, record| records | record.additional())
+	    & !answers;
+	let answer_count = self.put_records(&mut writer, answers);
+	let additional_count = self.put_records(&mut writer, additional);
+	if answer_count == 0 {
+	    return None;
+	}
+	let len = writer.finish(id, questions, answer_count, additional_count);
+	Some(Reply{ len, unicast: unicast || legacy })
+    }
+
+    /// Writes into `response` the announcement of every record, for the
+    /// network to learn of the pesebre when it joins, returning its length.
+    pub fn announce(&self, response: &mut [u8]) -> usize {
+	let mut writer = Writer{ buffer: response, len: HEADER_SIZE, legacy: false };
+	let all = RECORDS.into_iter().fold(0, |records, record| records | record.bit());
+	let answers = self.put_records(&mut writer, all);
+	writer.finish([0, 0], 0, answers, 0)
+    }
+
+    /// Writes the `records` that fit, returning how many did.
+    fn put_records(&self, writer: &mut Writer, records: u8) -> u16 {
+	let mut count = 0;
+	for record in RECORDS.into_iter().filter(|record| records & record.bit() != 0) {
+	    if self.put_record(writer, record).is_none() {
+		break;
+	    }
+	    count += 1;
+	}
+	count
+    }
+
+    fn put_record(&self, writer: &mut Writer, record: Record) -> Option<()> {
+	let start = writer.len;
+	let result = self.write_record(writer, record);
+	if result.is_none() {
+	    // nothing of a record that didn't fit
+	    writer.len = start;
+	}
+	result
+    }
+
+    fn write_record(&self, writer: &mut Writer, record: Record) -> Option<()> {
+	writer.put_name(record.name())?;
+	writer.put(&record.kind().to_be_bytes())?;
+	// pointers are shared among the responders of the network, the rest
+	// is the pesebre's own
+	let class = match record.target() {
+	    None if !writer.legacy => CLASS_IN | CLASS_TOP_BIT,
+	    _ => CLASS_IN,
+	};
+	writer.put(&class.to_be_bytes())?;
+	let ttl = if writer.legacy { record.ttl().min(LEGACY_TTL) } else { record.ttl() };
+	writer.put(&ttl.to_be_bytes())?;
+	let len_at = writer.len;
+	writer.put(&[0, 0])?;
+	match record {
+	    Record::ServiceType | Record::Service => writer.put_name(record.target()?)?,
+	    Record::Location => {
+		// priority and weight, as there is only one
+		writer.put(&[0, 0, 0, 0])?;
+		writer.put(&self.port.to_be_bytes())?;
+		writer.put_name(HOSTNAME)?;
+	    }
+	    Record::Details => {
+		writer.put_text(&["version=", self.version])?;
+		writer.put_text(&["path=", API_PATH])?;
+	    }
+	    Record::Address => writer.put(&self.address)?,
+	}
+	let len = (writer.len - len_at - 2) as u16;
+	writer.buffer[len_at..len_at + 2].copy_from_slice(&len.to_be_bytes());
+	Some(())
+    }
+}
+
+/// Message being written.
+struct Writer<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    legacy: bool,
+}
+
+impl Writer<'_> {
+
+    fn put(&mut self, bytes: &[u8]) -> Option<()> {
+	self.buffer.get_mut(self.len..self.len + bytes.len())?.copy_from_slice(bytes);
+	self.len += bytes.len();
+	Some(())
+    }
+
+    fn put_name(&mut self, name: &str) -> Option<()> {
+	for label in name.split('.') {
+	    self.put(&[label.len() as u8])?;
+	    self.put(label.as_bytes())?;
+	}
+	self.put(&[0])
+    }
+
+    /// A string of a TXT record, out of `parts`.
+    fn put_text(&mut self, parts: &[&str]) -> Option<()> {
+	let len = parts.iter().map(|part| part.len()).sum::<usize>();
+	self.put(&[u8::try_from(len).ok()?])?;
+	for part in parts {
+	    self.put(part.as_bytes())?;
+	}
+	Some(())
+    }
+
+    /// Writes the header, returning the length of the message.
+    fn finish(self, id: [u8; 2], questions: u16, answers: u16, additional: u16) -> usize {
+	let header = &mut self.buffer[..HEADER_SIZE];
+	header[..2].copy_from_slice(&id);
+	header[2..4].copy_from_slice(&FLAG_RESPONSE.to_be_bytes());
+	header[4..6].copy_from_slice(&questions.to_be_bytes());
+	header[6..8].copy_from_slice(&answers.to_be_bytes());
+	header[8..10].copy_from_slice(&0_u16.to_be_bytes());
+	header[10..12].copy_from_slice(&additional.to_be_bytes());
+	self.len
+    }
+}
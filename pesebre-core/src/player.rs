@@ -0,0 +1,169 @@
+//! What the player task is doing.
+//!
+//! The player task keeps a [`PlayerState`] up to date with the commands it
+//! sends to the DFPlayer Mini and the notifications it gets back, and
+//! publishes every change in a [`StateView`] for the other tasks to read, or
+//! to wait for with a [`StateWatcher`].
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::pubsub::{self, PubSubChannel, Subscriber};
+use embassy_time::{Duration, Instant};
+
+use crate::catalog::Track;
+use crate::dfplayer_mini::ModuleError;
+
+/// Tasks that can wait for the player state to change at once: the web
+/// server, the lights and the scheduler.
+pub const STATE_WATCHERS : usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlayerState {
+    /// Nothing played yet, or the last track finished with nothing after it.
+    #[default]
+    Idle,
+    /// `track` is playing since `started_at`, discounting the pauses.
+    Playing { track: &'static Track, started_at: Instant },
+    /// `track` is paused `elapsed` into it.
+    Paused { track: &'static Track, elapsed: Duration },
+    /// Stopped on request.
+    Stopped,
+    /// The module reported an error.
+    Error(ModuleError),
+}
+
+impl PlayerState {
+
+    /// `track` started playing at `now`.
+    pub fn play(&mut self, track: &'static Track, now: Instant) {
+	*self = Self::Playing{ track, started_at: now };
+    }
+
+    /// Pauses the current track, if one is playing.
+    pub fn pause(&mut self, now: Instant) {
+	if let Self::Playing{ track, started_at } = *self {
+	    *self = Self::Paused{ track, elapsed: now - started_at };
+	}
+    }
+
+    /// Goes on with the paused track, if any.
+    pub fn resume(&mut self, now: Instant) {
+	if let Self::Paused{ track, elapsed } = *self {
+	    *self = Self::Playing{ track, started_at: now - elapsed };
+	}
+    }
+
+    pub fn stop(&mut self) {
+	*self = Self::Stopped;
+    }
+
+    /// The module finished playing track number `id`. Returns whether it was
+    /// the current track; the module reports the end of a track twice, so
+    /// only the first report changes the state.
+    pub fn finish(&mut self, id: u16) -> bool {
+	match self.track() {
+	    Some(track) if track.id == id => {
+		*self = Self::Idle;
+		true
+	    }
+	    _ => false,
+	}
+    }
+
+    pub fn fail(&mut self, error: ModuleError) {
+	*self = Self::Error(error);
+    }
+
+    /// Leaves the error, the module being back.
+    pub fn recover(&mut self) {
+	if let Self::Error(_) = self {
+	    *self = Self::Idle;
+	}
+    }
+
+    /// The module reported `error` on its own. Fails unless the error is
+    /// transient: a busy module or a garbled frame leave the track as it
+    /// was. Returns whether it failed.
+    pub fn report(&mut self, error: ModuleError) -> bool {
+	if error.is_transient() {
+	    return false;
+	}
+	self.fail(error);
+	true
+    }
+
+    /// Track playing or paused.
+    pub fn track(&self) -> Option<&'static Track> {
+	match *self {
+	    Self::Playing{ track, .. } | Self::Paused{ track, .. } => Some(track),
+	    _ => None,
+	}
+    }
+
+    /// How much of the current track has been played at `now`.
+    pub fn elapsed(&self, now: Instant) -> Option<Duration> {
+	match *self {
+	    Self::Playing{ started_at, .. } => Some(now - started_at),
+	    Self::Paused{ elapsed, .. } => Some(elapsed),
+	    _ => None,
+	}
+    }
+
+    pub fn is_paused(&self) -> bool {
+	matches!(self, Self::Paused{ .. })
+    }
+}
+
+/// Copy of the player state published by the player task.
+pub struct StateView {
+    state: Mutex<CriticalSectionRawMutex, Cell<PlayerState>>,
+    /// Wakes the watchers on every change.
+    changes: PubSubChannel<CriticalSectionRawMutex, PlayerState, 1, STATE_WATCHERS, 0>,
+}
+
+impl StateView {
+
+    pub const fn new() -> Self {
+	Self{ state: Mutex::new(Cell::new(PlayerState::Idle)), changes: PubSubChannel::new() }
+    }
+
+    /// Sets the state, waking the watchers if it changed.
+    pub fn publish(&self, state: PlayerState) {
+	if self.state.lock(|view| view.replace(state)) != state {
+	    self.changes.immediate_publisher().publish_immediate(state);
+	}
+    }
+
+    pub fn get(&self) -> PlayerState {
+	self.state.lock(Cell::get)
+    }
+
+    /// Follows the changes from now on, or fails when `STATE_WATCHERS`
+    /// already do.
+    pub fn watch(&self) -> Result<StateWatcher<'_>, pubsub::Error> {
+	Ok(StateWatcher{ view: self, changes: self.changes.subscriber()? })
+    }
+}
+
+impl Default for StateView {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+/// Task waiting for the player state to change.
+pub struct StateWatcher<'a> {
+    view: &'a StateView,
+    changes: Subscriber<'a, CriticalSectionRawMutex, PlayerState, 1, STATE_WATCHERS, 0>,
+}
+
+impl StateWatcher<'_> {
+    /// Waits for the next change and returns the state then, skipping the
+    /// changes in between.
+    pub async fn changed(&mut self) -> PlayerState {
+	self.changes.next_message().await;
+	while self.changes.try_next_message().is_some() {}
+	self.view.get()
+    }
+}
@@ -0,0 +1,138 @@
+//! Tracks lined up to play after the current one.
+//!
+//! The player task owns the [`Playlist`] and changes it on requests from the
+//! control panel; after every change it publishes a copy in a
+//! [`PlaylistView`] for the web server to list.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use heapless::Vec;
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+use crate::catalog::Track;
+
+/// Tracks the playlist holds at most.
+pub const PLAYLIST_SIZE : usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistError {
+    /// The playlist already holds `PLAYLIST_SIZE` tracks.
+    Full,
+    /// No track at the given position.
+    OutOfRange(usize),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Playlist {
+    tracks: Vec<&'static Track, PLAYLIST_SIZE>,
+}
+
+impl Playlist {
+
+    pub const fn new() -> Self {
+	Self{ tracks: Vec::new() }
+    }
+
+    /// Adds `track` at the end.
+    pub fn enqueue(&mut self, track: &'static Track) -> Result<(), PlaylistError> {
+	self.tracks.push(track).map_err(|_| PlaylistError::Full)
+    }
+
+    /// Adds `track` in front of the others, to play right after the current one.
+    pub fn play_next(&mut self, track: &'static Track) -> Result<(), PlaylistError> {
+	self.tracks.insert(0, track).map_err(|_| PlaylistError::Full)
+    }
+
+    /// Takes out the track at `position`, 0 being the next one.
+    pub fn remove(&mut self, position: usize) -> Result<&'static Track, PlaylistError> {
+	if position >= self.tracks.len() {
+	    return Err(PlaylistError::OutOfRange(position));
+	}
+	Ok(self.tracks.remove(position))
+    }
+
+    /// Moves the track at `from` to `to`, shifting the ones between.
+    pub fn reorder(&mut self, from: usize, to: usize) -> Result<(), PlaylistError> {
+	let len = self.tracks.len();
+	if from >= len {
+	    return Err(PlaylistError::OutOfRange(from));
+	}
+	if to >= len {
+	    return Err(PlaylistError::OutOfRange(to));
+	}
+
+	if from < to {
+	    self.tracks[from..=to].rotate_left(1);
+	} else {
+	    self.tracks[to..=from].rotate_right(1);
+	}
+	Ok(())
+    }
+
+    pub fn clear(&mut self) {
+	self.tracks.clear();
+    }
+
+    /// Takes out the next track to play.
+    pub fn pop_next(&mut self) -> Option<&'static Track> {
+	if self.tracks.is_empty() {
+	    None
+	} else {
+	    Some(self.tracks.remove(0))
+	}
+    }
+
+    pub fn len(&self) -> usize {
+	self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.tracks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'static Track> + '_ {
+	self.tracks.iter().copied()
+    }
+}
+
+/// Lists the tracks as `[{"id": 1, "title": "..."}, ...]`.
+impl Serialize for Playlist {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+	#[derive(serde::Serialize)]
+	struct Entry {
+	    id: u16,
+	    title: &'static str,
+	}
+
+	let mut seq = serializer.serialize_seq(Some(self.tracks.len()))?;
+	for track in self.iter() {
+	    seq.serialize_element(&Entry{ id: track.id, title: track.title })?;
+	}
+	seq.end()
+    }
+}
+
+/// Copy of the playlist published by the player task.
+pub struct PlaylistView(Mutex<CriticalSectionRawMutex, RefCell<Playlist>>);
+
+impl PlaylistView {
+
+    pub const fn new() -> Self {
+	Self(Mutex::new(RefCell::new(Playlist::new())))
+    }
+
+    pub fn publish(&self, playlist: &Playlist) {
+	self.0.lock(|view| view.borrow_mut().clone_from(playlist));
+    }
+
+    pub fn get(&self) -> Playlist {
+	self.0.lock(|view| view.borrow().clone())
+    }
+}
+
+impl Default for PlaylistView {
+    fn default() -> Self {
+	Self::new()
+    }
+}
@@ -0,0 +1,58 @@
+//! Captive portal of the access point. While in portal mode the DNS
+//! responder leads every name to the pesebre, so the requests phones and
+//! laptops make to check whether they are online land here; they get a
+//! redirect to the control panel instead of the answer they expect, and the
+//! system pops the control panel up as soon as they join.
+
+use picoserve::request::Path;
+use picoserve::response::{IntoResponse, Response, ResponseWriter, StatusCode};
+use picoserve::routing::PathDescription;
+use picoserve::ResponseSent;
+
+/// Where the connectivity checks are sent.
+pub const PORTAL_URL : &str = "http://192.168.2.1/";
+
+/// Paths requested by the connectivity checks of Android, Apple and
+/// Windows.
+pub const PROBES : [&str; 7] = [
+    "/generate_204",
+    "/gen_204",
+    "/hotspot-detect.html",
+    "/library/test/success.html",
+    "/connecttest.txt",
+    "/ncsi.txt",
+    "/redirect",
+];
+
+/// Path description matching any of the `PROBES`.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectivityProbes;
+
+impl<CurrentPathParameters> PathDescription<CurrentPathParameters> for ConnectivityProbes {
+    type Output = CurrentPathParameters;
+
+    fn parse_and_call<'r, T, F: FnOnce(Self::Output, Path<'r>) -> Result<T, Self::Output>>(
+	&self,
+	current_path_parameters: CurrentPathParameters,
+	path: Path<'r>,
+	f: F,
+    ) -> Result<T, CurrentPathParameters> {
+	match PROBES.into_iter().find(|probe| path == *probe) {
+	    Some(probe) => probe.parse_and_call(current_path_parameters, path, f),
+	    None => Err(current_path_parameters),
+	}
+    }
+}
+
+/// Answer to a connectivity check: off to the control panel.
+pub struct PortalRedirect;
+
+impl IntoResponse for PortalRedirect {
+    async fn write_to<W: ResponseWriter>(self, response_writer: W) -> Result<ResponseSent, W::Error> {
+	Response::new(StatusCode::new(302), "Pesebre Navideño: http://192.168.2.1/\n")
+	    .with_header("Location", PORTAL_URL)
+	    .with_header("Cache-Control", "no-store")
+	    .write_to(response_writer)
+	    .await
+    }
+}
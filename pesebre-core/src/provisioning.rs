@@ -0,0 +1,187 @@
+//! Setting the household network up from the access point. The `/setup`
+//! page lists the networks around, as scanned by the connection task, and
+//! hands it the one chosen, which is tried before being saved. The page
+//! follows the trial until the pesebre got an address there, then the
+//! pesebre leaves the access point for the household network.
+
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration};
+use heapless::{String, Vec};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::status::dotted;
+
+/// Networks listed, the strongest ones.
+pub const MAX_NETWORKS : usize = 16;
+
+/// Time a scan is waited for, before answering with the last one.
+pub const SCAN_TIMEOUT : Duration = Duration::from_secs(10);
+
+pub type Networks = Vec<Network, MAX_NETWORKS>;
+
+/// How a network keeps strangers out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    Open,
+    Wep,
+    Wpa,
+    Wpa2,
+    Wpa3,
+    /// WPA2 Enterprise, which the pesebre can't join.
+    Enterprise,
+}
+
+/// Network seen around.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Network {
+    pub ssid: String<32>,
+    /// Signal strength in dBm.
+    pub rssi: i8,
+    pub security: Security,
+}
+
+/// Network chosen on the page, to try.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Candidate {
+    pub ssid: String<32>,
+    /// Empty for open networks.
+    #[serde(default)]
+    pub password: String<64>,
+}
+
+/// How the trial of the network chosen goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trial {
+    /// Nothing tried yet.
+    Idle,
+    Testing,
+    /// Joined, with the address leased there.
+    Joined([u8; 4]),
+    Failed(&'static str),
+}
+
+/// As `{"state":"joined","address":"192.168.1.50"}`.
+impl Serialize for Trial {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+	let fields = match self {
+	    Self::Joined(_) | Self::Failed(_) => 2,
+	    _ => 1,
+	};
+	let mut data = serializer.serialize_struct("Trial", fields)?;
+	match *self {
+	    Self::Idle => data.serialize_field("state", "idle")?,
+	    Self::Testing => data.serialize_field("state", "testing")?,
+	    Self::Joined(address) => {
+		data.serialize_field("state", "joined")?;
+		data.serialize_field("address", &dotted(address))?;
+	    }
+	    Self::Failed(why) => {
+		data.serialize_field("state", "failed")?;
+		data.serialize_field("error", why)?;
+	    }
+	}
+	data.end()
+    }
+}
+
+/// Adds `network` to `networks`, strongest first, keeping only the
+/// strongest access point of each network and leaving hidden ones out.
+pub fn add_network(networks: &mut Networks, network: Network) {
+    if network.ssid.is_empty() {
+	return;
+    }
+    if let Some(position) = networks.iter().position(|known| known.ssid == network.ssid) {
+	if networks[position].rssi >= network.rssi {
+	    return;
+	}
+	networks.remove(position);
+    }
+    let position = networks.iter().position(|known| known.rssi < network.rssi).unwrap_or(networks.len());
+    if networks.is_full() {
+	if position == networks.len() {
+	    return;
+	}
+	networks.pop();
+    }
+    // room was made
+    let _ = networks.insert(position, network);
+}
+
+/// What the `/setup` page shares with the connection task.
+pub struct Provisioning {
+    networks: Mutex<CriticalSectionRawMutex, RefCell<Networks>>,
+    scan_requested: Signal<CriticalSectionRawMutex, ()>,
+    scanned: Signal<CriticalSectionRawMutex, ()>,
+    candidate: Signal<CriticalSectionRawMutex, Candidate>,
+    trial: Mutex<CriticalSectionRawMutex, Cell<Trial>>,
+}
+
+impl Provisioning {
+
+    pub const fn new() -> Self {
+	Self{
+	    networks: Mutex::new(RefCell::new(Vec::new())),
+	    scan_requested: Signal::new(),
+	    scanned: Signal::new(),
+	    candidate: Signal::new(),
+	    trial: Mutex::new(Cell::new(Trial::Idle)),
+	}
+    }
+
+    /// Has the networks around scanned, returning them, or the last ones
+    /// seen when the scan takes too long.
+    pub async fn scan(&self) -> Networks {
+	self.scanned.reset();
+	self.scan_requested.signal(());
+	if with_timeout(SCAN_TIMEOUT, self.scanned.wait()).await.is_err() {
+	    log::warn!("Wifi scan timed out");
+	}
+	self.networks()
+    }
+
+    /// Waits for a page to want a scan.
+    pub async fn scan_requested(&self) {
+	self.scan_requested.wait().await
+    }
+
+    /// Takes the networks just scanned.
+    pub fn publish_networks(&self, networks: &Networks) {
+	self.networks.lock(|view| view.borrow_mut().clone_from(networks));
+	self.scanned.signal(());
+    }
+
+    pub fn networks(&self) -> Networks {
+	self.networks.lock(|view| view.borrow().clone())
+    }
+
+    /// Has `candidate` tried.
+    pub fn try_network(&self, candidate: Candidate) {
+	self.report(Trial::Testing);
+	self.candidate.signal(candidate);
+    }
+
+    /// Waits for a network to try.
+    pub async fn candidate(&self) -> Candidate {
+	self.candidate.wait().await
+    }
+
+    /// Tells how the trial went.
+    pub fn report(&self, trial: Trial) {
+	self.trial.lock(|view| view.set(trial));
+    }
+
+    pub fn trial(&self) -> Trial {
+	self.trial.lock(Cell::get)
+    }
+}
+
+impl Default for Provisioning {
+    fn default() -> Self {
+	Self::new()
+    }
+}
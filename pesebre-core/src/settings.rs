@@ -0,0 +1,563 @@
+//! Settings kept across reboots in a few sectors of flash.
+//!
+//! Every save appends a record with all the settings to the sector in use,
+//! and moves on to the next sector, erasing it, once that one is full; so
+//! each sector is erased once per sector's worth of saves, and the record
+//! before is still there if power fails in the middle of a save. Records
+//! carry a sequence number, to find the latest, and a CRC-32; the settings
+//! in them are key/value entries, so that keys can be added without
+//! breaking the records already written.
+//!
+//! A record is laid out as
+//!
+//! | bytes | content |
+//! |-------|---------|
+//! | 1     | `0x50`, the magic |
+//! | 1     | format version |
+//! | 2     | length of the entries, little endian |
+//! | 4     | sequence number, little endian |
+//! | n     | entries: key, length of the value, value |
+//! | 4     | CRC-32 of all of the above, little endian |
+//!
+//! padded with `0xff` to the flash write size.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::signal::Signal;
+use embedded_storage::nor_flash::NorFlash;
+use heapless::{String, Vec};
+
+use crate::catalog;
+use crate::dfplayer_mini::Equalizer;
+use crate::volume::{DEFAULT_VOLUME, MAX_VOLUME};
+
+/// Format of the records written.
+pub const FORMAT_VERSION : u8 = 1;
+
+/// Times of the day the pesebre plays at most.
+pub const SCHEDULES : usize = 4;
+
+const RECORD_MAGIC : u8 = 0x50;
+const HEADER_SIZE : usize = 8;
+const CRC_SIZE : usize = 4;
+/// Longest record, padding included, for a write size up to 16.
+const MAX_RECORD_SIZE : usize = 528;
+const MAX_ENTRIES_SIZE : usize = MAX_RECORD_SIZE - HEADER_SIZE - CRC_SIZE - 16;
+
+/** Keys of the entries */
+const KEY_VOLUME : u8        = 1;
+const KEY_MAX_VOLUME : u8    = 2;
+const KEY_EQ : u8            = 3;
+const KEY_WIFI_SSID : u8     = 4;
+const KEY_WIFI_PASSWORD : u8 = 5;
+const KEY_LIGHT_PROGRAM : u8 = 6;
+const KEY_SCHEDULE : u8      = 7;
+const KEY_WELCOME_TRACK : u8 = 8;
+const KEY_AP_SSID : u8       = 9;
+const KEY_AP_PASSWORD : u8   = 10;
+const KEY_AP_CHANNEL : u8    = 11;
+const KEY_AP_HIDDEN : u8     = 12;
+const KEY_AP_MAX_CLIENTS : u8 = 13;
+const KEY_ADMIN_PASSWORD : u8 = 14;
+
+/// Name of the access point until another is chosen.
+pub const DEFAULT_AP_SSID : &str = "pesebre-navideño";
+
+/// Stations the access point takes at most, as many as the ESP32-C3 does.
+pub const MAX_AP_CLIENTS : u8 = 10;
+
+/// Part of the day the pesebre plays, in minutes after midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    pub start: u16,
+    pub end: u16,
+}
+
+/// Access point the pesebre sets up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPointSettings {
+    /// Name of the network, `DEFAULT_AP_SSID` while empty.
+    pub ssid: String<32>,
+    /// WPA2 passphrase, of 8 to 63 characters. The network is open while it
+    /// is empty, which only the first boot should see.
+    pub password: String<64>,
+    /// From 1 to 13.
+    pub channel: u8,
+    /// Leaves the name out of the beacons.
+    pub hidden: bool,
+    /// Stations taken at once, from 1 to `MAX_AP_CLIENTS`.
+    pub max_clients: u8,
+}
+
+impl AccessPointSettings {
+
+    /// Open network of the first boot.
+    pub const fn new() -> Self {
+	Self{ ssid: String::new(), password: String::new(), channel: 1, hidden: false, max_clients: 4 }
+    }
+
+    /// Name of the network.
+    pub fn ssid(&self) -> &str {
+	if self.ssid.is_empty() { DEFAULT_AP_SSID } else { &self.ssid }
+    }
+
+    pub fn is_open(&self) -> bool {
+	self.password.is_empty()
+    }
+
+    /// Why the access point can't be set up this way, if it can't.
+    pub fn check(&self) -> Result<(), &'static str> {
+	if !self.is_open() && !is_passphrase(&self.password) {
+	    return Err("password must be 8 to 63 printable ASCII characters");
+	}
+	if !(1..=13).contains(&self.channel) {
+	    return Err("channel must be 1 to 13");
+	}
+	if !(1..=MAX_AP_CLIENTS).contains(&self.max_clients) {
+	    return Err("max clients must be 1 to 10");
+	}
+	Ok(())
+    }
+}
+
+impl Default for AccessPointSettings {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+/// Whether `password` can be a WPA2 passphrase.
+pub fn is_passphrase(password: &str) -> bool {
+    (8..=63).contains(&password.len()) && password.bytes().all(|byte| (b' '..=b'~').contains(&byte))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Volume chosen, from 0 to 30.
+    pub volume: u8,
+    /// Highest volume allowed.
+    pub max_volume: u8,
+    pub eq: Equalizer,
+    /// Network of the household, to join instead of being the access point.
+    pub wifi_ssid: String<32>,
+    pub wifi_password: String<64>,
+    /// Blinking pattern of the lights.
+    pub light_program: u8,
+    pub schedules: Vec<Schedule, SCHEDULES>,
+    /// Track played when the pesebre starts, if any.
+    pub welcome_track: Option<u16>,
+    pub access_point: AccessPointSettings,
+    /// Password of the settings. While it is empty the settings page only
+    /// takes it, and only over the open access point of the first boot.
+    pub admin_password: String<64>,
+}
+
+impl Settings {
+
+    /// Settings of the first boot.
+    pub const fn new() -> Self {
+	Self{
+	    volume: DEFAULT_VOLUME,
+	    max_volume: MAX_VOLUME,
+	    eq: Equalizer::Normal,
+	    wifi_ssid: String::new(),
+	    wifi_password: String::new(),
+	    light_program: 0,
+	    schedules: Vec::new(),
+	    welcome_track: Some(catalog::WELCOME),
+	    access_point: AccessPointSettings::new(),
+	    admin_password: String::new(),
+	}
+    }
+
+    /// Writes the entries into `buffer`, returning their length.
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+	let mut entries = Entries{ buffer, len: 0 };
+	entries.put(KEY_VOLUME, &[self.volume])?;
+	entries.put(KEY_MAX_VOLUME, &[self.max_volume])?;
+	entries.put(KEY_EQ, &[self.eq.into()])?;
+	entries.put(KEY_WIFI_SSID, self.wifi_ssid.as_bytes())?;
+	entries.put(KEY_WIFI_PASSWORD, self.wifi_password.as_bytes())?;
+	entries.put(KEY_LIGHT_PROGRAM, &[self.light_program])?;
+	for schedule in &self.schedules {
+	    let [start_lo, start_hi] = schedule.start.to_le_bytes();
+	    let [end_lo, end_hi] = schedule.end.to_le_bytes();
+	    entries.put(KEY_SCHEDULE, &[start_lo, start_hi, end_lo, end_hi])?;
+	}
+	match self.welcome_track {
+	    Some(track) => entries.put(KEY_WELCOME_TRACK, &track.to_le_bytes())?,
+	    None => entries.put(KEY_WELCOME_TRACK, &[])?,
+	}
+	let ap = &self.access_point;
+	entries.put(KEY_AP_SSID, ap.ssid.as_bytes())?;
+	entries.put(KEY_AP_PASSWORD, ap.password.as_bytes())?;
+	entries.put(KEY_AP_CHANNEL, &[ap.channel])?;
+	entries.put(KEY_AP_HIDDEN, &[ap.hidden.into()])?;
+	entries.put(KEY_AP_MAX_CLIENTS, &[ap.max_clients])?;
+	entries.put(KEY_ADMIN_PASSWORD, self.admin_password.as_bytes())?;
+	Some(entries.len)
+    }
+
+    /// Settings in `entries`. Missing or unknown keys and values that don't
+    /// fit are left out, keeping the first boot setting.
+    fn decode(mut entries: &[u8]) -> Self {
+	let mut settings = Self::new();
+	while let [key, len, rest @ ..] = entries {
+	    if rest.len() < *len as usize {
+		break;
+	    }
+	    let (value, rest) = rest.split_at(*len as usize);
+	    entries = rest;
+
+	    match (*key, value) {
+		(KEY_VOLUME, &[volume]) => settings.volume = volume.min(MAX_VOLUME),
+		(KEY_MAX_VOLUME, &[max]) => settings.max_volume = max.min(MAX_VOLUME),
+		(KEY_EQ, &[eq]) => settings.eq = Equalizer::try_from(eq).unwrap_or(Equalizer::Normal),
+		(KEY_WIFI_SSID, ssid) => settings.wifi_ssid = text(ssid).unwrap_or_default(),
+		(KEY_WIFI_PASSWORD, password) => settings.wifi_password = text(password).unwrap_or_default(),
+		(KEY_LIGHT_PROGRAM, &[program]) => settings.light_program = program,
+		(KEY_SCHEDULE, &[start_lo, start_hi, end_lo, end_hi]) => {
+		    let start = u16::from_le_bytes([start_lo, start_hi]);
+		    let end = u16::from_le_bytes([end_lo, end_hi]);
+		    let _ = settings.schedules.push(Schedule{ start, end });
+		}
+		(KEY_WELCOME_TRACK, &[]) => settings.welcome_track = None,
+		(KEY_WELCOME_TRACK, &[lo, hi]) => settings.welcome_track = Some(u16::from_le_bytes([lo, hi])),
+		(KEY_AP_SSID, ssid) => settings.access_point.ssid = text(ssid).unwrap_or_default(),
+		(KEY_AP_PASSWORD, password) => settings.access_point.password = text(password).unwrap_or_default(),
+		(KEY_AP_CHANNEL, &[channel]) => settings.access_point.channel = channel,
+		(KEY_AP_HIDDEN, &[hidden]) => settings.access_point.hidden = hidden != 0,
+		(KEY_AP_MAX_CLIENTS, &[max]) => settings.access_point.max_clients = max,
+		(KEY_ADMIN_PASSWORD, password) => settings.admin_password = text(password).unwrap_or_default(),
+		(key, _) => log::warn!("Setting {key} skipped"),
+	    }
+	}
+	if let Err(why) = settings.access_point.check() {
+	    log::warn!("Access point settings dropped: {why}");
+	    settings.access_point = AccessPointSettings::new();
+	}
+	settings
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+/// `value` as a string of up to `N` bytes.
+fn text<const N: usize>(value: &[u8]) -> Option<String<N>> {
+    let mut text = String::new();
+    text.push_str(core::str::from_utf8(value).ok()?).ok()?;
+    Some(text)
+}
+
+/// Entries being written into a record.
+struct Entries<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Entries<'_> {
+    fn put(&mut self, key: u8, value: &[u8]) -> Option<()> {
+	let end = self.len + 2 + value.len();
+	let entry = self.buffer.get_mut(self.len..end)?;
+	entry[0] = key;
+	entry[1] = value.len().try_into().ok()?;
+	entry[2..].copy_from_slice(value);
+	self.len = end;
+	Some(())
+    }
+}
+
+/// CRC-32 of `data`, as in Ethernet and zip.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in data {
+	crc ^= *byte as u32;
+	for _ in 0..8 {
+	    crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+	}
+    }
+    !crc
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsError<E> {
+    Flash(E),
+    /// The settings don't fit in a record.
+    TooBig,
+}
+
+/// Record found in flash.
+struct Record {
+    sequence: u32,
+    /// Bytes taken in flash, padding included.
+    size: u32,
+    settings: Settings,
+}
+
+/// What the start of a record slot holds.
+// short-lived, and there is no heap to box the record in
+#[allow(clippy::large_enum_variant)]
+enum Slot {
+    Record(Record),
+    /// Never written since the sector was erased.
+    Erased,
+    /// Neither a record nor erased: a save cut short, or foreign data.
+    Broken,
+}
+
+/// Settings stored in `sectors` flash sectors from `offset`.
+pub struct SettingsStore<F> {
+    flash: F,
+    offset: u32,
+    sectors: u32,
+    /// Sector the records go to.
+    sector: u32,
+    /// Where the next record goes, if the sector can take it.
+    head: Option<u32>,
+    /// Sequence number of the last record.
+    sequence: u32,
+    /// Settings in the last record.
+    saved: Option<Settings>,
+}
+
+impl<F: NorFlash> SettingsStore<F> {
+
+    /// Store in `sectors` sectors of `flash` from `offset`, which has to be
+    /// at the start of a sector. It takes two sectors at least, so that
+    /// there is always a record left while the next sector is erased.
+    pub fn new(flash: F, offset: u32, sectors: u32) -> Self {
+	assert!(sectors >= 2, "settings need two sectors");
+	assert!(offset % F::ERASE_SIZE as u32 == 0, "settings start mid-sector");
+	Self{
+	    flash,
+	    offset,
+	    sectors,
+	    sector: sectors - 1,
+	    head: None,
+	    sequence: 0,
+	    saved: None,
+	}
+    }
+
+    /// The flash, back.
+    pub fn release(self) -> F {
+	self.flash
+    }
+
+    /// Alignment of the records.
+    fn alignment() -> usize {
+	F::WRITE_SIZE.max(F::READ_SIZE).max(1)
+    }
+
+    fn sector_start(&self, sector: u32) -> u32 {
+	self.offset + sector * F::ERASE_SIZE as u32
+    }
+
+    /// Latest settings saved, or the first boot ones if none can be read.
+    pub fn load(&mut self) -> Settings {
+	// the latest record, its sector, where it ends and whether the next
+	// record can follow it
+	let mut latest: Option<(Record, u32, u32, bool)> = None;
+
+	for sector in 0..self.sectors {
+	    let end = self.sector_start(sector + 1);
+	    let mut position = self.sector_start(sector);
+	    let mut clean = true;
+	    while position + HEADER_SIZE as u32 <= end {
+		match self.read_slot(position, end) {
+		    Slot::Record(record) => {
+			let size = record.size;
+			if latest.as_ref().map_or(true, |(best, ..)| record.sequence > best.sequence) {
+			    latest = Some((record, sector, position + size, false));
+			}
+			position += size;
+		    }
+		    Slot::Erased => break,
+		    Slot::Broken => {
+			log::warn!("Settings sector {sector} broken at {position:#x}");
+			clean = false;
+			break;
+		    }
+		}
+	    }
+	    if let Some((_, latest_sector, latest_end, appendable)) = &mut latest {
+		if *latest_sector == sector {
+		    *appendable = clean && *latest_end == position;
+		}
+	    }
+	}
+
+	match latest {
+	    Some((record, sector, end, appendable)) => {
+		log::info!("Settings #{} loaded from sector {sector}", record.sequence);
+		self.sector = sector;
+		self.head = appendable.then_some(end);
+		self.sequence = record.sequence;
+		self.saved = Some(record.settings.clone());
+		record.settings
+	    }
+	    None => {
+		log::info!("No settings saved, using the defaults");
+		self.sector = self.sectors - 1;
+		self.head = None;
+		self.sequence = 0;
+		self.saved = None;
+		Settings::new()
+	    }
+	}
+    }
+
+    /// The record slot at `position`, in a sector ending at `end`.
+    fn read_slot(&mut self, position: u32, end: u32) -> Slot {
+	let mut record = [0; MAX_RECORD_SIZE];
+	let Ok(()) = self.flash.read(position, &mut record[..HEADER_SIZE]) else {
+	    return Slot::Broken;
+	};
+	if record[..HEADER_SIZE].iter().all(|byte| *byte == 0xff) {
+	    return Slot::Erased;
+	}
+	let [magic, version, len_lo, len_hi, ..] = record;
+	let len = u16::from_le_bytes([len_lo, len_hi]) as usize;
+	if magic != RECORD_MAGIC || version != FORMAT_VERSION || len > MAX_ENTRIES_SIZE {
+	    return Slot::Broken;
+	}
+
+	let size = padded(HEADER_SIZE + len + CRC_SIZE, Self::alignment());
+	if position + size as u32 > end
+	    || self.flash.read(position + HEADER_SIZE as u32, &mut record[HEADER_SIZE..size]).is_err()
+	{
+	    return Slot::Broken;
+	}
+	let (data, crc) = record[..HEADER_SIZE + len + CRC_SIZE].split_at(HEADER_SIZE + len);
+	if crc32(data).to_le_bytes() != crc {
+	    return Slot::Broken;
+	}
+
+	Slot::Record(Record{
+	    sequence: u32::from_le_bytes([record[4], record[5], record[6], record[7]]),
+	    size: size as u32,
+	    settings: Settings::decode(&data[HEADER_SIZE..]),
+	})
+    }
+
+    /// Saves `settings`, unless they are the ones saved last.
+    pub fn save(&mut self, settings: &Settings) -> Result<(), SettingsError<F::Error>> {
+	if self.saved.as_ref() == Some(settings) {
+	    return Ok(());
+	}
+
+	let mut record = [0xff; MAX_RECORD_SIZE];
+	let len = settings.encode(&mut record[HEADER_SIZE..HEADER_SIZE + MAX_ENTRIES_SIZE])
+	    .ok_or(SettingsError::TooBig)?;
+	let sequence = self.sequence.wrapping_add(1);
+	let [len_lo, len_hi] = (len as u16).to_le_bytes();
+	record[..4].copy_from_slice(&[RECORD_MAGIC, FORMAT_VERSION, len_lo, len_hi]);
+	record[4..HEADER_SIZE].copy_from_slice(&sequence.to_le_bytes());
+	let crc = crc32(&record[..HEADER_SIZE + len]);
+	record[HEADER_SIZE + len..HEADER_SIZE + len + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+	let size = padded(HEADER_SIZE + len + CRC_SIZE, Self::alignment());
+
+	let head = match self.head {
+	    Some(head) if head + size as u32 <= self.sector_start(self.sector + 1) => head,
+	    _ => {
+		let sector = (self.sector + 1) % self.sectors;
+		let start = self.sector_start(sector);
+		self.head = None;
+		self.flash.erase(start, start + F::ERASE_SIZE as u32).map_err(SettingsError::Flash)?;
+		self.sector = sector;
+		start
+	    }
+	};
+	// a failed write leaves the slot broken, so the next record goes to
+	// another sector
+	self.head = None;
+	self.flash.write(head, &record[..size]).map_err(SettingsError::Flash)?;
+
+	log::info!("Settings #{sequence} saved in sector {}", self.sector);
+	self.head = Some(head + size as u32);
+	self.sequence = sequence;
+	self.saved = Some(settings.clone());
+	Ok(())
+    }
+}
+
+/// `len` rounded up to a multiple of `alignment`.
+fn padded(len: usize, alignment: usize) -> usize {
+    len.div_ceil(alignment) * alignment
+}
+
+/// Copy of the settings shared by the firmware tasks.
+pub struct SettingsView {
+    settings: Mutex<CriticalSectionRawMutex, RefCell<Settings>>,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+    access_point_changed: Signal<CriticalSectionRawMutex, ()>,
+    network_changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl SettingsView {
+
+    pub const fn new() -> Self {
+	Self{
+	    settings: Mutex::new(RefCell::new(Settings::new())),
+	    changed: Signal::new(),
+	    access_point_changed: Signal::new(),
+	    network_changed: Signal::new(),
+	}
+    }
+
+    /// Takes the settings loaded from flash.
+    pub fn publish(&self, settings: &Settings) {
+	self.settings.lock(|view| view.borrow_mut().clone_from(settings));
+    }
+
+    pub fn get(&self) -> Settings {
+	self.settings.lock(|view| view.borrow().clone())
+    }
+
+    /// Changes some of the settings, to be saved.
+    pub fn update(&self, f: impl FnOnce(&mut Settings)) {
+	self.settings.lock(|view| f(&mut view.borrow_mut()));
+	self.changed.signal(());
+    }
+
+    /// Changes the access point, to be saved and set up again.
+    pub fn update_access_point(&self, access_point: AccessPointSettings) {
+	self.update(|settings| settings.access_point = access_point);
+	self.access_point_changed.signal(());
+    }
+
+    /// Changes the household network to join, to be saved and joined.
+    pub fn update_network(&self, ssid: String<32>, password: String<64>) {
+	self.update(|settings| {
+	    settings.wifi_ssid = ssid;
+	    settings.wifi_password = password;
+	});
+	self.network_changed.signal(());
+    }
+
+    /// Waits for a change to save.
+    pub async fn changed(&self) {
+	self.changed.wait().await
+    }
+
+    /// Waits for a change of the access point.
+    pub async fn access_point_changed(&self) {
+	self.access_point_changed.wait().await
+    }
+
+    /// Waits for a change of the household network.
+    pub async fn network_changed(&self) {
+	self.network_changed.wait().await
+    }
+}
+
+impl Default for SettingsView {
+    fn default() -> Self {
+	Self::new()
+    }
+}
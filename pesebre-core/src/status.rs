@@ -0,0 +1,159 @@
+//! Snapshot of the pesebre served as `GET /api/status`.
+
+use core::cell::Cell;
+use core::fmt::Write;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+use heapless::String;
+use serde::{Serialize, Serializer};
+
+use crate::control::Control;
+use crate::dfplayer_mini::{Device, Equalizer};
+use crate::player::PlayerState;
+use crate::volume::MAX_VOLUME;
+
+/// What the firmware learns about the module and the board, besides the
+/// player state. Each task fills in what it knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Volume chosen, from 0 to 30, kept while muted.
+    pub volume: u8,
+    pub muted: bool,
+    /// Highest volume allowed.
+    pub max_volume: u8,
+    pub eq: Option<Equalizer>,
+    /// Storage the module plays from.
+    pub source: Option<Device>,
+    /// Firmware version reported by the module.
+    pub firmware_version: Option<u16>,
+    /// Wi-Fi stations connected to the access point.
+    pub stations: u8,
+    /// Address in the household network, while on it.
+    pub station_address: Option<[u8; 4]>,
+    /// The SD card was taken out of the module and not put back.
+    pub card_removed: bool,
+}
+
+impl DeviceInfo {
+
+    /// Nothing known yet.
+    pub const fn new() -> Self {
+	Self{
+	    volume: 0,
+	    muted: false,
+	    max_volume: MAX_VOLUME,
+	    eq: None,
+	    source: None,
+	    firmware_version: None,
+	    stations: 0,
+	    station_address: None,
+	    card_removed: false,
+	}
+    }
+}
+
+impl Default for DeviceInfo {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+/// Copy of the device information shared by the firmware tasks.
+pub struct DeviceView(Mutex<CriticalSectionRawMutex, Cell<DeviceInfo>>);
+
+impl DeviceView {
+
+    pub const fn new() -> Self {
+	Self(Mutex::new(Cell::new(DeviceInfo::new())))
+    }
+
+    /// Changes some of the information, leaving the rest as it was.
+    pub fn update(&self, f: impl FnOnce(&mut DeviceInfo)) {
+	self.0.lock(|view| {
+	    let mut info = view.get();
+	    f(&mut info);
+	    view.set(info);
+	});
+    }
+
+    pub fn get(&self) -> DeviceInfo {
+	self.0.lock(Cell::get)
+    }
+}
+
+impl Default for DeviceView {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TrackInfo {
+    pub id: u16,
+    pub title: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Status {
+    /// `idle`, `playing`, `paused`, `stopped` or `error`.
+    pub state: &'static str,
+    pub track: Option<TrackInfo>,
+    pub elapsed_ms: Option<u64>,
+    pub volume: u8,
+    pub muted: bool,
+    pub max_volume: u8,
+    pub eq: Option<Equalizer>,
+    pub source: Option<Device>,
+    /// Tracks waiting in the playlist.
+    pub queue_length: usize,
+    pub uptime_s: u64,
+    pub stations: u8,
+    /// Address in the household network, dotted.
+    #[serde(serialize_with = "serialize_address")]
+    pub station_address: Option<[u8; 4]>,
+    pub firmware_version: Option<u16>,
+}
+
+impl Status {
+    /// Gathers what `control` holds at `now`.
+    pub fn new(control: &Control, now: Instant) -> Self {
+	let state = control.state.get();
+	let device = control.device.get();
+
+	Self{
+	    state: match state {
+		PlayerState::Idle => "idle",
+		PlayerState::Playing{ .. } => "playing",
+		PlayerState::Paused{ .. } => "paused",
+		PlayerState::Stopped => "stopped",
+		PlayerState::Error(_) => "error",
+	    },
+	    track: state.track().map(|track| TrackInfo{ id: track.id, title: track.title }),
+	    elapsed_ms: state.elapsed(now).map(|elapsed| elapsed.as_millis()),
+	    volume: device.volume,
+	    muted: device.muted,
+	    max_volume: device.max_volume,
+	    eq: device.eq,
+	    source: device.source,
+	    queue_length: control.playlist.get().len(),
+	    uptime_s: now.as_secs(),
+	    stations: device.stations,
+	    station_address: device.station_address,
+	    firmware_version: device.firmware_version,
+	}
+    }
+}
+
+/// `address` as written by people.
+pub fn dotted(address: [u8; 4]) -> String<15> {
+    let [a, b, c, d] = address;
+    let mut text = String::new();
+    // at most 15 characters
+    let _ = write!(text, "{a}.{b}.{c}.{d}");
+    text
+}
+
+fn serialize_address<S: Serializer>(address: &Option<[u8; 4]>, serializer: S) -> Result<S::Ok, S::Error> {
+    address.map(dotted).serialize(serializer)
+}
@@ -0,0 +1,104 @@
+//! Volume of the pesebre, kept within what the DFPlayer Mini takes and
+//! under a cap that can be lowered for the late-night novenas.
+
+/// Highest volume of the module.
+pub const MAX_VOLUME : u8 = 30;
+
+/// Volume on the first boot.
+pub const DEFAULT_VOLUME : u8 = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VolumeControl {
+    /// Level chosen, kept while muted.
+    level: u8,
+    /// Highest level allowed.
+    max: u8,
+    muted: bool,
+}
+
+impl VolumeControl {
+
+    pub const fn new() -> Self {
+	Self{ level: DEFAULT_VOLUME, max: MAX_VOLUME, muted: false }
+    }
+
+    /// Volume saved before, kept within the bounds.
+    pub fn restore(level: u8, max: u8) -> Self {
+	let mut volume = Self::new();
+	volume.set_max(max);
+	volume.set(level);
+	volume
+    }
+
+    /// Level chosen, whether muted or not.
+    pub fn level(&self) -> u8 {
+	self.level
+    }
+
+    pub fn max(&self) -> u8 {
+	self.max
+    }
+
+    pub fn is_muted(&self) -> bool {
+	self.muted
+    }
+
+    /// Volume to set in the module.
+    pub fn output(&self) -> u8 {
+	if self.muted { 0 } else { self.level }
+    }
+
+    /// Sets the level, up to the cap. Unmutes.
+    pub fn set(&mut self, level: u8) {
+	self.level = level.min(self.max);
+	self.muted = false;
+    }
+
+    /// One step up, up to the cap. Unmutes.
+    pub fn increase(&mut self) {
+	self.set(self.level.saturating_add(1));
+    }
+
+    /// One step down, down to 0. Unmutes.
+    pub fn decrease(&mut self) {
+	self.set(self.level.saturating_sub(1));
+    }
+
+    /// Silences the module, remembering the level.
+    pub fn mute(&mut self) {
+	self.muted = true;
+    }
+
+    /// Goes back to the level before muting.
+    pub fn unmute(&mut self) {
+	self.muted = false;
+    }
+
+    /// Caps the level at `max`, lowering it if it was above.
+    pub fn set_max(&mut self, max: u8) {
+	self.max = max.min(MAX_VOLUME);
+	self.level = self.level.min(self.max);
+    }
+
+    /// Changes what is given: the cap first, so that the level is kept
+    /// under the new one, then the level and the mute.
+    pub fn adjust(&mut self, level: Option<u8>, muted: Option<bool>, max: Option<u8>) {
+	if let Some(max) = max {
+	    self.set_max(max);
+	}
+	if let Some(level) = level {
+	    self.set(level);
+	}
+	match muted {
+	    Some(true) => self.mute(),
+	    Some(false) => self.unmute(),
+	    None => (),
+	}
+    }
+}
+
+impl Default for VolumeControl {
+    fn default() -> Self {
+	Self::new()
+    }
+}
@@ -0,0 +1,188 @@
+//! Control panel over a WebSocket at `/ws`.
+//!
+//! The page sends commands as small JSON objects, e.g.
+//! `{"cmd":"play","track":12}` or `{"cmd":"volume","value":20}`, and gets
+//! back the same events `GET /api/events` streams, as JSON text messages.
+//! Commands are checked as the REST interface checks them, see [`crate::api`],
+//! and those that can't be carried out are answered with `{"error":"..."}`.
+
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::mutex::Mutex;
+use embassy_sync::pubsub::{Subscriber, WaitResult};
+use embassy_time::{with_timeout, Duration};
+use picoserve::io::{Read, Write};
+use picoserve::response::ws::{
+    Message, ReadFrameError, ReadMessageError, SocketRx, SocketTx, WebSocketCallback,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::api::{self, ApiError, VolumeRequest};
+use crate::control::{Control, ControlMessages};
+use crate::events::{PanelEvent, EVENT_STREAMS, PANEL_EVENT_QUEUE_SIZE};
+
+/// Longest command taken, in bytes.
+const COMMAND_SIZE : usize = 128;
+
+/// Pause after which a ping goes out, to find out about pages gone away.
+const PING_PERIOD : Duration = Duration::from_secs(5);
+
+/** Close codes of RFC 6455 */
+const CLOSE_NORMAL : u16         = 1000;
+const CLOSE_PROTOCOL_ERROR : u16 = 1002;
+const CLOSE_TOO_BIG : u16        = 1009;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    /// Not a JSON object with a `cmd` string.
+    Malformed,
+    UnknownCommand,
+    /// The command lacks the named field.
+    MissingField(&'static str),
+    /// Refused as the REST interface refuses it.
+    Rejected(ApiError),
+}
+
+impl CommandError {
+    fn message(&self) -> &'static str {
+	match self {
+	    Self::Malformed => "malformed command",
+	    Self::UnknownCommand => "unknown command",
+	    Self::MissingField("track") => "missing track",
+	    Self::MissingField("value") => "missing value",
+	    Self::MissingField("position") => "missing position",
+	    Self::MissingField(_) => "missing from or to",
+	    Self::Rejected(why) => why.message(),
+	}
+    }
+}
+
+/// Fields a command may carry; which ones it needs depends on `cmd`.
+#[derive(Deserialize)]
+struct Command<'a> {
+    cmd: &'a str,
+    track: Option<u16>,
+    value: Option<u8>,
+    position: Option<usize>,
+    from: Option<usize>,
+    to: Option<usize>,
+}
+
+/// Hands a command of the page to the player task, through the same checks
+/// as the REST interface.
+pub fn run_command(control: &Control, text: &str) -> Result<(), CommandError> {
+    let (command, _) = serde_json_core::from_str::<Command>(text).map_err(|_| CommandError::Malformed)?;
+
+    let track = || command.track.ok_or(CommandError::MissingField("track"));
+    let value = || command.value.ok_or(CommandError::MissingField("value"));
+    let field = |value: Option<usize>, name| value.ok_or(CommandError::MissingField(name));
+    let volume = |volume, muted, max| VolumeRequest{ volume, muted, max };
+
+    match command.cmd {
+	"play" => api::play(control, track()?),
+	"pause" => api::pause(control),
+	"resume" => api::resume(control),
+	"stop" => api::stop(control),
+	"volume" => api::set_volume(control, volume(Some(value()?), None, None)),
+	"inc-vol" => api::step_volume(control, ControlMessages::IncVol),
+	"dec-vol" => api::step_volume(control, ControlMessages::DecVol),
+	"mute" => api::set_volume(control, volume(None, Some(true), None)),
+	"unmute" => api::set_volume(control, volume(None, Some(false), None)),
+	"max-volume" => api::set_volume(control, volume(None, None, Some(value()?))),
+	"enqueue" => api::enqueue(control, track()?),
+	"play-next" => api::play_next(control, track()?),
+	"remove" => api::remove(control, field(command.position, "position")?),
+	"move" => api::reorder(control, field(command.from, "from")?, field(command.to, "to")?),
+	"clear" => api::clear_playlist(control),
+	"skip" => api::skip(control),
+	_ => return Err(CommandError::UnknownCommand),
+    }.map(|_| ()).map_err(CommandError::Rejected)
+}
+
+#[derive(Serialize)]
+struct ErrorReply {
+    error: &'static str,
+}
+
+#[derive(Serialize)]
+struct Lagged {
+    event: &'static str,
+}
+
+/// One page connected through the WebSocket.
+pub(crate) struct ControlSocket {
+    pub(crate) control: &'static Control,
+    pub(crate) events: Subscriber<'static, CriticalSectionRawMutex, PanelEvent, PANEL_EVENT_QUEUE_SIZE, EVENT_STREAMS, 0>,
+}
+
+impl WebSocketCallback for ControlSocket {
+    async fn run<R: Read, W: Write<Error = R::Error>>(
+	self,
+	mut rx: SocketRx<R>,
+	tx: SocketTx<W>,
+    ) -> Result<(), W::Error> {
+	let Self{ control, mut events } = self;
+	// commands are answered and events pushed at the same time
+	let tx = Mutex::<NoopRawMutex, _>::new(tx);
+
+	let reason = {
+	    let commands = async {
+		let mut buffer = [0; COMMAND_SIZE];
+		loop {
+		    match rx.next_message(&mut buffer).await {
+			Ok(Message::Text(text)) => match run_command(control, text) {
+			    Ok(()) => (),
+			    Err(why) => {
+				log::warn!("WebSocket command {text} rejected: {why:?}");
+				tx.lock().await.send_json(ErrorReply{ error: why.message() }).await?;
+			    }
+			},
+			Ok(Message::Binary(_)) => {
+			    tx.lock().await.send_json(ErrorReply{ error: CommandError::Malformed.message() }).await?;
+			}
+			Ok(Message::Ping(data)) => tx.lock().await.send_pong(data).await?,
+			Ok(Message::Pong(_)) => (),
+			Ok(Message::Close(_)) => return Ok((CLOSE_NORMAL, "")),
+			Err(ReadMessageError::Io(why)) => return Err(why),
+			Err(ReadMessageError::ReadFrameError(ReadFrameError::Io(why))) => return Err(why),
+			Err(ReadMessageError::ReadFrameError(ReadFrameError::OutOfSpace)) => {
+			    return Ok((CLOSE_TOO_BIG, "command too long"));
+			}
+			Err(why) => {
+			    log::warn!("WebSocket broken message: {why:?}");
+			    return Ok((CLOSE_PROTOCOL_ERROR, ""));
+			}
+		    }
+		}
+	    };
+
+	    // only ends when the page can't be written to
+	    let pushes = async {
+		loop {
+		    let sent = match with_timeout(PING_PERIOD, events.next_message()).await {
+			Ok(WaitResult::Message(event)) => tx.lock().await.send_json(event).await,
+			Ok(WaitResult::Lagged(missed)) => {
+			    log::warn!("WebSocket missed {missed} events");
+			    tx.lock().await.send_json(Lagged{ event: "lagged" }).await
+			}
+			Err(_) => tx.lock().await.send_ping(&[]).await,
+		    };
+		    if let Err(why) = sent {
+			break why;
+		    }
+		}
+	    };
+
+	    let mut commands = pin!(commands);
+	    let mut pushes = pin!(pushes);
+	    poll_fn(|cx| match commands.as_mut().poll(cx) {
+		Poll::Ready(reason) => Poll::Ready(reason),
+		Poll::Pending => pushes.as_mut().poll(cx).map(Err),
+	    }).await
+	}?;
+	tx.into_inner().close(reason).await
+    }
+}
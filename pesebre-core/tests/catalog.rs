@@ -0,0 +1,48 @@
+use pesebre_core::catalog::{self, Category, TRACKS, WELCOME};
+
+#[test]
+fn tracks_are_sorted_and_unique() {
+    assert!(TRACKS.windows(2).all(|pair| pair[0].id < pair[1].id));
+}
+
+#[test]
+fn tracks_are_found_by_id() {
+    assert_eq!(catalog::find(1).map(|t| t.title), Some("A La Nanita Nana"));
+    assert_eq!(catalog::find(26).map(|t| t.title), Some("Agua"));
+    assert_eq!(catalog::find(19).map(|t| t.category), Some(Category::Jingle));
+    assert_eq!(catalog::find(23).map(|t| t.title), Some("Buey"));
+    assert_eq!(catalog::find(5).map(|t| t.title), Some("Cielito Lindo"));
+    assert_eq!(catalog::find(43).map(|t| t.category), Some(Category::Story));
+    assert_eq!(catalog::find(WELCOME).map(|t| t.category), Some(Category::Welcome));
+}
+
+#[test]
+fn unknown_ids_are_not_found() {
+    for id in [0, 38, 42, 3001, u16::MAX] {
+	assert_eq!(catalog::find(id), None);
+    }
+}
+
+#[test]
+fn every_listed_track_is_in_the_menu() {
+    let listed: usize = Category::MENU.iter().map(|c| catalog::by_category(*c).count()).sum();
+    let hidden = catalog::by_category(Category::Welcome).count();
+    assert_eq!(listed + hidden, TRACKS.len());
+    assert_eq!(catalog::by_category(Category::SoundEffect).count(), 4);
+}
+
+#[test]
+fn durations_come_from_the_files() {
+    let welcome = catalog::find(WELCOME).unwrap();
+    assert_eq!(welcome.duration.map(|d| d.as_secs()), Some(20));
+    // the ones only in the card aren't known
+    assert_eq!(catalog::find(27).unwrap().duration, None);
+    assert_eq!(TRACKS.iter().filter(|track| track.duration.is_none()).count(), 13);
+}
+
+#[test]
+fn every_track_of_the_card_is_listed() {
+    assert_eq!(TRACKS.len(), 38);
+    assert_eq!(catalog::by_category(Category::Novena).count(), 9);
+    assert_eq!(catalog::by_category(Category::Story).count(), 2);
+}
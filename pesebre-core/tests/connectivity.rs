@@ -0,0 +1,103 @@
+use embassy_time::{Duration, Instant};
+
+use pesebre_core::connectivity::{backoff, Connectivity, Mode, State, FALLBACK_RETRY, MAX_JOIN_ATTEMPTS};
+
+fn at(secs: u64) -> Instant {
+    Instant::from_secs(secs)
+}
+
+#[test]
+fn joins_the_household_network() {
+    let mut wifi = Connectivity::new(true);
+    assert_eq!(wifi.state(), State::Joining{ attempt: 1 });
+    assert_eq!(wifi.mode(), Mode::Station);
+    wifi.joined();
+    assert_eq!(wifi.state(), State::Connected);
+    assert_eq!(wifi.deadline(), None);
+
+    // and again when it goes away
+    wifi.disconnected();
+    assert_eq!(wifi.state(), State::Joining{ attempt: 1 });
+}
+
+#[test]
+fn backs_off_then_falls_back() {
+    let mut wifi = Connectivity::new(true);
+    let mut now = at(0);
+    for attempt in 1..MAX_JOIN_ATTEMPTS {
+	wifi.join_failed(now);
+	let retry = now + backoff(attempt);
+	assert_eq!(wifi.state(), State::Backoff{ attempt: attempt + 1, at: retry });
+	assert_eq!(wifi.mode(), Mode::Station);
+	assert_eq!(wifi.deadline(), Some(retry));
+	// not before its time
+	wifi.tick(retry - Duration::from_millis(1), false);
+	assert_eq!(wifi.state(), State::Backoff{ attempt: attempt + 1, at: retry });
+	wifi.tick(retry, false);
+	assert_eq!(wifi.state(), State::Joining{ attempt: attempt + 1 });
+	now = retry + Duration::from_secs(10);
+    }
+    wifi.join_failed(now);
+    assert_eq!(wifi.state(), State::Fallback{ retry: Some(now + FALLBACK_RETRY) });
+    assert_eq!(wifi.mode(), Mode::AccessPoint);
+
+    // the network isn't around yet
+    let later = now + FALLBACK_RETRY;
+    wifi.tick(later, false);
+    assert_eq!(wifi.state(), State::Fallback{ retry: Some(later + FALLBACK_RETRY) });
+
+    // now it is
+    wifi.tick(later + FALLBACK_RETRY, true);
+    assert_eq!(wifi.state(), State::Joining{ attempt: 1 });
+    assert_eq!(wifi.mode(), Mode::Station);
+}
+
+#[test]
+fn backoff_grows_up_to_a_limit() {
+    assert_eq!(backoff(1), Duration::from_secs(2));
+    assert_eq!(backoff(2), Duration::from_secs(4));
+    assert_eq!(backoff(4), Duration::from_secs(16));
+    assert_eq!(backoff(5), Duration::from_secs(30));
+    assert_eq!(backoff(u8::MAX), Duration::from_secs(30));
+}
+
+#[test]
+fn access_point_without_a_network() {
+    let mut wifi = Connectivity::new(false);
+    assert_eq!(wifi.state(), State::Fallback{ retry: None });
+    assert_eq!(wifi.deadline(), None);
+    wifi.button_held();
+    assert_eq!(wifi.state(), State::Fallback{ retry: None });
+
+    // until one is saved
+    wifi.network_changed(true);
+    assert_eq!(wifi.state(), State::Joining{ attempt: 1 });
+}
+
+#[test]
+fn button_brings_the_access_point_up_and_down() {
+    let mut wifi = Connectivity::new(true);
+    wifi.joined();
+    wifi.button_held();
+    // for good, the network isn't looked for
+    assert_eq!(wifi.state(), State::Fallback{ retry: None });
+    assert_eq!(wifi.mode(), Mode::AccessPoint);
+    wifi.tick(at(3600), true);
+    assert_eq!(wifi.state(), State::Fallback{ retry: None });
+
+    wifi.button_held();
+    assert_eq!(wifi.state(), State::Joining{ attempt: 1 });
+}
+
+#[test]
+fn forgetting_the_network_falls_back() {
+    let mut wifi = Connectivity::new(true);
+    wifi.joined();
+    wifi.network_changed(false);
+    assert_eq!(wifi.state(), State::Fallback{ retry: None });
+    // reports out of turn change nothing
+    wifi.joined();
+    wifi.join_failed(at(0));
+    wifi.disconnected();
+    assert_eq!(wifi.state(), State::Fallback{ retry: None });
+}
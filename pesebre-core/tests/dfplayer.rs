@@ -0,0 +1,167 @@
+use std::convert::Infallible;
+
+use embassy_sync::channel::Channel;
+use embassy_time::Duration;
+use embedded_io_async::{ErrorType, Write};
+use futures::executor::block_on;
+
+use pesebre_core::dfplayer_mini::{
+    route, Argument, Device, DfEvent, DfEventChannel, DfPlayer, DfPlayerError, DfReplyChannel,
+    FrameDecoder, FrameError, ModuleError, ReliableConfig, Response, Volume,
+};
+
+const ACK : [u8; 10] = [0x7E, 0xFF, 0x06, 0x41, 0x00, 0x00, 0x00, 0xFE, 0xBA, 0xEF];
+
+fn sent<F>(command: F) -> Vec<u8>
+where
+    F: FnOnce(&mut DfPlayer<'_, Vec<u8>>) -> Result<(), DfPlayerError>,
+{
+    let replies = DfReplyChannel::new();
+    let mut player = DfPlayer::new(Vec::new(), &replies);
+    command(&mut player).unwrap();
+    player.release()
+}
+
+/// Transport answering every frame with `answer`, as the module would.
+struct Answering<'a> {
+    replies: &'a DfReplyChannel,
+    answer: Response,
+}
+
+impl ErrorType for Answering<'_> {
+    type Error = Infallible;
+}
+
+impl Write for Answering<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+	self.replies.try_send(self.answer).unwrap();
+	Ok(buf.len())
+    }
+}
+
+#[test]
+fn play_frame_matches_datasheet() {
+    let frame = sent(|player| block_on(player.play(1)));
+    assert_eq!(frame, [0x7E, 0xFF, 0x06, 0x03, 0x01, 0x00, 0x01, 0xFE, 0xF6, 0xEF]);
+}
+
+#[test]
+fn pause_frame_matches_datasheet() {
+    let frame = sent(|player| block_on(player.pause()));
+    assert_eq!(frame, [0x7E, 0xFF, 0x06, 0x0E, 0x00, 0x00, 0x01, 0xFE, 0xEC, 0xEF]);
+}
+
+#[test]
+fn checksum_wraps_for_large_params() {
+    let frame = sent(|player| block_on(player.play_large_folder(15, 3000)));
+    // folder in the high nibble, track in the low 12 bits
+    assert_eq!(&frame[3..7], &[0x14, 0x01, 0xFB, 0xB8]);
+    let sum = frame[1..7].iter().map(|b| *b as u16).sum::<u16>();
+    let checksum = u16::from_be_bytes([frame[7], frame[8]]);
+    assert_eq!(sum.wrapping_add(checksum), 0);
+}
+
+#[test]
+fn invalid_arguments_are_not_sent() {
+    let replies = DfReplyChannel::new();
+    let mut player = DfPlayer::new(Vec::new(), &replies);
+    assert_eq!(block_on(player.play(0)), Err(DfPlayerError::InvalidArgument(Argument::Track(0))));
+    assert_eq!(block_on(player.playback_source(6)), Err(DfPlayerError::InvalidArgument(Argument::Source(6))));
+    assert_eq!(block_on(player.volume_adjust_set(32)), Err(DfPlayerError::InvalidArgument(Argument::Gain(32))));
+    assert!(player.release().is_empty());
+}
+
+#[test]
+fn decoder_resyncs_on_garbage_and_split_frames() {
+    let frame = sent(|player| block_on(player.play(37)));
+    let mut decoder = FrameDecoder::new();
+
+    let mut data = vec![0x00, 0x7E, 0x12];
+    data.extend_from_slice(&frame[..4]);
+    assert_eq!(decoder.decode(&data).count(), 0);
+
+    let mut rest = frame[4..].to_vec();
+    rest.extend_from_slice(&ACK);
+    let responses: Vec<_> = decoder.decode(&rest).collect();
+    assert_eq!(responses.len(), 2);
+    // the play command itself, which isn't a reply the driver knows
+    assert_eq!(responses[0], Ok(Response::Unknown{ command: 0x03, param: 37 }));
+    assert_eq!(responses[1], Ok(Response::Ack));
+}
+
+#[test]
+fn decoder_reports_bad_checksum() {
+    let mut bad = ACK;
+    bad[8] = 0;
+    let mut data = bad.to_vec();
+    data.extend_from_slice(&ACK);
+
+    let responses: Vec<_> = FrameDecoder::new().decode(&data).collect();
+    assert_eq!(responses, [Err(FrameError::Checksum), Ok(Response::Ack)]);
+}
+
+#[test]
+fn notifications_go_to_events_and_answers_to_replies() {
+    let replies = DfReplyChannel::new();
+    let events = DfEventChannel::new();
+
+    route(&replies, &events, Response::TrackFinished{ device: Device::Tf, track: 4 });
+    route(&replies, &events, Response::Volume(12));
+    route(&replies, &events, Response::Error(ModuleError::NotFound));
+
+    assert_eq!(events.try_receive(), Ok(DfEvent::TrackFinished{ device: Device::Tf, track: 4 }));
+    assert_eq!(events.try_receive(), Ok(DfEvent::Error(ModuleError::NotFound)));
+    assert!(events.try_receive().is_err());
+
+    assert_eq!(replies.try_receive(), Ok(Response::Volume(12)));
+    assert_eq!(replies.try_receive(), Ok(Response::Error(ModuleError::NotFound)));
+    assert!(replies.try_receive().is_err());
+}
+
+#[test]
+fn query_returns_the_answer() {
+    let replies = DfReplyChannel::new();
+    let module = Answering{ replies: &replies, answer: Response::Volume(12) };
+    let mut player = DfPlayer::new(module, &replies);
+    assert_eq!(block_on(player.query_volume()), Ok(Volume(12)));
+}
+
+#[test]
+fn query_times_out_without_answer() {
+    static REPLIES: DfReplyChannel = Channel::new();
+    let mut player = DfPlayer::new(Vec::new(), &REPLIES);
+    assert_eq!(block_on(player.query_volume()), Err(DfPlayerError::Timeout));
+}
+
+#[test]
+fn module_errors_are_typed() {
+    let replies = DfReplyChannel::new();
+    let module = Answering{ replies: &replies, answer: Response::Error(ModuleError::from(0x06)) };
+    let mut player = DfPlayer::new(module, &replies);
+    let why = block_on(player.query_tf_track()).unwrap_err();
+    assert_eq!(why, DfPlayerError::Module(ModuleError::NotFound));
+    assert_eq!(why.to_string(), "MP3 module error: track not found");
+    assert_eq!(ModuleError::from(0x0B), ModuleError::Unknown(0x0B));
+}
+
+#[test]
+fn reliable_mode_waits_for_the_acknowledge() {
+    let config = ReliableConfig{
+	ack_timeout: Duration::from_millis(20),
+	retries: 2,
+	backoff: Duration::from_millis(5),
+    };
+    let replies = DfReplyChannel::new();
+    let mut player = DfPlayer::new(Answering{ replies: &replies, answer: Response::Ack }, &replies);
+    player.set_reliable(Some(config));
+    assert_eq!(block_on(player.play(3)), Ok(()));
+
+    // nobody answers, every attempt goes out
+    let mut player = DfPlayer::new(Vec::new(), &replies);
+    player.set_reliable(Some(config));
+    assert_eq!(block_on(player.play(3)), Err(DfPlayerError::NotAcknowledged{ command: 0x03, attempts: 3 }));
+    let sent = player.release();
+    assert_eq!(sent.len(), 30);
+    // with the feedback asked for
+    assert!(sent.chunks(10).all(|frame| frame[4] == 1));
+}
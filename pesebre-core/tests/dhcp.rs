@@ -0,0 +1,236 @@
+use embassy_time::{Duration, Instant};
+
+use pesebre_core::dhcp::{DhcpConfig, DhcpServer, MessageType, Reply, MAX_REPLY_SIZE};
+
+const PHONE : [u8; 6] = [0x3c, 0x22, 0xfb, 0x01, 0x02, 0x03];
+const TABLET : [u8; 6] = [0x3c, 0x22, 0xfb, 0x04, 0x05, 0x06];
+const BROADCAST : [u8; 4] = [255, 255, 255, 255];
+
+/// Message of `kind` from `client`, with `ciaddr` and the options given.
+fn message(kind: MessageType, client: [u8; 6], ciaddr: [u8; 4], options: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut packet = vec![1, 1, 6, 0, 0xde, 0xad, 0xbe, 0xef, 0, 3, 0x80, 0];
+    packet.extend_from_slice(&ciaddr);
+    packet.extend_from_slice(&[0; 12]);
+    packet.extend_from_slice(&client);
+    packet.resize(236, 0);
+    packet.extend_from_slice(&[99, 130, 83, 99]);
+    packet.extend_from_slice(&[53, 1, kind as u8]);
+    for (code, value) in options {
+	packet.extend_from_slice(&[*code, value.len() as u8]);
+	packet.extend_from_slice(value);
+    }
+    // a hostname, a parameter request list and some padding, as phones send
+    packet.extend_from_slice(&[12, 5, b'p', b'h', b'o', b'n', b'e', 55, 3, 1, 3, 6, 0, 0, 255]);
+    packet
+}
+
+fn discover(client: [u8; 6]) -> Vec<u8> {
+    message(MessageType::Discover, client, [0; 4], &[])
+}
+
+fn request(client: [u8; 6], address: [u8; 4]) -> Vec<u8> {
+    message(MessageType::Request, client, [0; 4], &[(50, &address), (54, &[192, 168, 2, 1])])
+}
+
+/// Options of a reply, by code.
+fn options(reply: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    assert_eq!(&reply[236..240], &[99, 130, 83, 99]);
+    let mut options = Vec::new();
+    let mut rest = &reply[240..];
+    while let [code, len, tail @ ..] = rest {
+	if *code == 255 {
+	    break;
+	}
+	options.push((*code, tail[..*len as usize].to_vec()));
+	rest = &tail[*len as usize..];
+    }
+    options
+}
+
+fn option(reply: &[u8], code: u8) -> Option<Vec<u8>> {
+    options(reply).into_iter().find(|(c, _)| *c == code).map(|(_, value)| value)
+}
+
+/// Sends `packet` to `server` at `now`, returning the reply.
+fn exchange(server: &mut DhcpServer, packet: &[u8], now: Instant) -> Option<(Reply, Vec<u8>)> {
+    let mut response = [0; MAX_REPLY_SIZE];
+    let reply = server.respond(packet, &mut response, now)?;
+    Some((reply, response[..reply.len].to_vec()))
+}
+
+fn yiaddr(reply: &[u8]) -> [u8; 4] {
+    reply[16..20].try_into().unwrap()
+}
+
+#[test]
+fn discover_gets_an_offer() {
+    let mut server = DhcpServer::new(DhcpConfig::new());
+    let (reply, offer) = exchange(&mut server, &discover(PHONE), Instant::from_secs(0)).unwrap();
+    assert_eq!(reply.destination, BROADCAST);
+    assert!(offer.len() >= 300);
+    assert_eq!(&offer[..4], &[2, 1, 6, 0]);
+    // transaction id and broadcast flag echoed
+    assert_eq!(&offer[4..8], &[0xde, 0xad, 0xbe, 0xef]);
+    assert_eq!(&offer[10..12], &[0x80, 0]);
+    assert_eq!(yiaddr(&offer), [192, 168, 2, 100]);
+    assert_eq!(&offer[28..34], &PHONE);
+    assert_eq!(options(&offer), [
+	(53, vec![2]),
+	(54, vec![192, 168, 2, 1]),
+	(1, vec![255, 255, 255, 0]),
+	(3, vec![192, 168, 2, 1]),
+	(6, vec![192, 168, 2, 1]),
+	(51, 7200_u32.to_be_bytes().to_vec()),
+    ]);
+    // only offered
+    assert_eq!(server.lease_of(PHONE, Instant::from_secs(0)), None);
+}
+
+#[test]
+fn request_binds_the_offer() {
+    let mut server = DhcpServer::new(DhcpConfig::new());
+    let now = Instant::from_secs(0);
+    let (_, offer) = exchange(&mut server, &discover(PHONE), now).unwrap();
+    let (reply, ack) = exchange(&mut server, &request(PHONE, yiaddr(&offer)), now).unwrap();
+    assert_eq!(reply.destination, BROADCAST);
+    assert_eq!(option(&ack, 53), Some(vec![5]));
+    assert_eq!(yiaddr(&ack), [192, 168, 2, 100]);
+    assert_eq!(server.lease_of(PHONE, now), Some([192, 168, 2, 100]));
+
+    // another client gets another address
+    let (_, offer) = exchange(&mut server, &discover(TABLET), now).unwrap();
+    assert_eq!(yiaddr(&offer), [192, 168, 2, 101]);
+
+    // and the first one its own back
+    let (_, offer) = exchange(&mut server, &discover(PHONE), now).unwrap();
+    assert_eq!(yiaddr(&offer), [192, 168, 2, 100]);
+
+    // until the lease runs out
+    assert_eq!(server.lease_of(PHONE, now + Duration::from_secs(7200)), None);
+}
+
+#[test]
+fn leases_are_renewed() {
+    let mut server = DhcpServer::new(DhcpConfig::new());
+    let now = Instant::from_secs(0);
+    exchange(&mut server, &discover(PHONE), now).unwrap();
+    exchange(&mut server, &request(PHONE, [192, 168, 2, 100]), now).unwrap();
+
+    let later = now + Duration::from_secs(3600);
+    let renewal = message(MessageType::Request, PHONE, [192, 168, 2, 100], &[]);
+    let (reply, ack) = exchange(&mut server, &renewal, later).unwrap();
+    assert_eq!(reply.destination, [192, 168, 2, 100]);
+    assert_eq!(option(&ack, 53), Some(vec![5]));
+    assert_eq!(&ack[12..16], &[192, 168, 2, 100]);
+    assert_eq!(server.lease_of(PHONE, now + Duration::from_secs(7200)), Some([192, 168, 2, 100]));
+}
+
+#[test]
+fn foreign_addresses_are_refused() {
+    let mut server = DhcpServer::new(DhcpConfig::new());
+    let now = Instant::from_secs(0);
+    // a phone coming from the home network
+    let reboot = message(MessageType::Request, PHONE, [0; 4], &[(50, &[192, 168, 1, 34])]);
+    let (reply, nak) = exchange(&mut server, &reboot, now).unwrap();
+    assert_eq!(reply.destination, BROADCAST);
+    assert_eq!(options(&nak), [(53, vec![6]), (54, vec![192, 168, 2, 1])]);
+    assert_eq!(yiaddr(&nak), [0; 4]);
+
+    // nor can it take the address of another client
+    exchange(&mut server, &discover(TABLET), now).unwrap();
+    exchange(&mut server, &request(TABLET, [192, 168, 2, 100]), now).unwrap();
+    let (_, nak) = exchange(&mut server, &request(PHONE, [192, 168, 2, 100]), now).unwrap();
+    assert_eq!(option(&nak, 53), Some(vec![6]));
+    assert_eq!(server.lease_of(PHONE, now), None);
+}
+
+#[test]
+fn offers_taken_elsewhere_are_dropped() {
+    let mut server = DhcpServer::new(DhcpConfig::new());
+    let now = Instant::from_secs(0);
+    exchange(&mut server, &discover(PHONE), now).unwrap();
+    let elsewhere = message(MessageType::Request, PHONE, [0; 4], &[(50, &[10, 0, 0, 5]), (54, &[10, 0, 0, 1])]);
+    assert_eq!(exchange(&mut server, &elsewhere, now), None);
+
+    let (_, offer) = exchange(&mut server, &discover(TABLET), now).unwrap();
+    assert_eq!(yiaddr(&offer), [192, 168, 2, 100]);
+}
+
+#[test]
+fn released_addresses_go_back_to_the_pool() {
+    let mut server = DhcpServer::new(DhcpConfig{ pool_size: 1, ..DhcpConfig::new() });
+    let now = Instant::from_secs(0);
+    exchange(&mut server, &discover(PHONE), now).unwrap();
+    exchange(&mut server, &request(PHONE, [192, 168, 2, 100]), now).unwrap();
+    assert_eq!(exchange(&mut server, &discover(TABLET), now), None);
+
+    let release = message(MessageType::Release, PHONE, [192, 168, 2, 100], &[(54, &[192, 168, 2, 1])]);
+    assert_eq!(exchange(&mut server, &release, now), None);
+    assert_eq!(server.lease_of(PHONE, now), None);
+    let (_, offer) = exchange(&mut server, &discover(TABLET), now).unwrap();
+    assert_eq!(yiaddr(&offer), [192, 168, 2, 100]);
+}
+
+#[test]
+fn expired_leases_are_reused() {
+    let mut server = DhcpServer::new(DhcpConfig{ pool_size: 1, ..DhcpConfig::new() });
+    let now = Instant::from_secs(0);
+    exchange(&mut server, &discover(PHONE), now).unwrap();
+    exchange(&mut server, &request(PHONE, [192, 168, 2, 100]), now).unwrap();
+
+    let later = now + Duration::from_secs(7200);
+    let (_, offer) = exchange(&mut server, &discover(TABLET), later).unwrap();
+    assert_eq!(yiaddr(&offer), [192, 168, 2, 100]);
+    exchange(&mut server, &request(TABLET, [192, 168, 2, 100]), later).unwrap();
+    assert_eq!(server.lease_of(TABLET, later), Some([192, 168, 2, 100]));
+}
+
+#[test]
+fn declined_addresses_stay_out_of_the_pool() {
+    let mut server = DhcpServer::new(DhcpConfig{ pool_size: 3, ..DhcpConfig::new() });
+    let now = Instant::from_secs(0);
+    for address in [[192, 168, 2, 100], [192, 168, 2, 101]] {
+	let decline = message(MessageType::Decline, PHONE, [0; 4], &[(50, &address), (54, &[192, 168, 2, 1])]);
+	assert_eq!(exchange(&mut server, &decline, now), None);
+    }
+
+    // both of them, not only the last one
+    let (_, offer) = exchange(&mut server, &discover(TABLET), now).unwrap();
+    assert_eq!(yiaddr(&offer), [192, 168, 2, 102]);
+    exchange(&mut server, &request(TABLET, [192, 168, 2, 102]), now).unwrap();
+    assert_eq!(exchange(&mut server, &discover(PHONE), now), None);
+}
+
+#[test]
+fn inform_gets_the_network() {
+    let mut server = DhcpServer::new(DhcpConfig::new());
+    let inform = message(MessageType::Inform, PHONE, [192, 168, 2, 7], &[]);
+    let (reply, ack) = exchange(&mut server, &inform, Instant::from_secs(0)).unwrap();
+    assert_eq!(reply.destination, [192, 168, 2, 7]);
+    assert_eq!(yiaddr(&ack), [0; 4]);
+    assert_eq!(&ack[12..16], &[192, 168, 2, 7]);
+    assert_eq!(option(&ack, 53), Some(vec![5]));
+    assert_eq!(option(&ack, 3), Some(vec![192, 168, 2, 1]));
+    // no lease for an address it set up itself
+    assert_eq!(option(&ack, 51), None);
+}
+
+#[test]
+fn ignores_garbage() {
+    let mut server = DhcpServer::new(DhcpConfig::new());
+    let now = Instant::from_secs(0);
+    assert_eq!(exchange(&mut server, &[1, 1, 6, 0], now), None);
+
+    let mut reply = discover(PHONE);
+    reply[0] = 2;
+    assert_eq!(exchange(&mut server, &reply, now), None);
+
+    let mut no_cookie = discover(PHONE);
+    no_cookie[236] = 0;
+    assert_eq!(exchange(&mut server, &no_cookie, now), None);
+
+    let mut truncated = discover(PHONE);
+    truncated.truncate(245);
+    truncated[244] = 40;
+    assert_eq!(exchange(&mut server, &truncated, now), None);
+}
@@ -0,0 +1,214 @@
+use pesebre_core::dns::{DnsResponder, MAX_MESSAGE_SIZE, ZONE};
+
+/// `dig control-panel.pesebre.co` as captured: recursion desired, authentic
+/// data, and an EDNS record with a client cookie.
+const DIG_CONTROL_PANEL : [u8; 65] = [
+    0x5a, 0x1f, 0x01, 0x20, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x0d, b'c', b'o', b'n', b't', b'r', b'o', b'l', b'-', b'p', b'a', b'n', b'e', b'l',
+    0x07, b'p', b'e', b's', b'e', b'b', b'r', b'e', 0x02, b'c', b'o', 0x00,
+    0x00, 0x01, 0x00, 0x01,
+    0x00, 0x00, 0x29, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c,
+    0x00, 0x0a, 0x00, 0x08, 0x8e, 0x1d, 0x4c, 0x3b, 0x02, 0x9a, 0xf1, 0x70,
+];
+
+/// Query for `name` with the given id, type and class IN, as the resolver of
+/// a phone sends it: recursion desired, no EDNS.
+fn query(id: u16, name: &str, kind: u16) -> Vec<u8> {
+    let mut packet = id.to_be_bytes().to_vec();
+    packet.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    question(&mut packet, name, kind);
+    packet
+}
+
+fn question(packet: &mut Vec<u8>, name: &str, kind: u16) {
+    for label in name.split('.') {
+	packet.push(label.len() as u8);
+	packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&kind.to_be_bytes());
+    packet.extend_from_slice(&[0x00, 0x01]);
+}
+
+fn exchange(responder: &DnsResponder, request: &[u8]) -> Option<Vec<u8>> {
+    let mut response = [0; MAX_MESSAGE_SIZE];
+    let len = responder.respond(request, &mut response)?;
+    Some(response[..len].to_vec())
+}
+
+fn flags(reply: &[u8]) -> u16 {
+    u16::from_be_bytes([reply[2], reply[3]])
+}
+
+fn rcode(reply: &[u8]) -> u16 {
+    flags(reply) & 0x000f
+}
+
+/// Section counts of `reply`.
+fn counts(reply: &[u8]) -> [u16; 4] {
+    [4, 6, 8, 10].map(|at| u16::from_be_bytes([reply[at], reply[at + 1]]))
+}
+
+/// Answers of `reply` after the questions, which end at `questions`, as
+/// (offset of the name they point to, type, data).
+fn answers(reply: &[u8], questions: usize) -> Vec<(u16, u16, Vec<u8>)> {
+    let mut rest = &reply[questions..];
+    let mut answers = Vec::new();
+    for _ in 0..counts(reply)[1] {
+	assert_eq!(rest[0] & 0xc0, 0xc0);
+	let offset = u16::from_be_bytes([rest[0] & 0x3f, rest[1]]);
+	let kind = u16::from_be_bytes([rest[2], rest[3]]);
+	assert_eq!(&rest[4..10], &[0x00, 0x01, 0x00, 0x00, 0x00, 60]);
+	let len = u16::from_be_bytes([rest[10], rest[11]]) as usize;
+	answers.push((offset, kind, rest[12..12 + len].to_vec()));
+	rest = &rest[12 + len..];
+    }
+    answers
+}
+
+#[test]
+fn answers_dig() {
+    let responder = DnsResponder::new(&ZONE);
+    let reply = exchange(&responder, &DIG_CONTROL_PANEL).unwrap();
+    assert_eq!(&reply[..2], &[0x5a, 0x1f]);
+    // response, authoritative, recursion desired echoed but not available
+    assert_eq!(flags(&reply), 0x8500);
+    assert_eq!(counts(&reply), [1, 1, 0, 1]);
+    assert_eq!(&reply[12..42], &DIG_CONTROL_PANEL[12..42]);
+    assert_eq!(answers(&reply, 42), [(12, 1, vec![192, 168, 2, 1])]);
+    // EDNS record back, version 0, no options
+    assert_eq!(&reply[58..], &[0x00, 0x00, 0x29, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn names_are_case_insensitive() {
+    let responder = DnsResponder::new(&ZONE);
+    let asked = query(3, "Pesebre.CO", 1);
+    let reply = exchange(&responder, &asked).unwrap();
+    assert_eq!(rcode(&reply), 0);
+    // the question goes back as written
+    assert_eq!(&reply[12..asked.len()], &asked[12..]);
+    assert_eq!(answers(&reply, asked.len()), [(12, 1, vec![192, 168, 2, 1])]);
+}
+
+#[test]
+fn no_ipv6_address() {
+    let responder = DnsResponder::new(&ZONE);
+    let asked = query(4, "control-panel.pesebre.co", 28);
+    let reply = exchange(&responder, &asked).unwrap();
+    // the name exists, without data of that type
+    assert_eq!(flags(&reply), 0x8500);
+    assert_eq!(counts(&reply), [1, 0, 0, 0]);
+    assert_eq!(reply.len(), asked.len());
+}
+
+#[test]
+fn unknown_names() {
+    let responder = DnsResponder::new(&ZONE);
+    let reply = exchange(&responder, &query(5, "www.pesebre.co", 1)).unwrap();
+    assert_eq!(flags(&reply), 0x8503);
+    assert_eq!(counts(&reply), [1, 0, 0, 0]);
+
+    // nor is the pesebre a resolver for the rest
+    for name in ["captive.apple.com", "notpesebre.co"] {
+	let reply = exchange(&responder, &query(6, name, 1)).unwrap();
+	assert_eq!(flags(&reply), 0x8105, "{name}");
+	assert_eq!(counts(&reply), [1, 0, 0, 0], "{name}");
+    }
+}
+
+#[test]
+fn portal_answers_every_name() {
+    let mut responder = DnsResponder::new(&ZONE);
+    responder.portal = true;
+    for name in ["connectivitycheck.gstatic.com", "captive.apple.com", "www.msftconnecttest.com", "www.pesebre.co"] {
+	let asked = query(7, name, 1);
+	let reply = exchange(&responder, &asked).unwrap();
+	assert_eq!(flags(&reply), 0x8580, "{name}");
+	assert_eq!(answers(&reply, asked.len()), [(12, 1, vec![192, 168, 2, 1])], "{name}");
+    }
+
+    let reply = exchange(&responder, &query(8, "captive.apple.com", 28)).unwrap();
+    assert_eq!(&reply[..2], &[0, 8]);
+    assert_eq!(counts(&reply), [1, 0, 0, 0]);
+}
+
+#[test]
+fn several_questions() {
+    let responder = DnsResponder::new(&ZONE);
+    let mut asked = query(9, "pesebre.co", 1);
+    asked[5] = 3;
+    question(&mut asked, "control-panel.pesebre.co", 28);
+    let third = asked.len();
+    question(&mut asked, "control-panel.pesebre.co", 255);
+    let reply = exchange(&responder, &asked).unwrap();
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(counts(&reply), [3, 2, 0, 0]);
+    assert_eq!(answers(&reply, asked.len()), [
+	(12, 1, vec![192, 168, 2, 1]),
+	(third as u16, 1, vec![192, 168, 2, 1]),
+    ]);
+
+    // one name that doesn't exist spoils the lot
+    let mut asked = query(10, "pesebre.co", 1);
+    asked[5] = 2;
+    question(&mut asked, "belen.pesebre.co", 1);
+    let reply = exchange(&responder, &asked).unwrap();
+    assert_eq!(rcode(&reply), 3);
+    assert_eq!(counts(&reply), [2, 1, 0, 0]);
+}
+
+#[test]
+fn edns_versions() {
+    let responder = DnsResponder::new(&ZONE);
+    let mut asked = DIG_CONTROL_PANEL;
+    asked[48] = 1;
+    let reply = exchange(&responder, &asked).unwrap();
+    assert_eq!(counts(&reply), [1, 0, 0, 1]);
+    // BADVERS: the upper bits in the EDNS record
+    assert_eq!(rcode(&reply), 0);
+    assert_eq!(&reply[42..], &[0x00, 0x00, 0x29, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn malformed_queries() {
+    let responder = DnsResponder::new(&ZONE);
+    // a question cut short
+    let mut truncated = query(11, "control-panel.pesebre.co", 1);
+    truncated.truncate(20);
+    let reply = exchange(&responder, &truncated).unwrap();
+    assert_eq!(&reply[..2], &[0, 11]);
+    assert_eq!(rcode(&reply), 1);
+    assert_eq!(counts(&reply), [0, 0, 0, 0]);
+
+    // an EDNS record longer than the datagram
+    let reply = exchange(&responder, &DIG_CONTROL_PANEL[..60]).unwrap();
+    assert_eq!(rcode(&reply), 1);
+
+    // a name pointing at itself
+    let mut looping = query(12, "pesebre.co", 1);
+    looping.splice(12..24, [0xc0, 12]);
+    let reply = exchange(&responder, &looping).unwrap();
+    assert_eq!(rcode(&reply), 1);
+}
+
+#[test]
+fn only_standard_queries() {
+    let responder = DnsResponder::new(&ZONE);
+    let mut notify = query(13, "pesebre.co", 6);
+    notify[2] = 0x20;
+    let reply = exchange(&responder, &notify).unwrap();
+    assert_eq!(flags(&reply), 0xa004);
+    assert_eq!(counts(&reply), [1, 0, 0, 0]);
+}
+
+#[test]
+fn ignores_garbage() {
+    let responder = DnsResponder::new(&ZONE);
+    assert_eq!(exchange(&responder, &[0x12, 0x34, 0x01]), None);
+
+    // replies, as the ones of another server on the network
+    let mut reply = query(14, "pesebre.co", 1);
+    reply[2] |= 0x80;
+    assert_eq!(exchange(&responder, &reply), None);
+}
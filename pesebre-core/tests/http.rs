@@ -0,0 +1,671 @@
+// the routes of the control panel nest deeper than the default limit takes
+#![recursion_limit = "256"]
+
+use std::convert::Infallible;
+use std::future::poll_fn;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
+
+use embassy_time::{Duration, Instant};
+use futures::executor::block_on;
+use futures::future::join;
+
+use pesebre_core::catalog;
+use pesebre_core::control::{Control, ControlMessages, CONTROL_QUEUE_SIZE};
+use pesebre_core::dfplayer_mini::ModuleError;
+use pesebre_core::events::{notify, PanelEvent, EVENT_STREAMS};
+use pesebre_core::http::{make_app, EmbassyTimer, Interface};
+use pesebre_core::player::PlayerState;
+use pesebre_core::playlist::Playlist;
+use pesebre_core::provisioning::Trial;
+use pesebre_core::settings::AccessPointSettings;
+
+/// Serves the raw HTTP `request` on the access point and returns the raw
+/// response.
+fn serve(control: &'static Control, request: &str) -> String {
+    serve_on(control, Interface::AccessPoint, request)
+}
+
+/// Serves the raw HTTP `request` on `interface` and returns the raw response.
+fn serve_on(control: &'static Control, interface: Interface, request: &str) -> String {
+    let app = make_app(control, interface);
+    let config = picoserve::Config {
+	start_read_request_timeout: Some(Duration::from_secs(1)),
+	read_request_timeout: Some(Duration::from_secs(1)),
+    };
+    let mut response = Vec::new();
+
+    block_on(picoserve::serve(
+	&app,
+	EmbassyTimer,
+	&config,
+	&mut [0; 2048],
+	request.as_bytes(),
+	&mut response,
+    )).unwrap();
+
+    String::from_utf8(response).unwrap()
+}
+
+/// Lets the other futures run once.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+	if yielded {
+	    Poll::Ready(())
+	} else {
+	    yielded = true;
+	    cx.waker().wake_by_ref();
+	    Poll::Pending
+	}
+    }).await
+}
+
+/// Client sending `request` a part at a time, each one once the server
+/// took the one before, and keeping the connection open until `hung_up`.
+struct Client<'a> {
+    request: Vec<&'a [u8]>,
+    hung_up: &'a AtomicBool,
+}
+
+impl embedded_io_async::ErrorType for Client<'_> {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Read for Client<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+	while let Some(part) = self.request.first_mut() {
+	    if part.is_empty() {
+		self.request.remove(0);
+		// let the server answer
+		for _ in 0..10 {
+		    yield_now().await;
+		}
+		continue;
+	    }
+	    return embedded_io_async::Read::read(part, buf).await;
+	}
+	while !self.hung_up.load(Ordering::Relaxed) {
+	    yield_now().await;
+	}
+	Ok(0)
+    }
+}
+
+/// Serves the raw HTTP `request`, sent in parts, while `events` are sent,
+/// then hangs up and returns the raw response.
+fn serve_while(control: &'static Control, request: &[&[u8]], events: &[PanelEvent]) -> Vec<u8> {
+    let app = make_app(control, Interface::AccessPoint);
+    let config = picoserve::Config {
+	start_read_request_timeout: Some(Duration::from_secs(1)),
+	read_request_timeout: Some(Duration::from_secs(1)),
+    };
+    let hung_up = AtomicBool::new(false);
+    let mut response = Vec::new();
+
+    let client = Client{ request: request.to_vec(), hung_up: &hung_up };
+    let events = async {
+	// let the request through first
+	for _ in 0..10 {
+	    yield_now().await;
+	}
+	for event in events {
+	    notify(&control.events, *event);
+	    yield_now().await;
+	}
+	for _ in 0..10 {
+	    yield_now().await;
+	}
+	hung_up.store(true, Ordering::Relaxed);
+    };
+
+    let (served, ()) = block_on(join(
+	picoserve::serve(&app, EmbassyTimer, &config, &mut [0; 2048], client, &mut response),
+	events,
+    ));
+    served.unwrap();
+
+    response
+}
+
+#[test]
+fn index_is_served() {
+    static CONTROL: Control = Control::new();
+    let response = serve(&CONTROL, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("<title>Pesebre Navideño</title>"));
+    assert!(response.contains("<h2>Villancicos</h2>"));
+    assert!(response.contains("<li onclick=\"reproducir(26)\">Agua <a class=\"add\" onclick=\"encolar(event, 26)\">+</a></li>"));
+    assert!(!response.contains("<!-- catalog -->"));
+    // only in the card, listed in catalogo.txt
+    assert!(response.contains("<h2>Novena de Aguinaldos</h2>"));
+    assert!(response.contains("<li onclick=\"reproducir(43)\">El colibrí navideño <a class=\"add\" onclick=\"encolar(event, 43)\">+</a></li>"));
+
+    let (headers, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(headers.contains(&format!("Content-Length: {}", body.len())));
+}
+
+#[test]
+fn connectivity_checks_lead_to_the_control_panel() {
+    static CONTROL: Control = Control::new();
+    for path in ["/generate_204", "/hotspot-detect.html", "/connecttest.txt", "/ncsi.txt"] {
+	let response = serve(&CONTROL, &format!("GET {path} HTTP/1.1\r\nHost: example.com\r\n\r\n"));
+	assert!(response.starts_with("HTTP/1.1 302"), "{path}: {response}");
+	assert!(response.contains("Location: http://192.168.2.1/\r\n"));
+    }
+    assert!(serve(&CONTROL, "GET /generate_204/more HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn commands_are_not_taken_on_a_get() {
+    static CONTROL: Control = Control::new();
+    for path in ["/reproducir/37", "/pause", "/stop", "/resume", "/inc-vol", "/dec-vol"] {
+	let response = serve(&CONTROL, &format!("GET {path} HTTP/1.1\r\n\r\n"));
+	assert!(response.starts_with("HTTP/1.1 404"), "{path}: {response}");
+    }
+    assert!(CONTROL.commands.try_receive().is_err());
+}
+
+#[test]
+fn playlist_requests_are_sent_to_the_player() {
+    static CONTROL: Control = Control::new();
+    let track = catalog::find(26).unwrap();
+    let mut playlist = Playlist::new();
+    for _ in 0..5 {
+	playlist.enqueue(track).unwrap();
+    }
+    CONTROL.playlist.publish(&playlist);
+
+    for (path, body, expected) in [
+	("playlist", r#"{"track":26}"#, ControlMessages::Enqueue(track)),
+	("playlist/next", r#"{"track":26}"#, ControlMessages::PlayNext(track)),
+	("playlist/remove", r#"{"position":3}"#, ControlMessages::Remove(3)),
+	("playlist/move", r#"{"from":4,"to":1}"#, ControlMessages::Reorder{ from: 4, to: 1 }),
+	("playlist/clear", "", ControlMessages::ClearPlaylist),
+	("playlist/skip", "", ControlMessages::Skip),
+    ] {
+	let response = api_request(&CONTROL, "POST", path, body);
+	assert!(response.starts_with("HTTP/1.1 204"), "{path}: {response}");
+	assert_eq!(CONTROL.commands.try_receive(), Ok(expected));
+    }
+}
+
+#[test]
+fn playlist_rejects_what_it_cant_do() {
+    static CONTROL: Control = Control::new();
+    let mut playlist = Playlist::new();
+    playlist.enqueue(catalog::find(26).unwrap()).unwrap();
+    CONTROL.playlist.publish(&playlist);
+
+    for (path, body, status, error) in [
+	("playlist", r#"{"track":99}"#, "404", "unknown track"),
+	("playlist/next", r#"{"track":0}"#, "404", "unknown track"),
+	("playlist", "", "400", "malformed body"),
+	("playlist/remove", r#"{"position":1}"#, "409", "no track at that position"),
+	("playlist/move", r#"{"from":0,"to":3}"#, "409", "no track at that position"),
+	("playlist/move", r#"{"from":0}"#, "400", "malformed body"),
+    ] {
+	let response = api_request(&CONTROL, "POST", path, body);
+	assert!(response.starts_with(&format!("HTTP/1.1 {status}")), "{path}: {response}");
+	assert!(response.ends_with(&format!(r#"{{"error":"{error}"}}"#)), "{path}: {response}");
+    }
+
+    while playlist.enqueue(catalog::find(26).unwrap()).is_ok() {}
+    CONTROL.playlist.publish(&playlist);
+    let response = api_request(&CONTROL, "POST", "playlist", r#"{"track":26}"#);
+    assert!(response.ends_with(r#"{"error":"playlist full"}"#), "{response}");
+    assert!(CONTROL.commands.try_receive().is_err());
+
+    // a full channel doesn't hold the web task
+    for _ in 0..CONTROL_QUEUE_SIZE {
+	assert!(api_request(&CONTROL, "POST", "playlist/skip", "").starts_with("HTTP/1.1 204"));
+    }
+    let response = api_request(&CONTROL, "POST", "playlist/clear", "");
+    assert!(response.ends_with(r#"{"error":"player unavailable"}"#), "{response}");
+}
+
+#[test]
+fn playlist_is_listed() {
+    static CONTROL: Control = Control::new();
+    let mut playlist = Playlist::new();
+    playlist.enqueue(catalog::find(37).unwrap()).unwrap();
+    playlist.enqueue(catalog::find(26).unwrap()).unwrap();
+    CONTROL.playlist.publish(&playlist);
+
+    let response = api_request(&CONTROL, "GET", "playlist", "");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    assert_eq!(
+	body,
+	format!(
+	    r#"[{{"id":37,"title":"{}"}},{{"id":26,"title":"Agua"}}]"#,
+	    catalog::find(37).unwrap().title,
+	),
+    );
+}
+
+#[test]
+fn index_shows_the_track_playing() {
+    static CONTROL: Control = Control::new();
+    let response = serve(&CONTROL, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.contains("<p class=\"now-playing\" id=\"now-playing\"></p>"));
+    assert!(!response.contains("<!-- now playing -->"));
+
+    let track = catalog::find(26).unwrap();
+    CONTROL.state.publish(PlayerState::Paused{ track, elapsed: Duration::from_secs(3) });
+    let response = serve(&CONTROL, "GET / HTTP/1.1\r\n\r\n");
+    assert!(response.contains("<p class=\"now-playing\" id=\"now-playing\">En pausa: Agua</p>"));
+    let (headers, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(headers.contains(&format!("Content-Length: {}", body.len())));
+}
+
+#[test]
+fn status_is_reported() {
+    static CONTROL: Control = Control::new();
+    let track = catalog::find(26).unwrap();
+    CONTROL.state.publish(PlayerState::Paused{ track, elapsed: Duration::from_secs(3) });
+    CONTROL.device.update(|info| {
+	info.volume = 25;
+	info.firmware_version = Some(8);
+    });
+
+    let response = serve(&CONTROL, "GET /api/status HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("Content-Type: application/json"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(body.starts_with(r#"{"state":"paused","track":{"id":26,"title":"Agua"},"elapsed_ms":3000,"volume":25,"muted":false,"max_volume":30,"eq":null,"source":null,"queue_length":0,"uptime_s":"#));
+    assert!(body.ends_with(r#","stations":0,"station_address":null,"firmware_version":8}"#));
+}
+
+#[test]
+fn events_are_streamed() {
+    static CONTROL: Control = Control::new();
+    let response = serve_while(&CONTROL, &[b"GET /api/events HTTP/1.1\r\n\r\n"], &[
+	PanelEvent::TrackStarted(catalog::find(26).unwrap()),
+	PanelEvent::VolumeChanged(21),
+	PanelEvent::Paused,
+	PanelEvent::LightSceneChanged(2),
+    ]);
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("Content-Type: text/event-stream"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    assert_eq!(body, concat!(
+	"event:track-started\ndata:{\"event\":\"track-started\",\"id\":26,\"title\":\"Agua\"}\n\n",
+	"event:volume\ndata:{\"event\":\"volume\",\"volume\":21}\n\n",
+	"event:paused\ndata:{\"event\":\"paused\",\"state\":\"paused\"}\n\n",
+	"event:light-scene\ndata:{\"event\":\"light-scene\",\"program\":2}\n\n",
+    ));
+}
+
+#[test]
+fn event_streams_are_limited() {
+    static CONTROL: Control = Control::new();
+    let _pages: Vec<_> = (0..EVENT_STREAMS).map(|_| CONTROL.events.subscriber().unwrap()).collect();
+    let response = serve(&CONTROL, "GET /api/events HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 503"));
+}
+
+/// Frame as a browser sends it, masked.
+fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x37, 0xfa, 0x21, 0x3d];
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend(mask);
+    frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+    frame
+}
+
+/// Messages in the frames sent by the server, as (opcode, payload), with the
+/// fragments put together.
+fn server_messages(mut data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut messages: Vec<(u8, Vec<u8>)> = Vec::new();
+    let mut fragmented = false;
+    while !data.is_empty() {
+	let (fin, opcode) = (data[0] & 0x80 != 0, data[0] & 0x0F);
+	let (length, header) = match data[1] {
+	    126 => (u16::from_be_bytes([data[2], data[3]]) as usize, 4),
+	    length => (length as usize, 2),
+	};
+	let payload = &data[header..header + length];
+	if opcode == 0 {
+	    messages.last_mut().unwrap().1.extend(payload);
+	} else if !fragmented || opcode >= 8 {
+	    messages.push((opcode, payload.to_vec()));
+	}
+	if opcode < 8 {
+	    fragmented = !fin;
+	}
+	data = &data[header + length..];
+    }
+    messages
+}
+
+const WS_HANDSHAKE : &str = "GET /ws HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+    Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+#[test]
+fn websocket_takes_commands() {
+    static CONTROL: Control = Control::new();
+    let mut frames = client_frame(1, br#"{"cmd":"play","track":26}"#);
+    frames.extend(client_frame(1, br#"{"cmd":"volume","value":18}"#));
+    frames.extend(client_frame(1, br#"{"cmd":"play","track":99}"#));
+    frames.extend(client_frame(1, br#"{"cmd":"volume","value":31}"#));
+    frames.extend(client_frame(9, b"hola"));
+    frames.extend(client_frame(8, &1000_u16.to_be_bytes()));
+
+    let response = serve_while(&CONTROL, &[WS_HANDSHAKE.as_bytes(), &frames], &[]);
+
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let headers = std::str::from_utf8(&response[..split]).unwrap();
+    assert!(headers.starts_with("HTTP/1.1 101"));
+    assert!(headers.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+    assert_eq!(server_messages(&response[split..]), [
+	(1, br#"{"error":"unknown track"}"#.to_vec()),
+	(1, br#"{"error":"volume out of range"}"#.to_vec()),
+	(10, b"hola".to_vec()),
+	(8, 1000_u16.to_be_bytes().to_vec()),
+    ]);
+    assert_eq!(CONTROL.commands.try_receive(), Ok(ControlMessages::Play(catalog::find(26).unwrap())));
+    assert_eq!(
+	CONTROL.commands.try_receive(),
+	Ok(ControlMessages::AdjustVolume{ level: Some(18), muted: None, max: None }),
+    );
+    assert!(CONTROL.commands.try_receive().is_err());
+}
+
+#[test]
+fn websocket_pushes_events() {
+    static CONTROL: Control = Control::new();
+    let response = serve_while(&CONTROL, &[WS_HANDSHAKE.as_bytes()], &[
+	PanelEvent::QueueChanged(3),
+	PanelEvent::Stopped,
+    ]);
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let messages = server_messages(&response[split..]);
+    assert_eq!(messages[..2], [
+	(1, br#"{"event":"queue","length":3}"#.to_vec()),
+	(1, br#"{"event":"stopped","state":"stopped"}"#.to_vec()),
+    ]);
+    // the page hung up
+    assert_eq!(messages[2].0, 8);
+}
+
+/// Sends `body` to `path` of the REST interface with `method`.
+fn api_request(control: &'static Control, method: &str, path: &str, body: &str) -> String {
+    serve(control, &format!("{method} /api/v1/{path} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}", body.len()))
+}
+
+#[test]
+fn api_commands_are_sent_to_the_player() {
+    static CONTROL: Control = Control::new();
+    let track = catalog::find(26).unwrap();
+
+    let response = api_request(&CONTROL, "POST", "play", r#"{"track":26}"#);
+    assert!(response.starts_with("HTTP/1.1 204"));
+    assert_eq!(CONTROL.commands.try_receive(), Ok(ControlMessages::Play(track)));
+
+    CONTROL.state.publish(PlayerState::Playing{ track, started_at: Instant::from_secs(0) });
+    assert!(api_request(&CONTROL, "POST", "pause", "").starts_with("HTTP/1.1 204"));
+    assert_eq!(CONTROL.commands.try_receive(), Ok(ControlMessages::Pause));
+
+    CONTROL.state.publish(PlayerState::Paused{ track, elapsed: Duration::from_secs(3) });
+    assert!(api_request(&CONTROL, "POST", "resume", "").starts_with("HTTP/1.1 204"));
+    assert_eq!(CONTROL.commands.try_receive(), Ok(ControlMessages::Resume));
+
+    assert!(api_request(&CONTROL, "POST", "stop", "").starts_with("HTTP/1.1 204"));
+    assert_eq!(CONTROL.commands.try_receive(), Ok(ControlMessages::Stop));
+
+    assert!(api_request(&CONTROL, "PUT", "volume", r#"{"volume":18}"#).starts_with("HTTP/1.1 204"));
+    assert_eq!(
+	CONTROL.commands.try_receive(),
+	Ok(ControlMessages::AdjustVolume{ level: Some(18), muted: None, max: None }),
+    );
+
+    assert!(api_request(&CONTROL, "PUT", "volume", r#"{"muted":true,"max":20,"volume":22}"#).starts_with("HTTP/1.1 204"));
+    assert_eq!(
+	CONTROL.commands.try_receive(),
+	Ok(ControlMessages::AdjustVolume{ level: Some(22), muted: Some(true), max: Some(20) }),
+    );
+    assert!(CONTROL.commands.try_receive().is_err());
+
+    assert!(api_request(&CONTROL, "POST", "volume/up", "").starts_with("HTTP/1.1 204"));
+    assert_eq!(CONTROL.commands.try_receive(), Ok(ControlMessages::IncVol));
+    assert!(api_request(&CONTROL, "POST", "volume/down", "").starts_with("HTTP/1.1 204"));
+    assert_eq!(CONTROL.commands.try_receive(), Ok(ControlMessages::DecVol));
+
+    CONTROL.device.update(|info| (info.volume, info.muted, info.max_volume) = (20, true, 20));
+    let response = api_request(&CONTROL, "GET", "volume", "");
+    assert!(response.ends_with(r#"{"volume":20,"muted":true,"max":20}"#), "{response}");
+}
+
+#[test]
+fn api_rejects_what_it_cant_do() {
+    static CONTROL: Control = Control::new();
+    for (method, path, body, status, error) in [
+	("POST", "play", r#"{"track":99}"#, "404", "unknown track"),
+	("POST", "play", r#"{"song":26}"#, "400", "malformed body"),
+	("POST", "play", "", "400", "malformed body"),
+	("PUT", "volume", r#"{"volume":31}"#, "400", "volume out of range"),
+	("PUT", "volume", r#"{"max":40}"#, "400", "volume out of range"),
+	("PUT", "volume", "{}", "400", "nothing to change"),
+	("POST", "pause", "", "409", "nothing playing"),
+	("POST", "resume", "", "409", "nothing paused"),
+    ] {
+	let response = api_request(&CONTROL, method, path, body);
+	assert!(response.starts_with(&format!("HTTP/1.1 {status}")), "{method} {path}: {response}");
+	assert!(response.contains("Content-Type: application/json"));
+	assert!(response.ends_with(&format!(r#"{{"error":"{error}"}}"#)), "{method} {path}: {response}");
+    }
+    // nothing changes on a GET, whatever a browser prefetches
+    assert!(api_request(&CONTROL, "GET", "play", "").starts_with("HTTP/1.1 405"));
+    assert!(api_request(&CONTROL, "POST", "volume", r#"{"volume":18}"#).starts_with("HTTP/1.1 405"));
+    assert!(CONTROL.commands.try_receive().is_err());
+}
+
+#[test]
+fn api_reports_a_busy_player() {
+    static CONTROL: Control = Control::new();
+    for _ in 1..CONTROL_QUEUE_SIZE {
+	assert!(api_request(&CONTROL, "POST", "stop", "").starts_with("HTTP/1.1 204"));
+    }
+    // the last place takes every change of the volume
+    let response = api_request(&CONTROL, "PUT", "volume", r#"{"muted":true,"max":20,"volume":22}"#);
+    assert!(response.starts_with("HTTP/1.1 204"));
+    let response = api_request(&CONTROL, "POST", "stop", "");
+    assert!(response.starts_with("HTTP/1.1 503"));
+    assert!(response.ends_with(r#"{"error":"player unavailable"}"#));
+    assert!(api_request(&CONTROL, "PUT", "volume", r#"{"volume":18}"#).starts_with("HTTP/1.1 503"));
+}
+
+#[test]
+fn api_reports_a_failed_module() {
+    static CONTROL: Control = Control::new();
+    CONTROL.state.publish(PlayerState::Error(ModuleError::SdCard));
+    for (method, path, body) in [
+	("POST", "play", r#"{"track":26}"#),
+	("POST", "pause", ""),
+	("POST", "resume", ""),
+	("PUT", "volume", r#"{"volume":18}"#),
+	("POST", "volume/up", ""),
+	("POST", "playlist/skip", ""),
+    ] {
+	let response = api_request(&CONTROL, method, path, body);
+	assert!(response.starts_with("HTTP/1.1 503"), "{method} {path}: {response}");
+	assert!(response.ends_with(r#"{"error":"player unavailable"}"#), "{method} {path}: {response}");
+    }
+    assert!(CONTROL.commands.try_receive().is_err());
+
+    // stopping clears the failure once the module answers
+    assert!(api_request(&CONTROL, "POST", "stop", "").starts_with("HTTP/1.1 204"));
+    assert_eq!(CONTROL.commands.try_receive(), Ok(ControlMessages::Stop));
+}
+
+#[test]
+fn api_reports_a_removed_card() {
+    static CONTROL: Control = Control::new();
+    CONTROL.device.update(|info| info.card_removed = true);
+    let response = api_request(&CONTROL, "POST", "play", r#"{"track":26}"#);
+    assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+    assert!(response.ends_with(r#"{"error":"player unavailable"}"#));
+    assert!(CONTROL.commands.try_receive().is_err());
+
+    CONTROL.device.update(|info| info.card_removed = false);
+    assert!(api_request(&CONTROL, "POST", "play", r#"{"track":26}"#).starts_with("HTTP/1.1 204"));
+}
+
+/// Sends `body` to `path` of the REST interface with `method`, as the admin
+/// with the credentials in `authorization`.
+fn admin_request(control: &'static Control, method: &str, path: &str, authorization: &str, body: &str) -> String {
+    serve(
+	control,
+	&format!(
+	    "{method} /api/v1/{path} HTTP/1.1\r\nAuthorization: {authorization}\r\nContent-Length: {}\r\n\r\n{body}",
+	    body.len(),
+	),
+    )
+}
+
+/** `admin:noche de paz` and `admin:villancico` */
+const ADMIN : &str = "Basic YWRtaW46bm9jaGUgZGUgcGF6";
+const INTRUDER : &str = "Basic YWRtaW46dmlsbGFuY2ljbw==";
+
+#[test]
+fn settings_take_the_admin_password() {
+    static CONTROL: Control = Control::new();
+    // anybody, until there is a password
+    assert!(serve(&CONTROL, "GET /settings HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 200"));
+    let response = api_request(&CONTROL, "POST", "settings/password", r#"{"password":"corto"}"#);
+    assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+    let response = api_request(&CONTROL, "POST", "settings/password", r#"{"password":"noche de paz"}"#);
+    assert!(response.starts_with("HTTP/1.1 204"), "{response}");
+    assert_eq!(CONTROL.settings.get().admin_password, "noche de paz");
+
+    for authorization in ["", INTRUDER, "Basic !!!!", "Bearer YWRtaW46bm9jaGUgZGUgcGF6"] {
+	let response = serve(&CONTROL, &format!("GET /settings HTTP/1.1\r\nAuthorization: {authorization}\r\n\r\n"));
+	assert!(response.starts_with("HTTP/1.1 401"), "{authorization}: {response}");
+	assert!(response.contains("WWW-Authenticate: Basic realm=\"pesebre\""));
+	assert!(response.ends_with(r#"{"error":"unauthorized"}"#));
+    }
+    let response = serve(&CONTROL, &format!("GET /settings HTTP/1.1\r\nAuthorization: {ADMIN}\r\n\r\n"));
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("<h1>Ajustes</h1>"));
+
+    let response = admin_request(&CONTROL, "POST", "settings/password", INTRUDER, r#"{"password":"villancico"}"#);
+    assert!(response.starts_with("HTTP/1.1 401"));
+    assert!(admin_request(&CONTROL, "GET", "settings/access-point", INTRUDER, "").starts_with("HTTP/1.1 401"));
+    assert_eq!(CONTROL.settings.get().admin_password, "noche de paz");
+}
+
+#[test]
+fn settings_are_closed_without_the_admin_password() {
+    static CONTROL: Control = Control::new();
+    for (method, path, body) in [
+	("GET", "settings/access-point", ""),
+	("PUT", "settings/access-point", r#"{"password":"noche buena"}"#),
+	("GET", "setup/networks", ""),
+	("GET", "setup/network", ""),
+	("POST", "setup/network", r#"{"ssid":"Casa"}"#),
+    ] {
+	let response = api_request(&CONTROL, method, path, body);
+	assert!(response.starts_with("HTTP/1.1 409"), "{method} {path}: {response}");
+	assert!(response.ends_with(r#"{"error":"admin password not set"}"#), "{method} {path}: {response}");
+    }
+    assert_eq!(CONTROL.settings.get().access_point, AccessPointSettings::new());
+    assert_eq!(CONTROL.provisioning.trial(), Trial::Idle);
+
+    // nor from the household network
+    for path in ["/settings", "/setup"] {
+	let response = serve_on(&CONTROL, Interface::Station, &format!("GET {path} HTTP/1.1\r\n\r\n"));
+	assert!(response.starts_with("HTTP/1.1 409"), "{path}: {response}");
+    }
+    let response = serve_on(
+	&CONTROL,
+	Interface::Station,
+	"POST /api/v1/settings/password HTTP/1.1\r\nContent-Length: 27\r\n\r\n{\"password\":\"noche de paz\"}",
+    );
+    assert!(response.ends_with(r#"{"error":"admin password only set first on the access point"}"#), "{response}");
+
+    // once the network has a password, nobody can claim the settings
+    CONTROL.settings.update(|settings| settings.access_point.password = "noche buena".into());
+    let response = api_request(&CONTROL, "POST", "settings/password", r#"{"password":"noche de paz"}"#);
+    assert!(response.starts_with("HTTP/1.1 409"), "{response}");
+    assert_eq!(CONTROL.settings.get().admin_password, "");
+}
+
+#[test]
+fn access_point_is_set_up() {
+    static CONTROL: Control = Control::new();
+    CONTROL.settings.update(|settings| settings.admin_password = "noche de paz".into());
+
+    let response = admin_request(&CONTROL, "GET", "settings/access-point", ADMIN, "");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with(r#"{"ssid":"pesebre-navideño","secured":false,"channel":1,"hidden":false,"max_clients":4}"#), "{response}");
+
+    // no going back to an open network
+    let response = admin_request(&CONTROL, "PUT", "settings/access-point", ADMIN, r#"{"channel":6}"#);
+    assert!(response.ends_with(r#"{"error":"password missing"}"#), "{response}");
+    let response = admin_request(&CONTROL, "PUT", "settings/access-point", ADMIN, r#"{"password":"belen","channel":6}"#);
+    assert!(response.starts_with("HTTP/1.1 400"));
+    let response = admin_request(&CONTROL, "PUT", "settings/access-point", ADMIN, r#"{"password":"noche buena","channel":14}"#);
+    assert!(response.ends_with(r#"{"error":"channel must be 1 to 13"}"#), "{response}");
+    assert_eq!(CONTROL.settings.get().access_point, AccessPointSettings::new());
+
+    let response = admin_request(
+	&CONTROL,
+	"PUT",
+	"settings/access-point",
+	ADMIN,
+	r#"{"ssid":"Pesebre \"Hurtado\"","password":"noche\\buena","channel":6,"hidden":true,"max_clients":8}"#,
+    );
+    assert!(response.starts_with("HTTP/1.1 204"), "{response}");
+    let access_point = CONTROL.settings.get().access_point;
+    assert_eq!(access_point.ssid, "Pesebre \"Hurtado\"");
+    assert_eq!(access_point.password, "noche\\buena");
+    assert_eq!((access_point.channel, access_point.hidden, access_point.max_clients), (6, true, 8));
+
+    // the password stays when left out, and isn't read back
+    let response = admin_request(&CONTROL, "PUT", "settings/access-point", ADMIN, r#"{"hidden":false}"#);
+    assert!(response.starts_with("HTTP/1.1 204"));
+    let response = admin_request(&CONTROL, "GET", "settings/access-point", ADMIN, "");
+    assert!(response.ends_with(r#"{"ssid":"Pesebre \"Hurtado\"","secured":true,"channel":6,"hidden":false,"max_clients":8}"#), "{response}");
+    assert_eq!(CONTROL.settings.get().access_point.password, "noche\\buena");
+}
+
+#[test]
+fn household_network_is_set_up() {
+    static CONTROL: Control = Control::new();
+    CONTROL.settings.update(|settings| settings.admin_password = "noche de paz".into());
+    assert!(serve(&CONTROL, "GET /setup HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 401"));
+    let response = serve(&CONTROL, &format!("GET /setup HTTP/1.1\r\nAuthorization: {ADMIN}\r\n\r\n"));
+    assert!(response.contains("<h1>Red Wi-Fi de la casa</h1>"));
+
+    let response = admin_request(&CONTROL, "GET", "setup/network", ADMIN, "");
+    assert!(response.ends_with(r#"{"state":"idle"}"#), "{response}");
+    for (body, status, error) in [
+	(r#"{"ssid":"Casa","password":"corto"}"#, "400", "password must be 8 to 63 printable ASCII characters"),
+	(r#"{"ssid":""}"#, "400", "ssid missing"),
+	(r#"{"password":"noche buena"}"#, "400", "malformed body"),
+    ] {
+	let response = admin_request(&CONTROL, "POST", "setup/network", ADMIN, body);
+	assert!(response.starts_with(&format!("HTTP/1.1 {status}")), "{body}: {response}");
+	assert!(response.ends_with(&format!(r#"{{"error":"{error}"}}"#)), "{body}: {response}");
+    }
+    assert!(admin_request(&CONTROL, "POST", "setup/network", INTRUDER, r#"{"ssid":"Casa"}"#).starts_with("HTTP/1.1 401"));
+    assert_eq!(CONTROL.provisioning.trial(), Trial::Idle);
+
+    let response = admin_request(&CONTROL, "POST", "setup/network", ADMIN, r#"{"ssid":"Casa Hurtado","password":"noche buena"}"#);
+    assert!(response.starts_with("HTTP/1.1 204"), "{response}");
+    let candidate = block_on(CONTROL.provisioning.candidate());
+    assert_eq!((candidate.ssid.as_str(), candidate.password.as_str()), ("Casa Hurtado", "noche buena"));
+    let response = admin_request(&CONTROL, "GET", "setup/network", ADMIN, "");
+    assert!(response.ends_with(r#"{"state":"testing"}"#), "{response}");
+
+    // one at a time
+    let response = admin_request(&CONTROL, "POST", "setup/network", ADMIN, r#"{"ssid":"Vecinos"}"#);
+    assert!(response.ends_with(r#"{"error":"already trying a network"}"#), "{response}");
+
+    CONTROL.provisioning.report(Trial::Joined([192, 168, 1, 50]));
+    let response = admin_request(&CONTROL, "GET", "setup/network", ADMIN, "");
+    assert!(response.ends_with(r#"{"state":"joined","address":"192.168.1.50"}"#), "{response}");
+}
@@ -0,0 +1,257 @@
+use pesebre_core::mdns::{MdnsResponder, Reply, MAX_MESSAGE_SIZE};
+
+const ADDRESS : [u8; 4] = [192, 168, 1, 50];
+
+/** Record types */
+const A : u16 = 1;
+const PTR : u16 = 12;
+const TXT : u16 = 16;
+const AAAA : u16 = 28;
+const SRV : u16 = 33;
+const ANY : u16 = 255;
+
+/// Class of a question wanting a unicast reply, of a cache flushing record.
+const QU : u16 = 0x8001;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Data {
+    Name(String),
+    Service{ port: u16, target: String },
+    Text(Vec<String>),
+    Address([u8; 4]),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    name: String,
+    kind: u16,
+    class: u16,
+    ttl: u32,
+    data: Data,
+}
+
+fn responder() -> MdnsResponder<'static> {
+    MdnsResponder::new(ADDRESS, 80, "0.1.0")
+}
+
+fn put_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.') {
+	packet.push(label.len() as u8);
+	packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+}
+
+/// Query with the `questions` given, as (name, type, class), and the
+/// pointers the querier knows, as (name, target, ttl).
+fn query(questions: &[(&str, u16, u16)], known: &[(&str, &str, u32)]) -> Vec<u8> {
+    let mut packet = vec![0, 0, 0, 0, 0, questions.len() as u8, 0, known.len() as u8, 0, 0, 0, 0];
+    for (name, kind, class) in questions {
+	put_name(&mut packet, name);
+	packet.extend_from_slice(&kind.to_be_bytes());
+	packet.extend_from_slice(&class.to_be_bytes());
+    }
+    for (name, target, ttl) in known {
+	put_name(&mut packet, name);
+	packet.extend_from_slice(&[0, 12, 0, 1]);
+	packet.extend_from_slice(&ttl.to_be_bytes());
+	let mut data = Vec::new();
+	put_name(&mut data, target);
+	packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+	packet.extend_from_slice(&data);
+    }
+    packet
+}
+
+fn read_name(message: &[u8], mut at: usize) -> (String, usize) {
+    let mut labels = Vec::new();
+    while message[at] != 0 {
+	let len = message[at] as usize;
+	labels.push(String::from_utf8(message[at + 1..at + 1 + len].to_vec()).unwrap());
+	at += 1 + len;
+    }
+    (labels.join("."), at + 1)
+}
+
+/// Answers and additional records of `reply`, after the questions, which
+/// end at `questions`.
+fn records(reply: &[u8], questions: usize) -> (Vec<Record>, Vec<Record>) {
+    let count = |at: usize| u16::from_be_bytes([reply[at], reply[at + 1]]) as usize;
+    assert_eq!(count(8), 0);
+    let mut records = Vec::new();
+    let mut at = questions;
+    for _ in 0..count(6) + count(10) {
+	let (name, end) = read_name(reply, at);
+	let fixed = &reply[end..end + 10];
+	let kind = u16::from_be_bytes([fixed[0], fixed[1]]);
+	let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+	let data = &reply[end + 10..end + 10 + len];
+	let data = match kind {
+	    PTR => Data::Name(read_name(reply, end + 10).0),
+	    SRV => Data::Service{
+		port: u16::from_be_bytes([data[4], data[5]]),
+		target: read_name(reply, end + 16).0,
+	    },
+	    TXT => {
+		let mut strings = Vec::new();
+		let mut rest = data;
+		while let [len, tail @ ..] = rest {
+		    strings.push(String::from_utf8(tail[..*len as usize].to_vec()).unwrap());
+		    rest = &tail[*len as usize..];
+		}
+		Data::Text(strings)
+	    }
+	    A => Data::Address(data.try_into().unwrap()),
+	    _ => panic!("unexpected record type {kind}"),
+	};
+	records.push(Record{
+	    name,
+	    kind,
+	    class: u16::from_be_bytes([fixed[2], fixed[3]]),
+	    ttl: u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+	    data,
+	});
+	at = end + 10 + len;
+    }
+    assert_eq!(at, reply.len());
+    let additional = records.split_off(count(6));
+    (records, additional)
+}
+
+fn exchange(request: &[u8], legacy: bool) -> Option<(Reply, Vec<u8>)> {
+    let mut response = [0; MAX_MESSAGE_SIZE];
+    let reply = responder().respond(request, &mut response, legacy)?;
+    Some((reply, response[..reply.len].to_vec()))
+}
+
+fn service() -> Record {
+    Record{ name: "_http._tcp.local".into(), kind: PTR, class: 1, ttl: 4500, data: Data::Name("Pesebre Navideño._http._tcp.local".into()) }
+}
+
+fn location() -> Record {
+    Record{ name: "Pesebre Navideño._http._tcp.local".into(), kind: SRV, class: QU, ttl: 120, data: Data::Service{ port: 80, target: "pesebre.local".into() } }
+}
+
+fn details() -> Record {
+    Record{
+	name: "Pesebre Navideño._http._tcp.local".into(),
+	kind: TXT,
+	class: QU,
+	ttl: 4500,
+	data: Data::Text(vec!["version=0.1.0".into(), "path=/api/v1".into()]),
+    }
+}
+
+fn address() -> Record {
+    Record{ name: "pesebre.local".into(), kind: A, class: QU, ttl: 120, data: Data::Address(ADDRESS) }
+}
+
+#[test]
+fn browsing_finds_the_control_panel() {
+    let (reply, response) = exchange(&query(&[("_http._tcp.local", PTR, 1)], &[]), false).unwrap();
+    assert!(!reply.unicast);
+    assert_eq!(&response[..6], &[0, 0, 0x84, 0, 0, 0]);
+    let (answers, additional) = records(&response, 12);
+    assert_eq!(answers, [service()]);
+    // all it takes to open the control panel without asking again
+    assert_eq!(additional, [location(), details(), address()]);
+}
+
+#[test]
+fn service_types_list_http() {
+    let (_, response) = exchange(&query(&[("_services._dns-sd._udp.local", PTR, 1)], &[]), false).unwrap();
+    let (answers, additional) = records(&response, 12);
+    assert_eq!(answers, [Record{
+	name: "_services._dns-sd._udp.local".into(),
+	kind: PTR,
+	class: 1,
+	ttl: 4500,
+	data: Data::Name("_http._tcp.local".into()),
+    }]);
+    assert_eq!(additional, []);
+}
+
+#[test]
+fn resolves_the_hostname() {
+    let (reply, response) = exchange(&query(&[("Pesebre.local", A, QU)], &[]), false).unwrap();
+    // asked for a unicast reply
+    assert!(reply.unicast);
+    assert_eq!(records(&response, 12), (vec![address()], vec![]));
+
+    // the service itself, with the address of its host
+    let (reply, response) = exchange(&query(&[("Pesebre Navideño._http._tcp.local", ANY, 1), ("pesebre.local", A, QU)], &[]), false).unwrap();
+    assert!(!reply.unicast);
+    assert_eq!(records(&response, 12), (vec![location(), details(), address()], vec![]));
+}
+
+#[test]
+fn nothing_to_answer_without_an_address() {
+    let mut responder = responder();
+    responder.address = [0; 4];
+    let mut response = [0u8; MAX_MESSAGE_SIZE];
+    let request = query(&[("pesebre.local", A, 1), ("_http._tcp.local", PTR, 1)], &[]);
+    assert_eq!(responder.respond(&request, &mut response, false), None);
+    assert_eq!(responder.respond(&request, &mut response, true), None);
+}
+
+#[test]
+fn other_names_go_unanswered() {
+    assert_eq!(exchange(&query(&[("impresora.local", A, 1)], &[]), false), None);
+    assert_eq!(exchange(&query(&[("_ipp._tcp.local", PTR, 1)], &[]), false), None);
+    // only an IPv4 address
+    assert_eq!(exchange(&query(&[("pesebre.local", AAAA, 1)], &[]), false), None);
+}
+
+#[test]
+fn known_answers_are_not_repeated() {
+    let browse = [("_http._tcp.local", PTR, 1)];
+    let known = query(&browse, &[("_http._tcp.local", "Pesebre Navideño._http._tcp.local", 4000)]);
+    assert_eq!(exchange(&known, false), None);
+
+    // unless about to expire
+    let expiring = query(&browse, &[("_http._tcp.local", "Pesebre Navideño._http._tcp.local", 1000)]);
+    let (_, response) = exchange(&expiring, false).unwrap();
+    assert_eq!(records(&response, 12).0, [service()]);
+
+    // other services known don't count
+    let others = query(&browse, &[("_http._tcp.local", "Impresora._http._tcp.local", 4500)]);
+    assert!(exchange(&others, false).is_some());
+}
+
+#[test]
+fn legacy_queries_get_plain_dns_replies() {
+    // dig -p 5353 @224.0.0.251 pesebre.local
+    let mut dig = query(&[("pesebre.local", A, 1)], &[]);
+    dig[..4].copy_from_slice(&[0x6b, 0x3a, 0x01, 0x00]);
+    let (reply, response) = exchange(&dig, true).unwrap();
+    assert!(reply.unicast);
+    assert_eq!(&response[..6], &[0x6b, 0x3a, 0x84, 0, 0, 1]);
+    assert_eq!(&response[12..dig.len()], &dig[12..]);
+    let (answers, _) = records(&response, dig.len());
+    // no cache flushing, and short lived
+    assert_eq!(answers, [Record{ class: 1, ttl: 10, ..address() }]);
+}
+
+#[test]
+fn announces_everything() {
+    let mut response = [0; MAX_MESSAGE_SIZE];
+    let len = responder().announce(&mut response);
+    let (answers, additional) = records(&response[..len], 12);
+    assert_eq!(&response[..6], &[0, 0, 0x84, 0, 0, 0]);
+    assert_eq!(answers[1..], [service(), location(), details(), address()]);
+    assert_eq!(additional, []);
+}
+
+#[test]
+fn ignores_garbage() {
+    assert_eq!(exchange(&[0, 0, 0, 0, 0, 1], false), None);
+
+    // the replies of the other responders of the network
+    let mut reply = query(&[("pesebre.local", A, 1)], &[]);
+    reply[2] = 0x84;
+    assert_eq!(exchange(&reply, false), None);
+
+    let mut truncated = query(&[("pesebre.local", A, 1)], &[]);
+    truncated.truncate(20);
+    assert_eq!(exchange(&truncated, false), None);
+}
@@ -0,0 +1,108 @@
+use embassy_time::{Duration, Instant};
+use futures::executor::block_on;
+use futures::FutureExt;
+
+use pesebre_core::catalog;
+use pesebre_core::dfplayer_mini::ModuleError;
+use pesebre_core::player::{PlayerState, StateView, STATE_WATCHERS};
+
+fn at(secs: u64) -> Instant {
+    Instant::from_secs(secs)
+}
+
+#[test]
+fn pauses_keep_the_elapsed_time() {
+    let track = catalog::find(37).unwrap();
+    let mut state = PlayerState::Idle;
+    state.play(track, at(10));
+    assert_eq!(state.elapsed(at(15)), Some(Duration::from_secs(5)));
+
+    state.pause(at(15));
+    assert_eq!(state, PlayerState::Paused{ track, elapsed: Duration::from_secs(5) });
+    assert_eq!(state.elapsed(at(100)), Some(Duration::from_secs(5)));
+
+    state.resume(at(100));
+    assert_eq!(state, PlayerState::Playing{ track, started_at: at(95) });
+    assert_eq!(state.elapsed(at(102)), Some(Duration::from_secs(7)));
+}
+
+#[test]
+fn nothing_to_pause_or_resume() {
+    let mut state = PlayerState::Stopped;
+    state.pause(at(1));
+    state.resume(at(2));
+    assert_eq!(state, PlayerState::Stopped);
+    assert!(!state.is_paused());
+    assert_eq!(state.track(), None);
+}
+
+#[test]
+fn only_the_current_track_finishes() {
+    let track = catalog::find(37).unwrap();
+    let mut state = PlayerState::Idle;
+    state.play(track, at(0));
+    assert!(!state.finish(26));
+    assert!(state.finish(37));
+    assert_eq!(state, PlayerState::Idle);
+    // the module's second report of the same end
+    assert!(!state.finish(37));
+}
+
+#[test]
+fn errors_and_stops() {
+    let mut state = PlayerState::Idle;
+    state.play(catalog::find(26).unwrap(), at(0));
+    state.fail(ModuleError::NotFound);
+    assert_eq!(state, PlayerState::Error(ModuleError::NotFound));
+    assert_eq!(state.elapsed(at(1)), None);
+    state.stop();
+    assert_eq!(state, PlayerState::Stopped);
+}
+
+#[test]
+fn transient_errors_leave_the_track_playing() {
+    let track = catalog::find(26).unwrap();
+    let mut state = PlayerState::Idle;
+    state.play(track, at(0));
+    for error in [ModuleError::Busy, ModuleError::SerialError, ModuleError::Checksum] {
+	assert!(!state.report(error));
+    }
+    assert_eq!(state, PlayerState::Playing{ track, started_at: at(0) });
+    assert!(state.report(ModuleError::SdCard));
+    assert_eq!(state, PlayerState::Error(ModuleError::SdCard));
+
+    // once the card is back
+    state.recover();
+    assert_eq!(state, PlayerState::Idle);
+    state.stop();
+    state.recover();
+    assert_eq!(state, PlayerState::Stopped);
+}
+
+#[test]
+fn view_keeps_the_published_state() {
+    let view = StateView::new();
+    assert_eq!(view.get(), PlayerState::Idle);
+    view.publish(PlayerState::Stopped);
+    assert_eq!(view.get(), PlayerState::Stopped);
+}
+
+#[test]
+fn watchers_wait_for_a_change() {
+    let view = StateView::new();
+    let mut watcher = view.watch().unwrap();
+    assert_eq!(watcher.changed().now_or_never(), None);
+
+    // the same state again isn't a change
+    view.publish(PlayerState::Idle);
+    assert_eq!(watcher.changed().now_or_never(), None);
+
+    // the state at the last change, once
+    view.publish(PlayerState::Stopped);
+    view.publish(PlayerState::Error(ModuleError::NotFound));
+    assert_eq!(block_on(watcher.changed()), PlayerState::Error(ModuleError::NotFound));
+    assert_eq!(watcher.changed().now_or_never(), None);
+
+    let _others: Vec<_> = (1..STATE_WATCHERS).map(|_| view.watch().unwrap()).collect();
+    assert!(view.watch().is_err());
+}
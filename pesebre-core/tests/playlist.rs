@@ -0,0 +1,75 @@
+use pesebre_core::catalog::{self, Track};
+use pesebre_core::playlist::{Playlist, PlaylistError, PlaylistView, PLAYLIST_SIZE};
+
+fn track(id: u16) -> &'static Track {
+    catalog::find(id).unwrap()
+}
+
+fn ids(playlist: &Playlist) -> Vec<u16> {
+    playlist.iter().map(|track| track.id).collect()
+}
+
+#[test]
+fn tracks_play_in_order() {
+    let mut playlist = Playlist::new();
+    assert!(playlist.is_empty());
+    for id in [19, 20, 21] {
+	playlist.enqueue(track(id)).unwrap();
+    }
+    playlist.play_next(track(37)).unwrap();
+    assert_eq!(ids(&playlist), [37, 19, 20, 21]);
+
+    assert_eq!(playlist.pop_next(), Some(track(37)));
+    assert_eq!(playlist.pop_next(), Some(track(19)));
+    assert_eq!(playlist.len(), 2);
+    playlist.clear();
+    assert_eq!(playlist.pop_next(), None);
+}
+
+#[test]
+fn full_playlist_rejects_tracks() {
+    let mut playlist = Playlist::new();
+    for _ in 0..PLAYLIST_SIZE {
+	playlist.enqueue(track(19)).unwrap();
+    }
+    assert_eq!(playlist.enqueue(track(20)), Err(PlaylistError::Full));
+    assert_eq!(playlist.play_next(track(20)), Err(PlaylistError::Full));
+    assert_eq!(playlist.len(), PLAYLIST_SIZE);
+}
+
+#[test]
+fn tracks_are_removed() {
+    let mut playlist = Playlist::new();
+    for id in [19, 20, 21] {
+	playlist.enqueue(track(id)).unwrap();
+    }
+    assert_eq!(playlist.remove(1), Ok(track(20)));
+    assert_eq!(playlist.remove(2), Err(PlaylistError::OutOfRange(2)));
+    assert_eq!(ids(&playlist), [19, 21]);
+}
+
+#[test]
+fn tracks_are_reordered() {
+    let mut playlist = Playlist::new();
+    for id in [19, 20, 21, 22] {
+	playlist.enqueue(track(id)).unwrap();
+    }
+    playlist.reorder(0, 2).unwrap();
+    assert_eq!(ids(&playlist), [20, 21, 19, 22]);
+    playlist.reorder(3, 0).unwrap();
+    assert_eq!(ids(&playlist), [22, 20, 21, 19]);
+    playlist.reorder(1, 1).unwrap();
+    assert_eq!(ids(&playlist), [22, 20, 21, 19]);
+    assert_eq!(playlist.reorder(4, 0), Err(PlaylistError::OutOfRange(4)));
+    assert_eq!(playlist.reorder(0, 4), Err(PlaylistError::OutOfRange(4)));
+}
+
+#[test]
+fn view_keeps_the_published_copy() {
+    let view = PlaylistView::new();
+    let mut playlist = Playlist::new();
+    playlist.enqueue(track(19)).unwrap();
+    view.publish(&playlist);
+    playlist.enqueue(track(20)).unwrap();
+    assert_eq!(ids(&view.get()), [19]);
+}
@@ -0,0 +1,76 @@
+use futures::executor::block_on;
+use futures::future::join;
+use heapless::String;
+
+use pesebre_core::provisioning::{add_network, Network, Networks, Provisioning, Security, Trial, MAX_NETWORKS};
+
+fn network(ssid: &str, rssi: i8) -> Network {
+    Network{ ssid: ssid.into(), rssi, security: Security::Wpa2 }
+}
+
+#[test]
+fn networks_are_listed_strongest_first() {
+    let mut networks = Networks::new();
+    add_network(&mut networks, network("Casa", -70));
+    add_network(&mut networks, network("Vecinos", -50));
+    add_network(&mut networks, network("", -30));
+    // another access point of the same network
+    add_network(&mut networks, network("Casa", -40));
+    add_network(&mut networks, network("Vecinos", -80));
+    assert_eq!(networks, [network("Casa", -40), network("Vecinos", -50)]);
+}
+
+#[test]
+fn only_the_strongest_networks_are_kept() {
+    let mut networks = Networks::new();
+    for rssi in 0..MAX_NETWORKS as i8 {
+	let mut ssid = String::<32>::new();
+	core::fmt::write(&mut ssid, format_args!("Red {rssi}")).unwrap();
+	add_network(&mut networks, Network{ ssid, rssi: -60 - rssi, security: Security::Open });
+    }
+    add_network(&mut networks, network("Lejana", -90));
+    assert!(networks.iter().all(|known| known.ssid != "Lejana"));
+    add_network(&mut networks, network("Casa", -30));
+    assert_eq!(networks.len(), MAX_NETWORKS);
+    assert_eq!(networks[0], network("Casa", -30));
+    assert_eq!(networks.last().unwrap().rssi, -60 - MAX_NETWORKS as i8 + 2);
+}
+
+#[test]
+fn networks_are_serialized() {
+    let networks: Networks = [network("Casa", -40), Network{ ssid: "Café".into(), rssi: -81, security: Security::Open }]
+	.into_iter()
+	.collect();
+    let mut buffer = [0; 128];
+    let len = serde_json_core::to_slice(&networks, &mut buffer).unwrap();
+    assert_eq!(
+	core::str::from_utf8(&buffer[..len]).unwrap(),
+	r#"[{"ssid":"Casa","rssi":-40,"security":"wpa2"},{"ssid":"Café","rssi":-81,"security":"open"}]"#,
+    );
+}
+
+#[test]
+fn trials_are_serialized() {
+    for (trial, json) in [
+	(Trial::Idle, r#"{"state":"idle"}"#),
+	(Trial::Testing, r#"{"state":"testing"}"#),
+	(Trial::Joined([192, 168, 1, 50]), r#"{"state":"joined","address":"192.168.1.50"}"#),
+	(Trial::Failed("wrong password"), r#"{"state":"failed","error":"wrong password"}"#),
+    ] {
+	let mut buffer = [0; 64];
+	let len = serde_json_core::to_slice(&trial, &mut buffer).unwrap();
+	assert_eq!(core::str::from_utf8(&buffer[..len]).unwrap(), json);
+    }
+}
+
+#[test]
+fn scans_are_handed_over() {
+    let provisioning = Provisioning::new();
+    let scanned: Networks = [network("Casa", -40)].into_iter().collect();
+    let (networks, ()) = block_on(join(provisioning.scan(), async {
+	provisioning.scan_requested().await;
+	provisioning.publish_networks(&scanned);
+    }));
+    assert_eq!(networks, scanned);
+    assert_eq!(provisioning.networks(), scanned);
+}
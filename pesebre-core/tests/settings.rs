@@ -0,0 +1,203 @@
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+use pesebre_core::dfplayer_mini::Equalizer;
+use pesebre_core::settings::{AccessPointSettings, Schedule, Settings, SettingsStore, DEFAULT_AP_SSID};
+
+const SECTOR_SIZE : usize = 256;
+
+/// Flash in RAM that, like NOR flash, only clears bits when written.
+struct RamFlash {
+    data: Vec<u8>,
+    /// Times each sector was erased.
+    erases: Vec<usize>,
+}
+
+impl RamFlash {
+    fn new(sectors: usize) -> Self {
+	Self{ data: vec![0xff; sectors * SECTOR_SIZE], erases: vec![0; sectors] }
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE : usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+	let start = offset as usize;
+	if start % Self::READ_SIZE != 0 || bytes.len() % Self::READ_SIZE != 0 {
+	    return Err(NorFlashErrorKind::NotAligned);
+	}
+	let data = self.data.get(start..start + bytes.len()).ok_or(NorFlashErrorKind::OutOfBounds)?;
+	bytes.copy_from_slice(data);
+	Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+	self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE : usize = 4;
+    const ERASE_SIZE : usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+	let (from, to) = (from as usize, to as usize);
+	if from % SECTOR_SIZE != 0 || to % SECTOR_SIZE != 0 {
+	    return Err(NorFlashErrorKind::NotAligned);
+	}
+	self.data.get_mut(from..to).ok_or(NorFlashErrorKind::OutOfBounds)?.fill(0xff);
+	for sector in from / SECTOR_SIZE..to / SECTOR_SIZE {
+	    self.erases[sector] += 1;
+	}
+	Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+	let start = offset as usize;
+	if start % Self::WRITE_SIZE != 0 || bytes.len() % Self::WRITE_SIZE != 0 {
+	    return Err(NorFlashErrorKind::NotAligned);
+	}
+	let data = self.data.get_mut(start..start + bytes.len()).ok_or(NorFlashErrorKind::OutOfBounds)?;
+	for (old, new) in data.iter_mut().zip(bytes) {
+	    *old &= new;
+	}
+	Ok(())
+    }
+}
+
+fn some_settings(volume: u8) -> Settings {
+    let mut settings = Settings::new();
+    settings.volume = volume;
+    settings.max_volume = 28;
+    settings.eq = Equalizer::Jazz;
+    settings.wifi_ssid = "Casa Hurtado".into();
+    settings.wifi_password = "villancico".into();
+    settings.light_program = 2;
+    settings.schedules.push(Schedule{ start: 18 * 60, end: 22 * 60 }).unwrap();
+    settings.welcome_track = None;
+    settings.access_point.ssid = "Pesebre Hurtado".into();
+    settings.access_point.password = "noche buena".into();
+    settings.access_point.channel = 6;
+    settings.access_point.hidden = true;
+    settings.access_point.max_clients = 8;
+    settings.admin_password = "san jose".into();
+    settings
+}
+
+#[test]
+fn blank_flash_has_the_defaults() {
+    let mut store = SettingsStore::new(RamFlash::new(2), 0, 2);
+    assert_eq!(store.load(), Settings::new());
+}
+
+#[test]
+fn saved_settings_survive_a_reboot() {
+    let mut store = SettingsStore::new(RamFlash::new(3), SECTOR_SIZE as u32, 2);
+    store.load();
+    store.save(&some_settings(12)).unwrap();
+    store.save(&some_settings(14)).unwrap();
+    let flash = store.release();
+    // nothing outside the sectors given
+    assert!(flash.data[..SECTOR_SIZE].iter().all(|byte| *byte == 0xff));
+
+    let mut store = SettingsStore::new(flash, SECTOR_SIZE as u32, 2);
+    assert_eq!(store.load(), some_settings(14));
+}
+
+#[test]
+fn unchanged_settings_are_not_written() {
+    let mut store = SettingsStore::new(RamFlash::new(2), 0, 2);
+    store.load();
+    store.save(&some_settings(12)).unwrap();
+    let written = store.release().data;
+
+    let mut store = SettingsStore::new(RamFlash{ data: written.clone(), erases: vec![0; 2] }, 0, 2);
+    store.load();
+    store.save(&some_settings(12)).unwrap();
+    assert_eq!(store.release().data, written);
+}
+
+#[test]
+fn sectors_are_erased_only_when_full() {
+    let mut store = SettingsStore::new(RamFlash::new(2), 0, 2);
+    store.load();
+    for volume in 0..30 {
+	store.save(&some_settings(volume)).unwrap();
+    }
+    let flash = store.release();
+    // a record takes 104 bytes, two to a sector
+    assert_eq!(flash.erases, [8, 7]);
+
+    let mut store = SettingsStore::new(flash, 0, 2);
+    assert_eq!(store.load(), some_settings(29));
+    // and goes on where it was
+    store.save(&some_settings(3)).unwrap();
+    store.save(&some_settings(4)).unwrap();
+    let flash = store.release();
+    assert_eq!(flash.erases, [8, 8]);
+
+    let mut store = SettingsStore::new(flash, 0, 2);
+    assert_eq!(store.load(), some_settings(4));
+}
+
+#[test]
+fn broken_record_falls_back_to_the_one_before() {
+    let mut store = SettingsStore::new(RamFlash::new(2), 0, 2);
+    store.load();
+    store.save(&some_settings(12)).unwrap();
+    store.save(&some_settings(14)).unwrap();
+    let mut flash = store.release();
+    // power failed while writing the second record
+    flash.data[104 + 40] = 0xff;
+
+    let mut store = SettingsStore::new(flash, 0, 2);
+    assert_eq!(store.load(), some_settings(12));
+
+    // the next record goes after the broken one, in a fresh sector
+    store.save(&some_settings(16)).unwrap();
+    let flash = store.release();
+    assert_eq!(flash.erases, [1, 1]);
+    let mut store = SettingsStore::new(flash, 0, 2);
+    assert_eq!(store.load(), some_settings(16));
+}
+
+#[test]
+fn foreign_data_gives_the_defaults() {
+    let mut flash = RamFlash::new(2);
+    for (i, byte) in flash.data.iter_mut().enumerate() {
+	*byte = i as u8;
+    }
+    let mut store = SettingsStore::new(flash, 0, 2);
+    assert_eq!(store.load(), Settings::new());
+
+    store.save(&some_settings(12)).unwrap();
+    let mut store = SettingsStore::new(store.release(), 0, 2);
+    assert_eq!(store.load(), some_settings(12));
+}
+
+#[test]
+fn first_boot_access_point_is_open() {
+    let access_point = Settings::new().access_point;
+    assert_eq!(access_point.ssid(), DEFAULT_AP_SSID);
+    assert!(access_point.is_open());
+    assert_eq!(access_point.check(), Ok(()));
+}
+
+#[test]
+fn access_point_is_checked() {
+    let secured = AccessPointSettings{ password: "noche buena".into(), ..AccessPointSettings::new() };
+    assert_eq!(secured.check(), Ok(()));
+    for (access_point, error) in [
+	(AccessPointSettings{ password: "belen".into(), ..secured.clone() }, "password must be 8 to 63 printable ASCII characters"),
+	(AccessPointSettings{ password: "nöche buena".into(), ..secured.clone() }, "password must be 8 to 63 printable ASCII characters"),
+	(AccessPointSettings{ channel: 14, ..secured.clone() }, "channel must be 1 to 13"),
+	(AccessPointSettings{ max_clients: 0, ..secured.clone() }, "max clients must be 1 to 10"),
+	(AccessPointSettings{ max_clients: 11, ..secured.clone() }, "max clients must be 1 to 10"),
+    ] {
+	assert_eq!(access_point.check(), Err(error));
+    }
+}
@@ -0,0 +1,225 @@
+use core::future::Future;
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{Read, Write};
+use futures::{executor::block_on, future::select, pin_mut};
+
+use pesebre_core::catalog;
+use pesebre_core::dfplayer_mini::simulator::{Layout, Simulator, Track};
+use pesebre_core::dfplayer_mini::{
+    route, Device, DfEvent, DfEventChannel, DfPlayer, DfPlayerError, DfReplyChannel, Equalizer,
+    FrameDecoder, ModuleError, Playback, ReliableConfig, Volume,
+};
+use pesebre_core::player::PlayerState;
+
+const TRACKS : [Duration; 40] = [Duration::from_secs(3); 40];
+const FOLDERS : [u16; 2] = [5, 2];
+
+fn layout() -> Layout<'static> {
+    Layout{
+	tracks: &TRACKS,
+	folders: &FOLDERS,
+	folder_track_length: Duration::from_secs(1),
+    }
+}
+
+/// Does what the UART reader task does on the board, against the simulator.
+async fn reader(sim: &Simulator<'_>, replies: &DfReplyChannel, events: &DfEventChannel) {
+    let mut decoder = FrameDecoder::new();
+    let mut buf = [0u8; 16];
+    let mut rx = sim;
+    loop {
+	let len = rx.read(&mut buf).await.unwrap();
+	for response in decoder.decode(&buf[..len]) {
+	    route(replies, events, response.unwrap());
+	}
+    }
+}
+
+/// Runs `test` while the reader feeds the module answers to the driver.
+fn run(sim: &Simulator<'_>, replies: &DfReplyChannel, events: &DfEventChannel, test: impl Future<Output = ()>) {
+    let reader = reader(sim, replies, events);
+    pin_mut!(reader);
+    pin_mut!(test);
+    block_on(select(reader, test));
+}
+
+/// Gives the reader the chance to route what the simulator sent.
+async fn settle() {
+    Timer::after(Duration::from_millis(10)).await;
+}
+
+#[test]
+fn queries_report_the_module_state() {
+    let sim = Simulator::new(layout());
+    let replies = DfReplyChannel::new();
+    let events = DfEventChannel::new();
+    let mut player = DfPlayer::new(&sim, &replies);
+
+    run(&sim, &replies, &events, async {
+	player.volume(20).await.unwrap();
+	player.eq_select(2).await.unwrap();
+	player.play(37).await.unwrap();
+
+	assert_eq!(player.query_volume().await, Ok(Volume(20)));
+	assert_eq!(player.query_eq().await, Ok(Equalizer::Rock));
+	assert_eq!(player.query_tf_track().await, Ok(37));
+	assert_eq!(player.query_tf_files().await, Ok(40));
+	assert_eq!(player.query_folders().await, Ok(2));
+	assert_eq!(player.query_folder_files(1).await, Ok(5));
+
+	let status = player.query_status().await.unwrap();
+	assert_eq!(status.device, Some(Device::Tf));
+	assert_eq!(status.playback, Playback::Playing);
+    });
+}
+
+#[test]
+fn pause_resume_and_stop() {
+    let sim = Simulator::new(layout());
+    let replies = DfReplyChannel::new();
+    let events = DfEventChannel::new();
+    let mut player = DfPlayer::new(&sim, &replies);
+
+    run(&sim, &replies, &events, async {
+	player.play_folder(2, 1).await.unwrap();
+	assert_eq!(sim.state().track, Some(Track::Folder{ folder: 2, track: 1 }));
+
+	player.pause().await.unwrap();
+	assert_eq!(sim.state().playback, Playback::Paused);
+	player.resume().await.unwrap();
+	assert_eq!(sim.state().playback, Playback::Playing);
+
+	player.stop().await.unwrap();
+	assert_eq!(player.query_status().await.map(|s| s.playback), Ok(Playback::Stopped));
+    });
+}
+
+#[test]
+fn track_finishes_after_its_length() {
+    let sim = Simulator::new(layout());
+    let replies = DfReplyChannel::new();
+    let events = DfEventChannel::new();
+    let mut player = DfPlayer::new(&sim, &replies);
+
+    run(&sim, &replies, &events, async {
+	sim.power_on();
+	player.play(5).await.unwrap();
+
+	sim.advance(Duration::from_secs(2));
+	settle().await;
+	assert_eq!(events.try_receive(), Ok(DfEvent::Online(0x02)));
+	assert!(events.try_receive().is_err());
+
+	sim.advance(Duration::from_secs(1));
+	settle().await;
+	assert_eq!(events.try_receive(), Ok(DfEvent::TrackFinished{ device: Device::Tf, track: 5 }));
+	assert_eq!(sim.state().playback, Playback::Stopped);
+    });
+}
+
+#[test]
+fn missing_track_is_a_module_error() {
+    let sim = Simulator::new(layout());
+    let replies = DfReplyChannel::new();
+    let events = DfEventChannel::new();
+    let mut player = DfPlayer::new(&sim, &replies);
+    player.set_reliable(Some(ReliableConfig::default()));
+
+    run(&sim, &replies, &events, async {
+	assert_eq!(player.play(41).await, Err(DfPlayerError::Module(ModuleError::NotFound)));
+	assert_eq!(player.play_folder(3, 1).await, Err(DfPlayerError::Module(ModuleError::NotFound)));
+    });
+}
+
+#[test]
+fn empty_card_has_no_next_track() {
+    let sim = Simulator::new(Layout{ tracks: &[], ..layout() });
+    let replies = DfReplyChannel::new();
+    let events = DfEventChannel::new();
+    let mut player = DfPlayer::new(&sim, &replies);
+    player.set_reliable(Some(ReliableConfig::default()));
+
+    run(&sim, &replies, &events, async {
+	assert_eq!(player.play_next().await, Err(DfPlayerError::Module(ModuleError::NotFound)));
+	assert_eq!(player.play_previous().await, Err(DfPlayerError::Module(ModuleError::NotFound)));
+    });
+    assert_eq!(sim.state().track, None);
+}
+
+#[test]
+fn reliable_mode_retries_lost_acks() {
+    let sim = Simulator::new(layout());
+    let replies = DfReplyChannel::new();
+    let events = DfEventChannel::new();
+    let mut player = DfPlayer::new(&sim, &replies);
+    let config = ReliableConfig{
+	ack_timeout: Duration::from_millis(20),
+	retries: 2,
+	backoff: Duration::from_millis(5),
+    };
+    player.set_reliable(Some(config));
+
+    run(&sim, &replies, &events, async {
+	sim.drop_acks(2);
+	assert_eq!(player.play(3).await, Ok(()));
+
+	sim.drop_acks(3);
+	assert_eq!(player.play(4).await, Err(DfPlayerError::NotAcknowledged{ command: 0x03, attempts: 3 }));
+    });
+}
+
+#[test]
+fn only_lasting_module_errors_fail_the_player() {
+    let sim = Simulator::new(layout());
+    let replies = DfReplyChannel::new();
+    let events = DfEventChannel::new();
+    let mut player = DfPlayer::new(&sim, &replies);
+    let track = catalog::find(1).unwrap();
+    let mut state = PlayerState::Idle;
+
+    run(&sim, &replies, &events, async {
+	player.play(track.id).await.unwrap();
+	state.play(track, Instant::from_secs(0));
+
+	// a frame with its checksum wrong
+	let mut tx = &sim;
+	tx.write_all(&[0x7E, 0xFF, 0x06, 0x03, 0x00, 0x00, 0x01, 0x00, 0x00, 0xEF]).await.unwrap();
+	settle().await;
+	let Ok(DfEvent::Error(why)) = events.try_receive() else { panic!("no error reported") };
+	assert_eq!(why, ModuleError::Checksum);
+	assert!(!state.report(why));
+	assert_eq!(state.track(), Some(track));
+
+	sim.remove_card();
+	settle().await;
+	assert_eq!(events.try_receive(), Ok(DfEvent::MediaRemoved(Device::Tf)));
+	player.play(track.id).await.unwrap();
+	settle().await;
+	let Ok(DfEvent::Error(why)) = events.try_receive() else { panic!("no error reported") };
+	assert!(state.report(why));
+	assert_eq!(state, PlayerState::Error(ModuleError::SdCard));
+    });
+}
+
+#[test]
+fn card_removal_is_reported() {
+    let sim = Simulator::new(layout());
+    let replies = DfReplyChannel::new();
+    let events = DfEventChannel::new();
+    let mut player = DfPlayer::new(&sim, &replies);
+    player.set_reliable(Some(ReliableConfig::default()));
+
+    run(&sim, &replies, &events, async {
+	sim.remove_card();
+	settle().await;
+	assert_eq!(events.try_receive(), Ok(DfEvent::MediaRemoved(Device::Tf)));
+	assert_eq!(player.play(1).await, Err(DfPlayerError::Module(ModuleError::SdCard)));
+	assert_eq!(events.try_receive(), Ok(DfEvent::Error(ModuleError::SdCard)));
+
+	sim.insert_card();
+	settle().await;
+	assert_eq!(events.try_receive(), Ok(DfEvent::MediaInserted(Device::Tf)));
+	assert_eq!(player.play(1).await, Ok(()));
+    });
+}
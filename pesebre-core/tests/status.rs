@@ -0,0 +1,56 @@
+use embassy_time::{Duration, Instant};
+
+use pesebre_core::catalog;
+use pesebre_core::control::Control;
+use pesebre_core::dfplayer_mini::{Device, Equalizer};
+use pesebre_core::player::PlayerState;
+use pesebre_core::playlist::Playlist;
+use pesebre_core::status::{Status, TrackInfo};
+
+#[test]
+fn status_gathers_the_shared_state() {
+    let control = Control::new();
+    let track = catalog::find(26).unwrap();
+    control.state.publish(PlayerState::Playing{ track, started_at: Instant::from_secs(100) });
+    let mut playlist = Playlist::new();
+    playlist.enqueue(catalog::find(37).unwrap()).unwrap();
+    control.playlist.publish(&playlist);
+    control.device.update(|info| {
+	info.volume = 20;
+	info.muted = true;
+	info.max_volume = 24;
+	info.eq = Some(Equalizer::Jazz);
+	info.source = Some(Device::Tf);
+    });
+    control.device.update(|info| {
+	info.stations = 2;
+	info.station_address = Some([192, 168, 1, 50]);
+    });
+
+    let status = Status::new(&control, Instant::from_secs(130));
+    assert_eq!(status.state, "playing");
+    assert_eq!(status.track, Some(TrackInfo{ id: 26, title: "Agua" }));
+    assert_eq!(status.elapsed_ms, Some(30_000));
+    assert_eq!(status.volume, 20);
+    assert!(status.muted);
+    assert_eq!(status.max_volume, 24);
+    assert_eq!(status.eq, Some(Equalizer::Jazz));
+    assert_eq!(status.source, Some(Device::Tf));
+    assert_eq!(status.queue_length, 1);
+    assert_eq!(status.uptime_s, 130);
+    assert_eq!(status.stations, 2);
+    assert_eq!(status.station_address, Some([192, 168, 1, 50]));
+    assert_eq!(status.firmware_version, None);
+}
+
+#[test]
+fn idle_status_has_no_track() {
+    let control = Control::new();
+    control.state.publish(PlayerState::Paused{ track: catalog::find(26).unwrap(), elapsed: Duration::from_secs(4) });
+    control.state.publish(PlayerState::Stopped);
+
+    let status = Status::new(&control, Instant::from_secs(1));
+    assert_eq!(status.state, "stopped");
+    assert_eq!(status.track, None);
+    assert_eq!(status.elapsed_ms, None);
+}
@@ -0,0 +1,81 @@
+use pesebre_core::volume::{VolumeControl, DEFAULT_VOLUME, MAX_VOLUME};
+
+#[test]
+fn volume_stays_within_the_module_range() {
+    let mut volume = VolumeControl::new();
+    assert_eq!(volume.level(), DEFAULT_VOLUME);
+
+    volume.set(200);
+    assert_eq!(volume.level(), MAX_VOLUME);
+    volume.increase();
+    assert_eq!(volume.level(), MAX_VOLUME);
+
+    volume.set(0);
+    volume.decrease();
+    assert_eq!(volume.level(), 0);
+    volume.increase();
+    assert_eq!(volume.level(), 1);
+}
+
+#[test]
+fn mute_keeps_the_level() {
+    let mut volume = VolumeControl::new();
+    volume.set(18);
+    volume.mute();
+    assert!(volume.is_muted());
+    assert_eq!(volume.output(), 0);
+    assert_eq!(volume.level(), 18);
+
+    volume.unmute();
+    assert_eq!(volume.output(), 18);
+
+    // changing the volume unmutes
+    volume.mute();
+    volume.decrease();
+    assert!(!volume.is_muted());
+    assert_eq!(volume.output(), 17);
+}
+
+#[test]
+fn cap_limits_the_level() {
+    let mut volume = VolumeControl::new();
+    volume.set(25);
+    volume.set_max(12);
+    assert_eq!(volume.level(), 12);
+    volume.increase();
+    assert_eq!(volume.level(), 12);
+    volume.set(20);
+    assert_eq!(volume.level(), 12);
+
+    // raising the cap leaves the level alone
+    volume.set_max(99);
+    assert_eq!(volume.max(), MAX_VOLUME);
+    assert_eq!(volume.level(), 12);
+}
+
+#[test]
+fn adjustments_cap_first() {
+    let mut volume = VolumeControl::new();
+    volume.adjust(Some(22), Some(true), Some(20));
+    assert_eq!((volume.level(), volume.max(), volume.is_muted()), (20, 20, true));
+
+    // what isn't given stays
+    volume.adjust(None, Some(false), None);
+    assert_eq!((volume.level(), volume.max(), volume.is_muted()), (20, 20, false));
+    volume.adjust(Some(8), None, None);
+    assert_eq!((volume.level(), volume.max(), volume.is_muted()), (8, 20, false));
+}
+
+#[test]
+fn restored_volume_keeps_within_bounds() {
+    let volume = VolumeControl::restore(14, 20);
+    assert_eq!(volume.level(), 14);
+    assert_eq!(volume.max(), 20);
+    assert!(!volume.is_muted());
+
+    let volume = VolumeControl::restore(99, 20);
+    assert_eq!(volume.level(), 20);
+    let volume = VolumeControl::restore(25, 99);
+    assert_eq!(volume.max(), MAX_VOLUME);
+    assert_eq!(volume.level(), 25);
+}
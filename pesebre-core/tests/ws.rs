@@ -0,0 +1,66 @@
+use embassy_time::{Duration, Instant};
+
+use pesebre_core::api::ApiError;
+use pesebre_core::catalog;
+use pesebre_core::control::{Control, ControlMessages};
+use pesebre_core::player::PlayerState;
+use pesebre_core::playlist::Playlist;
+use pesebre_core::ws::{run_command, CommandError};
+
+#[test]
+fn commands_become_requests() {
+    static CONTROL: Control = Control::new();
+    let track = catalog::find(26).unwrap();
+    let mut playlist = Playlist::new();
+    for _ in 0..4 {
+	playlist.enqueue(track).unwrap();
+    }
+    CONTROL.playlist.publish(&playlist);
+    let volume = |level, muted, max| ControlMessages::AdjustVolume{ level, muted, max };
+
+    for (state, text, expected) in [
+	(PlayerState::Idle, r#"{"cmd":"play","track":26}"#, ControlMessages::Play(track)),
+	(PlayerState::Playing{ track, started_at: Instant::from_secs(0) }, r#"{"cmd":"pause"}"#, ControlMessages::Pause),
+	(PlayerState::Paused{ track, elapsed: Duration::from_secs(3) }, r#"{"cmd":"resume"}"#, ControlMessages::Resume),
+	(PlayerState::Idle, r#"{"cmd":"stop"}"#, ControlMessages::Stop),
+	(PlayerState::Idle, r#"{"cmd":"volume","value":20}"#, volume(Some(20), None, None)),
+	(PlayerState::Idle, r#"{"cmd":"inc-vol"}"#, ControlMessages::IncVol),
+	(PlayerState::Idle, r#"{"cmd":"dec-vol"}"#, ControlMessages::DecVol),
+	(PlayerState::Idle, r#"{"cmd":"mute"}"#, volume(None, Some(true), None)),
+	(PlayerState::Idle, r#"{"cmd":"unmute"}"#, volume(None, Some(false), None)),
+	(PlayerState::Idle, r#"{"cmd":"max-volume","value":12}"#, volume(None, None, Some(12))),
+	(PlayerState::Idle, r#"{"track":26,"cmd":"enqueue"}"#, ControlMessages::Enqueue(track)),
+	(PlayerState::Idle, r#"{"cmd":"play-next","track":26}"#, ControlMessages::PlayNext(track)),
+	(PlayerState::Idle, r#"{"cmd":"remove","position":2}"#, ControlMessages::Remove(2)),
+	(PlayerState::Idle, r#"{"cmd":"move","from":3,"to":0}"#, ControlMessages::Reorder{ from: 3, to: 0 }),
+	(PlayerState::Idle, r#"{"cmd":"clear"}"#, ControlMessages::ClearPlaylist),
+	(PlayerState::Idle, r#" { "cmd" : "skip" } "#, ControlMessages::Skip),
+    ] {
+	CONTROL.state.publish(state);
+	assert_eq!(run_command(&CONTROL, text), Ok(()), "{text}");
+	assert_eq!(CONTROL.commands.try_receive(), Ok(expected), "{text}");
+    }
+}
+
+#[test]
+fn bad_commands_are_rejected() {
+    static CONTROL: Control = Control::new();
+    for (text, expected) in [
+	("play 26", CommandError::Malformed),
+	(r#"{"track":26}"#, CommandError::Malformed),
+	(r#"{"cmd":"play","track":"26"}"#, CommandError::Malformed),
+	(r#"{"cmd":"dance"}"#, CommandError::UnknownCommand),
+	(r#"{"cmd":"play"}"#, CommandError::MissingField("track")),
+	(r#"{"cmd":"volume"}"#, CommandError::MissingField("value")),
+	(r#"{"cmd":"move","from":1}"#, CommandError::MissingField("to")),
+	// as the REST interface refuses them
+	(r#"{"cmd":"play","track":99}"#, CommandError::Rejected(ApiError::UnknownTrack(99))),
+	(r#"{"cmd":"volume","value":31}"#, CommandError::Rejected(ApiError::BadRequest("volume out of range"))),
+	(r#"{"cmd":"max-volume","value":200}"#, CommandError::Rejected(ApiError::BadRequest("volume out of range"))),
+	(r#"{"cmd":"pause"}"#, CommandError::Rejected(ApiError::InvalidState("nothing playing"))),
+	(r#"{"cmd":"remove","position":0}"#, CommandError::Rejected(ApiError::InvalidState("no track at that position"))),
+    ] {
+	assert_eq!(run_command(&CONTROL, text), Err(expected), "{text}");
+    }
+    assert!(CONTROL.commands.try_receive().is_err());
+}